@@ -0,0 +1,42 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+// The previous, twice as large level, or the scene for the bright pass
+uniform sampler2D source;
+uniform vec2 texel_size;
+
+// The first downsample only keeps what's brighter than the threshold, with a quadratic knee of
+// threshold * knee on either side of it
+uniform bool bright_pass;
+uniform float threshold;
+uniform float knee;
+
+vec3 bright_parts(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float knee_width = threshold * knee + 1e-5;
+    float soft = clamp(brightness - threshold + knee_width, 0.0, 2.0 * knee_width);
+    soft = soft * soft / (4.0 * knee_width);
+    return color * max(soft, brightness - threshold) / max(brightness, 1e-5);
+}
+
+// Dual Kawase downsample: the center and the four diagonal texel corners around it
+void main()
+{
+    vec2 half_texel = 0.5 * texel_size;
+
+    vec3 color = texture(source, Uv).rgb * 4.0;
+    color += texture(source, Uv - half_texel).rgb;
+    color += texture(source, Uv + half_texel).rgb;
+    color += texture(source, Uv + vec2(half_texel.x, -half_texel.y)).rgb;
+    color += texture(source, Uv - vec2(half_texel.x, -half_texel.y)).rgb;
+    color /= 8.0;
+
+    if (bright_pass) {
+        color = bright_parts(max(color, 0.0));
+    }
+
+    Color = vec4(color, 1.0);
+}
@@ -0,0 +1,26 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+// The next, half as large level, which is added onto the current one by blending
+uniform sampler2D source;
+uniform vec2 texel_size;
+
+// Dual Kawase upsample: a tent of eight samples around the center
+void main()
+{
+    vec2 half_texel = 0.5 * texel_size;
+
+    vec3 color = texture(source, Uv + vec2(-2.0 * half_texel.x, 0.0)).rgb;
+    color += texture(source, Uv + vec2(2.0 * half_texel.x, 0.0)).rgb;
+    color += texture(source, Uv + vec2(0.0, -2.0 * half_texel.y)).rgb;
+    color += texture(source, Uv + vec2(0.0, 2.0 * half_texel.y)).rgb;
+    color += texture(source, Uv + vec2(-half_texel.x, half_texel.y)).rgb * 2.0;
+    color += texture(source, Uv + vec2(half_texel.x, half_texel.y)).rgb * 2.0;
+    color += texture(source, Uv + vec2(half_texel.x, -half_texel.y)).rgb * 2.0;
+    color += texture(source, Uv + vec2(-half_texel.x, -half_texel.y)).rgb * 2.0;
+
+    Color = vec4(color / 12.0, 1.0);
+}
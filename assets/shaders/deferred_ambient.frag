@@ -0,0 +1,77 @@
+#version 330 core
+
+out vec4 Color;
+
+// Camera, shared by all programs
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
+
+uniform sampler2D gbuffer_albedo;
+uniform sampler2D gbuffer_normal;
+uniform sampler2D gbuffer_position;
+uniform sampler2D gbuffer_material;
+uniform sampler2D gbuffer_emissive;
+
+#define SHADING_CLASSIC 0
+#define SHADING_PBR 1
+uniform int shading_model;
+
+uniform sampler2D environment_map;
+uniform float environment_intensity;
+uniform sampler2D ambient_occlusion;
+
+#define PI 3.1415926535897932384626433832795
+
+// The ambient parts of shade_pbr in triangle.frag
+vec3 environment_brdf(vec3 f0, float roughness, float n_dot_v) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    vec2 scale_bias = vec2(-1.04, 1.04) * a004 + r.zw;
+    return f0 * scale_bias.x + scale_bias.y;
+}
+
+vec3 environment(vec3 direction, float blur) {
+    vec3 d = normalize(direction);
+    vec2 uv = vec2(atan(d.y, d.x) / (2.0 * PI) + 0.5, acos(clamp(-d.z, -1.0, 1.0)) / PI);
+    float max_level = float(textureQueryLevels(environment_map) - 1);
+    return environment_intensity * textureLod(environment_map, uv, blur * max_level).rgb;
+}
+
+// Ambient light and emissive surfaces, which the light volumes then add to
+void main()
+{
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    vec4 position = texelFetch(gbuffer_position, pixel, 0);
+    if (position.a == 0.0) {
+        discard;
+    }
+
+    vec4 albedo = texelFetch(gbuffer_albedo, pixel, 0);
+    vec4 material = texelFetch(gbuffer_material, pixel, 0);
+    float occlusion = texelFetch(ambient_occlusion, pixel, 0).r;
+
+    vec3 ambient;
+    if (shading_model == SHADING_PBR) {
+        vec3 n = normalize(texelFetch(gbuffer_normal, pixel, 0).xyz);
+        vec3 v = normalize(view_location - position.xyz);
+        float n_dot_v = max(dot(n, v), 1e-4);
+        float metallic = clamp(material.x, 0.0, 1.0);
+        float roughness = clamp(material.y, 0.04, 1.0);
+        vec3 f0 = mix(vec3(0.04), albedo.rgb, metallic);
+
+        vec3 ambient_fresnel = environment_brdf(f0, roughness, n_dot_v);
+        vec3 ambient_diffuse = (1.0 - ambient_fresnel) * (1.0 - metallic) * albedo.rgb * environment(n, 0.8);
+        vec3 ambient_specular = ambient_fresnel * environment(reflect(-v, n), roughness);
+        ambient = ambient_diffuse + ambient_specular;
+    } else {
+        ambient = albedo.rgb * albedo.a;
+    }
+
+    Color = vec4(ambient * occlusion + texelFetch(gbuffer_emissive, pixel, 0).rgb, 1.0);
+}
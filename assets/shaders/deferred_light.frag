@@ -0,0 +1,146 @@
+#version 430 core
+
+out vec4 Color;
+
+flat in int LightIndex;
+
+// Camera, shared by all programs
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
+
+// Spot lights, shared by all programs
+struct Light {
+    vec3 location;
+    float radius;
+    vec3 color;
+    // Layer in shadow_maps, -1 for lights without shadows
+    int shadow_layer;
+};
+layout (std430) readonly buffer Lights {
+    Light lights[];
+};
+
+uniform sampler2D gbuffer_albedo;
+uniform sampler2D gbuffer_normal;
+uniform sampler2D gbuffer_position;
+uniform sampler2D gbuffer_material;
+uniform sampler2D gbuffer_emissive;
+
+#define SHADING_CLASSIC 0
+#define SHADING_PBR 1
+uniform int shading_model;
+
+// Shadows, as in triangle.frag
+uniform samplerCubeArrayShadow shadow_maps;
+uniform float shadow_bias;
+uniform float shadow_pcf_radius;
+
+#define PCF_SAMPLES 20
+const vec3 pcf_offsets[PCF_SAMPLES] = vec3[](
+    vec3(1, 1, 1), vec3(1, -1, 1), vec3(-1, -1, 1), vec3(-1, 1, 1),
+    vec3(1, 1, -1), vec3(1, -1, -1), vec3(-1, -1, -1), vec3(-1, 1, -1),
+    vec3(1, 1, 0), vec3(1, -1, 0), vec3(-1, -1, 0), vec3(-1, 1, 0),
+    vec3(1, 0, 1), vec3(-1, 0, 1), vec3(1, 0, -1), vec3(-1, 0, -1),
+    vec3(0, 1, 1), vec3(0, -1, 1), vec3(0, -1, -1), vec3(0, 1, -1)
+);
+
+#define PI 3.1415926535897932384626433832795
+
+float shadow_visibility(Light light, vec3 vertex_world_location) {
+    if (light.shadow_layer < 0) {
+        return 1.0;
+    }
+
+    vec3 light_to_vertex = vertex_world_location - light.location;
+    float light_distance = length(light_to_vertex);
+    float reference = (light_distance - shadow_bias) / light.radius;
+    float spread = shadow_pcf_radius * light_distance;
+
+    float visibility = 0.0;
+    for (int i = 0; i < PCF_SAMPLES; i++) {
+        vec4 direction = vec4(light_to_vertex + pcf_offsets[i] * spread, float(light.shadow_layer));
+        visibility += texture(shadow_maps, direction, reference);
+    }
+
+    return visibility / float(PCF_SAMPLES);
+}
+
+// A single light of shade_classic in triangle.frag
+vec3 light_classic(Light light, vec3 position, vec3 normal, vec3 albedo, vec4 material) {
+    float light_distance = distance(light.location, position);
+    float attenuation = 1.0 - (min(light_distance, light.radius) / light.radius);
+    vec3 light_color = light.color * shadow_visibility(light, position) * attenuation;
+
+    vec3 light_direction = light.location - position;
+    float diffuse = max(dot(normal, normalize(light_direction)), 0.0);
+
+    vec3 halfway = light_direction + (view_location - position);
+    float angle = acos(dot(normal, normalize(halfway)));
+    float exponent = angle / material.w;
+    float term = exp(-(exponent * exponent));
+    float specular = material.z * pow(term, 64);
+
+    return albedo * (diffuse + specular) * light_color;
+}
+
+// A single light of shade_pbr in triangle.frag
+vec3 light_pbr(Light light, vec3 position, vec3 n, vec3 albedo, vec4 material) {
+    float metallic = clamp(material.x, 0.0, 1.0);
+    float roughness = clamp(material.y, 0.04, 1.0);
+
+    vec3 v = normalize(view_location - position);
+    float n_dot_v = max(dot(n, v), 1e-4);
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+
+    vec3 to_light = light.location - position;
+    float light_distance = length(to_light);
+    vec3 l = to_light / light_distance;
+    vec3 h = normalize(v + l);
+    float n_dot_l = max(dot(n, l), 0.0);
+
+    float window = clamp(1.0 - pow(light_distance / light.radius, 4.0), 0.0, 1.0);
+    float attenuation = window * window / max(light_distance * light_distance, 0.01);
+    vec3 radiance = light.color * attenuation * shadow_visibility(light, position);
+
+    vec3 fresnel = f0 + (1.0 - f0) * pow(clamp(1.0 - max(dot(h, v), 0.0), 0.0, 1.0), 5.0);
+
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+    float n_dot_h = max(dot(n, h), 0.0);
+    float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    float distribution = alpha2 / (PI * denominator * denominator);
+
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+
+    vec3 specular = fresnel * distribution * geometry / (4.0 * n_dot_v * max(n_dot_l, 1e-4));
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+// Adds the light of this volume to the pixels it covers
+void main()
+{
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    vec4 position = texelFetch(gbuffer_position, pixel, 0);
+
+    Light light = lights[LightIndex];
+    if (position.a == 0.0 || distance(position.xyz, light.location) >= light.radius) {
+        discard;
+    }
+
+    vec3 albedo = texelFetch(gbuffer_albedo, pixel, 0).rgb;
+    vec3 normal = normalize(texelFetch(gbuffer_normal, pixel, 0).xyz);
+    vec4 material = texelFetch(gbuffer_material, pixel, 0);
+
+    if (shading_model == SHADING_PBR) {
+        Color = vec4(light_pbr(light, position.xyz, normal, albedo, material), 1.0);
+    } else {
+        Color = vec4(light_classic(light, position.xyz, normal, albedo, material), 1.0);
+    }
+}
@@ -0,0 +1,34 @@
+#version 430 core
+
+layout (location = 0) in vec3 Position;
+
+// Camera, shared by all programs
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
+
+// Spot lights, shared by all programs
+struct Light {
+    vec3 location;
+    float radius;
+    vec3 color;
+    int shadow_layer;
+};
+layout (std430) readonly buffer Lights {
+    Light lights[];
+};
+
+flat out int LightIndex;
+
+// Every instance is the unit cube scaled to enclose the sphere of one light's radius
+void main()
+{
+    Light light = lights[gl_InstanceID];
+    vec3 world_location = light.location + Position * 2.0 * light.radius;
+
+    gl_Position = projection * view_rotation * view_translation * vec4(world_location, 1.0);
+    LightIndex = gl_InstanceID;
+}
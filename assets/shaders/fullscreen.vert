@@ -0,0 +1,12 @@
+#version 330 core
+
+out vec2 Uv;
+
+// A single triangle covering the whole screen, with no vertex data at all
+void main()
+{
+    vec2 position = vec2(float((gl_VertexID & 1) << 2), float((gl_VertexID & 2) << 1)) - 1.0;
+
+    Uv = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,36 @@
+#version 330 core
+
+// The G-buffer layers, see GBUFFER_LAYERS in deferred.rs
+layout (location = 0) out vec4 Albedo;
+layout (location = 1) out vec4 Normal;
+layout (location = 2) out vec4 Position;
+layout (location = 3) out vec4 Material;
+layout (location = 4) out vec4 Emissive;
+
+in VS_OUTPUT {
+    vec4 Color;
+    vec3 Normal;
+    vec3 WorldCoords;
+    vec2 Uv;
+} IN;
+
+// Material of the drawn batch, as in triangle.frag
+uniform vec4 material_base_color;
+uniform float material_ambient;
+uniform float material_specular_strength;
+uniform float material_specular_roughness;
+uniform float material_metallic;
+uniform float material_roughness;
+uniform vec3 material_emissive;
+uniform sampler2D diffuse_map;
+
+void main()
+{
+    vec4 surface_color = material_base_color * IN.Color * texture(diffuse_map, IN.Uv);
+
+    Albedo = vec4(surface_color.rgb, material_ambient);
+    Normal = vec4(normalize(IN.Normal), 0.0);
+    Position = vec4(IN.WorldCoords, 1.0);
+    Material = vec4(material_metallic, material_roughness, material_specular_strength, material_specular_roughness);
+    Emissive = vec4(material_emissive, 1.0);
+}
@@ -0,0 +1,22 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+uniform sampler2D source;
+
+// How far red and blue are pulled apart at the corners, in UV units
+uniform float strength = 0.004;
+
+// Like a cheap lens, red is magnified a little more than green, and blue a little less
+void main()
+{
+    vec2 offset = (Uv - 0.5) * 2.0 * strength;
+
+    Color = vec4(
+        texture(source, Uv - offset).r,
+        texture(source, Uv).g,
+        texture(source, Uv + offset).b,
+        1.0);
+}
@@ -0,0 +1,32 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+uniform sampler2D source;
+
+// A 3D lookup table laid out as lut_size slices of lut_size x lut_size texels side by side, red
+// along each slice, green down it and blue across slices. Each texel holds the graded color.
+uniform sampler2D lut;
+uniform float lut_size = 16.0;
+// Blends between the original (0) and the graded (1) colors
+uniform float intensity = 1.0;
+
+vec3 lookup(vec3 color) {
+    float blue = color.b * (lut_size - 1.0);
+    float slice = floor(blue);
+
+    // Sample texel centers, so the slices don't bleed into each other
+    vec2 in_slice = (color.rg * (lut_size - 1.0) + 0.5) / vec2(lut_size * lut_size, lut_size);
+    vec2 low = in_slice + vec2(slice / lut_size, 0.0);
+    vec2 high = in_slice + vec2(min(slice + 1.0, lut_size - 1.0) / lut_size, 0.0);
+
+    return mix(texture(lut, low).rgb, texture(lut, high).rgb, blue - slice);
+}
+
+void main()
+{
+    vec3 color = clamp(texture(source, Uv).rgb, 0.0, 1.0);
+    Color = vec4(mix(color, lookup(color), intensity), 1.0);
+}
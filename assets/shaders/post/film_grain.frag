@@ -0,0 +1,28 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+uniform sampler2D source;
+uniform float frame;
+
+// Amplitude of the noise
+uniform float strength = 0.05;
+
+float hash(vec2 p) {
+    vec3 p3 = fract(vec3(p.xyx) * 0.1031);
+    p3 += dot(p3, p3.yzx + 33.33);
+    return fract((p3.x + p3.y) * p3.z);
+}
+
+// Noise which changes every frame, strongest in the midtones like grain on film
+void main()
+{
+    vec3 color = texture(source, Uv).rgb;
+
+    float noise = hash(gl_FragCoord.xy + mod(frame, 1024.0) * vec2(17.0, 59.0)) - 0.5;
+    float midtones = 1.0 - abs(dot(color, vec3(0.299, 0.587, 0.114)) * 2.0 - 1.0);
+
+    Color = vec4(color + noise * strength * midtones, 1.0);
+}
@@ -0,0 +1,46 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+uniform sampler2D source;
+uniform vec2 texel_size;
+
+// How far along an edge to blur, in pixels
+uniform float span_max = 8.0;
+// Keep flat areas and dark edges from being blurred
+uniform float reduce_mul = 1.0 / 8.0;
+uniform float reduce_min = 1.0 / 128.0;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+// Lottes' FXAA: blurs along the direction of edges found from the luma of the neighbouring
+// corners, unless the blur would reach outside the local luma range
+void main()
+{
+    vec3 rgb_m = texture(source, Uv).rgb;
+    float luma_nw = luma(texture(source, Uv + vec2(-1.0, -1.0) * texel_size).rgb);
+    float luma_ne = luma(texture(source, Uv + vec2(1.0, -1.0) * texel_size).rgb);
+    float luma_sw = luma(texture(source, Uv + vec2(-1.0, 1.0) * texel_size).rgb);
+    float luma_se = luma(texture(source, Uv + vec2(1.0, 1.0) * texel_size).rgb);
+    float luma_m = luma(rgb_m);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 direction = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+    float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2(-span_max), vec2(span_max)) * texel_size;
+
+    vec3 rgb_a = 0.5 * (texture(source, Uv + direction * (1.0 / 3.0 - 0.5)).rgb +
+                        texture(source, Uv + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (texture(source, Uv - direction * 0.5).rgb +
+                                       texture(source, Uv + direction * 0.5).rgb);
+
+    float luma_b = luma(rgb_b);
+    Color = vec4((luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b, 1.0);
+}
@@ -0,0 +1,25 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+uniform sampler2D source;
+
+// How dark the corners get, from 0 to 1
+uniform float strength = 0.4;
+// Distance from the center where darkening starts, and how far it takes to fade in, with the
+// screen's half height being 1
+uniform float radius = 0.8;
+uniform float softness = 0.6;
+uniform vec2 texel_size;
+
+void main()
+{
+    // Round regardless of the aspect ratio
+    vec2 from_center = (Uv - 0.5) * 2.0 * vec2(texel_size.y / texel_size.x, 1.0);
+    float falloff = smoothstep(radius, radius + softness, length(from_center));
+
+    vec3 color = texture(source, Uv).rgb;
+    Color = vec4(color * (1.0 - strength * falloff), 1.0);
+}
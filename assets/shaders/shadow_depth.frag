@@ -0,0 +1,26 @@
+#version 330 core
+
+in VS_OUTPUT {
+    vec4 Color;
+    vec3 Normal;
+    vec3 WorldCoords;
+    vec2 Uv;
+} IN;
+
+// Camera, looking from the light through one of the faces of its shadow cube map
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
+
+void main()
+{
+    // The far plane of the perspective projection, which is the light's radius
+    float far_plane = projection[3][2] / (projection[2][2] + 1.0);
+
+    // Store the linear distance to the light rather than the projected depth, so that it can be
+    // compared without knowing which face a direction falls on
+    gl_FragDepth = distance(IN.WorldCoords, view_location) / far_plane;
+}
@@ -0,0 +1,62 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+// Of the prepass, normals are in view space
+uniform sampler2D depth;
+uniform sampler2D normals;
+// Tiled across the screen, rotates the kernel around the normal differently in every pixel
+uniform sampler2D noise;
+uniform vec2 noise_scale;
+
+#define MAX_SAMPLES 64
+uniform vec3 kernel[MAX_SAMPLES];
+uniform int samples;
+uniform float radius;
+uniform float bias;
+uniform float power;
+
+uniform mat4 projection;
+uniform mat4 inverse_projection;
+
+vec3 view_position(vec2 uv) {
+    vec4 clip = vec4(uv * 2.0 - 1.0, texture(depth, uv).r * 2.0 - 1.0, 1.0);
+    vec4 view = inverse_projection * clip;
+    return view.xyz / view.w;
+}
+
+void main()
+{
+    // Nothing was drawn here, so nothing occludes it
+    if (texture(depth, Uv).r >= 1.0) {
+        Color = vec4(1.0);
+        return;
+    }
+
+    vec3 position = view_position(Uv);
+    vec3 normal = normalize(texture(normals, Uv).xyz);
+    vec3 random = vec3(texture(noise, Uv * noise_scale).xy * 2.0 - 1.0, 0.0);
+
+    // Orients the kernel's hemisphere around the normal, rotated by the noise
+    vec3 tangent = normalize(random - normal * dot(random, normal));
+    vec3 bitangent = cross(normal, tangent);
+    mat3 tbn = mat3(tangent, bitangent, normal);
+
+    float occlusion = 0.0;
+    for (int i = 0; i < samples; i++) {
+        vec3 sample_position = position + tbn * kernel[i] * radius;
+
+        vec4 offset = projection * vec4(sample_position, 1.0);
+        vec2 sample_uv = offset.xy / offset.w * 0.5 + 0.5;
+        float surface_depth = view_position(sample_uv).z;
+
+        // Surfaces far in front of the fragment are something else entirely, which mustn't
+        // darken its outline
+        float in_range = smoothstep(0.0, 1.0, radius / abs(position.z - surface_depth));
+        occlusion += (surface_depth >= sample_position.z + bias ? 1.0 : 0.0) * in_range;
+    }
+
+    Color = vec4(vec3(pow(1.0 - occlusion / float(samples), power)), 1.0);
+}
@@ -0,0 +1,22 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+// The noisy occlusion, possibly smaller than the output
+uniform sampler2D source;
+uniform vec2 texel_size;
+
+// A 4x4 box blur, the size of the noise tile, which averages out the noise's rotations
+void main()
+{
+    float occlusion = 0.0;
+    for (int x = -2; x < 2; x++) {
+        for (int y = -2; y < 2; y++) {
+            occlusion += texture(source, Uv + (vec2(x, y) + 0.5) * texel_size).r;
+        }
+    }
+
+    Color = vec4(vec3(occlusion / 16.0), 1.0);
+}
@@ -0,0 +1,43 @@
+#version 330 core
+
+in vec2 Uv;
+
+out vec4 Color;
+
+// The resolved HDR scene, in linear colors
+uniform sampler2D scene;
+uniform float exposure;
+
+// The blurred bright parts of the scene, added on top of it. The intensity is 0 without bloom.
+uniform sampler2D bloom;
+uniform float bloom_intensity;
+
+#define TONE_MAPPING_CLAMP 0
+#define TONE_MAPPING_REINHARD 1
+#define TONE_MAPPING_ACES 2
+uniform int tone_mapping;
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 color) {
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+void main()
+{
+    vec3 hdr = texture(scene, Uv).rgb + texture(bloom, Uv).rgb * bloom_intensity;
+    vec3 color = max(hdr * exposure, 0.0);
+
+    if (tone_mapping == TONE_MAPPING_REINHARD) {
+        color = color / (1.0 + color);
+    } else if (tone_mapping == TONE_MAPPING_ACES) {
+        color = aces(color);
+    }
+
+    Color = vec4(linear_to_srgb(clamp(color, 0.0, 1.0)), 1.0);
+}
@@ -1,4 +1,4 @@
-#version 330 core
+#version 430 core
 
 out vec4 Color;
 
@@ -6,41 +6,118 @@ in VS_OUTPUT {
     vec4 Color;
     vec3 Normal;
     vec3 WorldCoords;
+    vec2 Uv;
 } IN;
 
-// Camera
-uniform vec3 view_location;
+// Camera, shared by all programs
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
 
-// Spot lights
-#define MAX_LIGHTS 330u
-uniform uint lights_count;
-uniform vec3[MAX_LIGHTS] light_locations;
-uniform vec3[MAX_LIGHTS] light_colors;
-uniform float[MAX_LIGHTS] light_radiuses;
+// Spot lights, shared by all programs. The screen is split into tiles of tile_size pixels, each of
+// which has a list of the lights reaching into it
+struct Light {
+    vec3 location;
+    float radius;
+    vec3 color;
+    // Layer in shadow_maps, -1 for lights without shadows
+    int shadow_layer;
+};
+layout (std140) uniform LightGrid {
+    uint tile_size;
+    uint tiles_x;
+    uint tiles_y;
+    uint lights_count;
+};
+layout (std430) readonly buffer Lights {
+    Light lights[];
+};
+// Offset into light_indices and light count of every tile
+layout (std430) readonly buffer LightTiles {
+    uvec2 tiles[];
+};
+layout (std430) readonly buffer LightIndices {
+    uint light_indices[];
+};
 
-// Ambient lighting
-float ambient_strength = 0.35;
-vec3 ambient_color = ambient_strength * vec3(1.0, 1.0, 1.0);
+// Shadows, the distance to the closest surface in every direction from the light, divided by the
+// light's radius
+uniform samplerCubeArrayShadow shadow_maps;
+uniform float shadow_bias;
+uniform float shadow_pcf_radius;
 
-// Specular lighting
-float specular_strength = 100;
-float specular_roughness = 0.9;
+// Directions to take PCF samples in, on top of the direction to the light
+#define PCF_SAMPLES 20
+const vec3 pcf_offsets[PCF_SAMPLES] = vec3[](
+    vec3(1, 1, 1), vec3(1, -1, 1), vec3(-1, -1, 1), vec3(-1, 1, 1),
+    vec3(1, 1, -1), vec3(1, -1, -1), vec3(-1, -1, -1), vec3(-1, 1, -1),
+    vec3(1, 1, 0), vec3(1, -1, 0), vec3(-1, -1, 0), vec3(-1, 1, 0),
+    vec3(1, 0, 1), vec3(-1, 0, 1), vec3(1, 0, -1), vec3(-1, 0, -1),
+    vec3(0, 1, 1), vec3(0, -1, 1), vec3(0, -1, -1), vec3(0, 1, -1)
+);
+
+// Material of the drawn batch. The diffuse map is multiplied with the base and vertex colors, it's
+// a white texel for untextured materials
+uniform vec4 material_base_color;
+uniform float material_ambient;
+uniform float material_specular_strength;
+uniform float material_specular_roughness;
+uniform float material_metallic;
+uniform float material_roughness;
+uniform vec3 material_emissive;
+uniform sampler2D diffuse_map;
+
+// Which of the shade_* functions lights the fragment
+#define SHADING_CLASSIC 0
+#define SHADING_PBR 1
+uniform int shading_model;
+
+// Equirectangular environment with mipmaps, lighting PBR surfaces from all directions
+uniform sampler2D environment_map;
+uniform float environment_intensity;
+
+// How much ambient light reaches every pixel of the screen, from 0 (fully occluded) to 1
+uniform sampler2D ambient_occlusion;
+
+#define PI 3.1415926535897932384626433832795
 
 float normal_dot_sat(vec3 v1, vec3 v2) {
     return max(dot(normalize(v1), normalize(v2)), 0.0);
 }
 
-void main()
-{
-    vec3 vertex_world_location = IN.WorldCoords;
-    vec3 vertex_normal = IN.Normal;
+// How much of the light reaches the fragment, from 0 (fully shadowed) to 1
+float shadow_visibility(Light light, vec3 vertex_world_location) {
+    if (light.shadow_layer < 0) {
+        return 1.0;
+    }
+
+    vec3 light_to_vertex = vertex_world_location - light.location;
+    float light_distance = length(light_to_vertex);
+    float reference = (light_distance - shadow_bias) / light.radius;
+    float spread = shadow_pcf_radius * light_distance;
 
-    vec3 final_color = ambient_color;
-    for (uint i = 0u; i < lights_count; i++) {
+    float visibility = 0.0;
+    for (int i = 0; i < PCF_SAMPLES; i++) {
+        vec4 direction = vec4(light_to_vertex + pcf_offsets[i] * spread, float(light.shadow_layer));
+        visibility += texture(shadow_maps, direction, reference);
+    }
+
+    return visibility / float(PCF_SAMPLES);
+}
+
+// Gaussian specular highlights on top of lambertian diffuse, with light falling off linearly
+// towards its radius
+vec4 shade_classic(vec3 vertex_world_location, vec3 vertex_normal, vec4 surface_color, uvec2 tile_lights, float occlusion) {
+    vec3 final_color = vec3(material_ambient * occlusion);
+    for (uint j = 0u; j < tile_lights.y; j++) {
         // Get current light
-        vec3 light_location = light_locations[i];
-        vec3 light_color = light_colors[i];
-        float light_radius = light_radiuses[i];
+        Light light = lights[light_indices[tile_lights.x + j]];
+        vec3 light_location = light.location;
+        vec3 light_color = light.color * shadow_visibility(light, vertex_world_location);
+        float light_radius = light.radius;
 
         // Light distance / attenuation
         float light_distance = distance(light_location, vertex_world_location);
@@ -56,12 +133,115 @@ void main()
         vec3 view_direction = view_location - vertex_world_location;
         vec3 halfway = light_direction + view_direction;
         float angle = acos(dot(normalize(vertex_normal), normalize(halfway)));
-        float exponent = (angle / specular_roughness);
+        float exponent = (angle / material_specular_roughness);
         float term = exp(-(exponent * exponent));
-        vec3 specular = specular_strength * pow(term, 64) * light_color * light_attenuation;
+        vec3 specular = material_specular_strength * pow(term, 64) * light_color * light_attenuation;
 
         final_color += specular;
     }
 
-    Color = IN.Color * vec4(final_color, 1.0);
-}
\ No newline at end of file
+    return surface_color * vec4(final_color, 1.0);
+}
+
+// Trowbridge-Reitz GGX normal distribution, with alpha = roughness^2
+float distribution_ggx(float n_dot_h, float roughness) {
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+    float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * denominator * denominator);
+}
+
+// Smith's method with Schlick-GGX for both the view and the light direction
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float view = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float light = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return view * light;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Karis' analytic fit of the split-sum environment BRDF, in place of a lookup table
+vec3 environment_brdf(vec3 f0, float roughness, float n_dot_v) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    vec2 scale_bias = vec2(-1.04, 1.04) * a004 + r.zw;
+    return f0 * scale_bias.x + scale_bias.y;
+}
+
+// The environment in `direction`, blurred more the higher `blur` is, from 0 to 1. Z is up.
+vec3 environment(vec3 direction, float blur) {
+    vec3 d = normalize(direction);
+    vec2 uv = vec2(atan(d.y, d.x) / (2.0 * PI) + 0.5, acos(clamp(-d.z, -1.0, 1.0)) / PI);
+    float max_level = float(textureQueryLevels(environment_map) - 1);
+    return environment_intensity * textureLod(environment_map, uv, blur * max_level).rgb;
+}
+
+// Metallic-roughness Cook-Torrance, with light falling off with the inverse square of the distance,
+// windowed to reach zero at the light's radius, and ambient light from the environment
+vec4 shade_pbr(vec3 vertex_world_location, vec3 vertex_normal, vec4 surface_color, uvec2 tile_lights, float occlusion) {
+    vec3 albedo = surface_color.rgb;
+    float roughness = clamp(material_roughness, 0.04, 1.0);
+    float metallic = clamp(material_metallic, 0.0, 1.0);
+
+    vec3 n = normalize(vertex_normal);
+    vec3 v = normalize(view_location - vertex_world_location);
+    float n_dot_v = max(dot(n, v), 1e-4);
+
+    // Dielectrics reflect about 4% head-on, metals reflect their albedo
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+
+    vec3 radiance_out = vec3(0.0);
+    for (uint j = 0u; j < tile_lights.y; j++) {
+        Light light = lights[light_indices[tile_lights.x + j]];
+
+        vec3 to_light = light.location - vertex_world_location;
+        float light_distance = length(to_light);
+        vec3 l = to_light / light_distance;
+        vec3 h = normalize(v + l);
+        float n_dot_l = max(dot(n, l), 0.0);
+
+        float window = clamp(1.0 - pow(light_distance / light.radius, 4.0), 0.0, 1.0);
+        float attenuation = window * window / max(light_distance * light_distance, 0.01);
+        vec3 radiance = light.color * attenuation * shadow_visibility(light, vertex_world_location);
+
+        vec3 fresnel = fresnel_schlick(max(dot(h, v), 0.0), f0);
+        float distribution = distribution_ggx(max(dot(n, h), 0.0), roughness);
+        float geometry = geometry_smith(n_dot_v, n_dot_l, roughness);
+        vec3 specular = fresnel * distribution * geometry / (4.0 * n_dot_v * max(n_dot_l, 1e-4));
+
+        // Whatever isn't reflected is refracted and diffused, metals absorb it all
+        vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+
+        radiance_out += (diffuse + specular) * radiance * n_dot_l;
+    }
+
+    // Irradiance from the heavily blurred environment, reflections blurred by roughness
+    vec3 ambient_fresnel = environment_brdf(f0, roughness, n_dot_v);
+    vec3 ambient_diffuse = (1.0 - ambient_fresnel) * (1.0 - metallic) * albedo * environment(n, 0.8);
+    vec3 ambient_specular = ambient_fresnel * environment(reflect(-v, n), roughness);
+    radiance_out += (ambient_diffuse + ambient_specular) * occlusion;
+
+    return vec4(radiance_out, surface_color.a);
+}
+
+void main()
+{
+    uvec2 tile = min(uvec2(gl_FragCoord.xy) / tile_size, uvec2(tiles_x, tiles_y) - 1u);
+    uvec2 tile_lights = tiles[tile.y * tiles_x + tile.x];
+
+    vec4 surface_color = material_base_color * IN.Color * texture(diffuse_map, IN.Uv);
+    float occlusion = texelFetch(ambient_occlusion, ivec2(gl_FragCoord.xy), 0).r;
+
+    if (shading_model == SHADING_PBR) {
+        Color = shade_pbr(IN.WorldCoords, IN.Normal, surface_color, tile_lights, occlusion);
+    } else {
+        Color = shade_classic(IN.WorldCoords, IN.Normal, surface_color, tile_lights, occlusion);
+    }
+
+    Color += vec4(material_emissive, 0.0);
+}
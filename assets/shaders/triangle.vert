@@ -3,11 +3,13 @@
 layout (location = 0) in vec3 Position;
 layout (location = 1) in vec4 Color;
 layout (location = 2) in vec3 Normal;
+layout (location = 3) in vec2 Uv;
 
 out VS_OUTPUT {
     vec4 Color;
     vec3 Normal;
     vec3 WorldCoords;
+    vec2 Uv;
 } OUT;
 
 #define M_PI 3.1415926535897932384626433832795
@@ -18,20 +20,25 @@ uniform float model_scale;
 uniform mat4 model_rotation;
 uniform mat4 model_translation;
 
-// View matrices
-uniform mat4 view_rotation;
-uniform mat4 view_translation;
-uniform vec3 view_location;
+// Tints the vertex colors, like the per-instance color of triangle_instanced.vert
+uniform vec4 model_color;
 
-// Projection matrix
-uniform mat4 projection;
+// Camera, shared by all programs
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
 
 void main()
 {
     vec3 vertex_world_location = (model_translation * model_rotation * vec4(model_scale * Position.xyz, 1.0)).xyz;
     gl_Position = projection * view_rotation * view_translation * vec4(vertex_world_location, 1.0);
 
-    OUT.Color = Color;
-    OUT.Normal = (model_rotation * vec4(Normal, 1.0)).xyz;
+    OUT.Color = Color * model_color;
+    // The inverse transpose keeps normals perpendicular to the surface, like Model::normal_matrix
+    OUT.Normal = transpose(inverse(mat3(model_rotation) * model_scale)) * Normal;
     OUT.WorldCoords = vertex_world_location;
+    OUT.Uv = Uv;
 }
\ No newline at end of file
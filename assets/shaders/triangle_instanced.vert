@@ -0,0 +1,37 @@
+#version 330 core
+
+layout (location = 0) in vec3 Position;
+layout (location = 1) in vec4 Color;
+layout (location = 2) in vec3 Normal;
+layout (location = 3) in vec2 Uv;
+
+// Per-instance attributes, the model matrix takes up locations 4 to 7
+layout (location = 4) in mat4 InstanceModel;
+layout (location = 8) in vec4 InstanceColor;
+
+out VS_OUTPUT {
+    vec4 Color;
+    vec3 Normal;
+    vec3 WorldCoords;
+    vec2 Uv;
+} OUT;
+
+// Camera, shared by all programs
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
+
+void main()
+{
+    vec3 vertex_world_location = (InstanceModel * vec4(Position.xyz, 1.0)).xyz;
+    gl_Position = projection * view_rotation * view_translation * vec4(vertex_world_location, 1.0);
+
+    OUT.Color = Color * InstanceColor;
+    // The inverse transpose keeps normals perpendicular to the surface, like Model::normal_matrix
+    OUT.Normal = transpose(inverse(mat3(InstanceModel))) * Normal;
+    OUT.WorldCoords = vertex_world_location;
+    OUT.Uv = Uv;
+}
\ No newline at end of file
@@ -6,7 +6,10 @@ in VS_OUTPUT {
     vec4 Color;
 } IN;
 
+// Lights are drawn brighter than any lit surface, so that they bloom
+uniform float emissive_strength;
+
 void main()
 {
-    Color = IN.Color;
+    Color = vec4(IN.Color.rgb * emissive_strength, IN.Color.a);
 }
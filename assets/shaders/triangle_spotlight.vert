@@ -16,12 +16,13 @@ uniform float model_scale;
 uniform mat4 model_rotation;
 uniform mat4 model_translation;
 
-// View matrices
-uniform mat4 view_rotation;
-uniform mat4 view_translation;
-
-// Projection matrix
-uniform mat4 projection;
+// Camera, shared by all programs
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
 
 // Spotlight color
 uniform vec4 solid_color;
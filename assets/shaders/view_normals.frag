@@ -0,0 +1,24 @@
+#version 330 core
+
+out vec4 Color;
+
+in VS_OUTPUT {
+    vec4 Color;
+    vec3 Normal;
+    vec3 WorldCoords;
+    vec2 Uv;
+} IN;
+
+// Camera, shared by all programs
+layout (std140) uniform Camera {
+    mat4 view_rotation;
+    mat4 view_translation;
+    mat4 projection;
+    vec3 view_location;
+};
+
+void main()
+{
+    // Translation doesn't affect directions, so the rotation alone takes normals to view space
+    Color = vec4(normalize(mat3(view_rotation) * IN.Normal), 1.0);
+}
@@ -17,6 +17,7 @@ fn main() {
         Fallbacks::All,
         [
             "GL_NV_command_list", // additional extension we want to use
+            "GL_ARB_texture_filter_anisotropic",
         ],
     );
 
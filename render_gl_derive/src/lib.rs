@@ -19,10 +19,20 @@ pub fn vertex_attrib_pointers_derive(input: proc_macro::TokenStream) -> proc_mac
 
     let expanded = quote! {
         impl #ident #generics #where_clause {
+            #[allow(dead_code)]
             pub fn vertex_attrib_pointers(gl: &gl::Gl) {
+                Self::attrib_pointers(gl, 0, 0);
+            }
+
+            #[allow(dead_code)]
+            pub fn instance_attrib_pointers(gl: &gl::Gl, first_location: usize) {
+                Self::attrib_pointers(gl, first_location, 1);
+            }
+
+            fn attrib_pointers(gl: &gl::Gl, first_location: usize, divisor: gl::types::GLuint) {
                 let stride = std::mem::size_of::<Self>();
                 let mut offset = 0;
-                let mut location = 0;
+                let mut location = first_location;
 
                 #fields_vertex_attrib_pointer
             }
@@ -56,6 +66,7 @@ fn generate_struct_field_vertex_attrib_pointer_call(
     quote! {
         unsafe {
             #field_ty::vertex_attrib_pointer(gl, stride, location, offset);
+            gl.VertexAttribDivisor(location as gl::types::GLuint, divisor);
         }
 
         offset += std::mem::size_of::<#field_ty>();
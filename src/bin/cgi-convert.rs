@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::Path;
+
+use failure::{err_msg, format_err};
+
+use cgi::models::cgi_format::{CgiMesh, IndexType};
+use cgi::models::obj::ObjModel;
+use cgi::models::{ply, stl};
+
+const USAGE: &str = "Usage: cgi-convert [OPTIONS] <INPUT> [OUTPUT]
+
+Converts an OBJ, STL, PLY or .cgi mesh into the .cgi mesh format. When OUTPUT is omitted, nothing
+is written, which is useful together with --stats.
+
+Options:
+    --recompute-normals  Replace the normals with ones calculated from the triangles
+    --index              Deduplicate verticies into an index buffer
+    --normalize          Recenter around the origin and rescale to unit size
+    --stats              Print vertex/triangle counts and bounds
+    --legacy             Read a .cgi INPUT as a legacy headerless file
+    -h, --help           Print this message";
+
+#[derive(Default)]
+struct Options {
+    input: String,
+    output: Option<String>,
+    recompute_normals: bool,
+    index: bool,
+    normalize: bool,
+    stats: bool,
+    legacy: bool,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", cgi::debug::failure_to_string(e));
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), failure::Error> {
+    let options = match parse_args(std::env::args().skip(1))? {
+        Some(options) => options,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let mut mesh = load(&options)?;
+
+    if options.recompute_normals {
+        mesh.recompute_normals();
+    }
+
+    if options.normalize {
+        mesh.normalize_size();
+    }
+
+    if options.index {
+        mesh.deduplicate();
+    }
+
+    if options.stats {
+        print_stats(&mesh);
+    }
+
+    if let Some(output) = &options.output {
+        fs::write(output, mesh.write()?)?;
+    }
+
+    Ok(())
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Options>, failure::Error> {
+    let mut options = Options::default();
+    let mut positional = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--recompute-normals" => options.recompute_normals = true,
+            "--index" => options.index = true,
+            "--normalize" => options.normalize = true,
+            "--stats" => options.stats = true,
+            "--legacy" => options.legacy = true,
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with('-') => return Err(format_err!("Unknown option {}\n\n{}", arg, USAGE)),
+            _ => positional.push(arg),
+        }
+    }
+
+    match positional.len() {
+        1 | 2 => {
+            let mut positional = positional.into_iter();
+            options.input = positional.next().unwrap_or_default();
+            options.output = positional.next();
+            Ok(Some(options))
+        }
+        _ => Err(err_msg(USAGE)),
+    }
+}
+
+fn load(options: &Options) -> Result<CgiMesh, failure::Error> {
+    let bytes = fs::read(&options.input)?;
+
+    let extension = Path::new(&options.input)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+        .unwrap_or_default();
+
+    Ok(match extension.as_str() {
+        "obj" => ObjModel::parse(&String::from_utf8(bytes)?)?.to_cgi_mesh(),
+        "stl" => stl::parse(&bytes)?,
+        "ply" => ply::parse(&bytes)?,
+        "cgi" if options.legacy => CgiMesh::read_legacy(&bytes)?,
+        "cgi" => CgiMesh::read(&bytes)?,
+        _ => return Err(format_err!("Unsupported input format \"{}\"", extension)),
+    })
+}
+
+fn print_stats(mesh: &CgiMesh) {
+    let bounds = mesh.bounds();
+    let index_type = match mesh.index_type() {
+        Some(IndexType::U16) => "u16",
+        Some(IndexType::U32) => "u32",
+        None => "none",
+    };
+
+    println!("Verticies:  {}", mesh.vertex_count());
+    println!("Triangles:  {}", mesh.triangle_count());
+    println!("Indices:    {}", index_type);
+    println!(
+        "Attributes: {}",
+        mesh.layout
+            .iter()
+            .map(|layout| format!("{:?} ({:?})", layout.attribute, layout.element_type))
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+    println!("Bounds min: {:?}", bounds.min.as_slice());
+    println!("Bounds max: {:?}", bounds.max.as_slice());
+    println!("Size:       {:?}", bounds.size().as_slice());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, parse_args, Options};
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>().into_iter()
+    }
+
+    #[test]
+    fn options_and_paths_are_parsed() {
+        let options = parse_args(args(&["--index", "in.ply", "--stats", "out.cgi"])).unwrap().unwrap();
+
+        assert_eq!(options.input, "in.ply");
+        assert_eq!(options.output.as_deref(), Some("out.cgi"));
+        assert!(options.index && options.stats);
+        assert!(!options.recompute_normals && !options.normalize && !options.legacy);
+
+        assert!(parse_args(args(&["--help"])).unwrap().is_none());
+        assert!(parse_args(args(&["--bogus", "in.ply"])).is_err());
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["a", "b", "c"])).is_err());
+    }
+
+    #[test]
+    fn inputs_are_loaded_by_extension() {
+        let directory = std::env::temp_dir().join(format!("cgi-convert-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_string_lossy().into_owned();
+        std::fs::write(path("triangle.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        std::fs::write(
+            path("triangle.STL"),
+            "solid t facet normal 0 0 1 outer loop vertex 0 0 0 vertex 1 0 0 vertex 0 1 0 endloop endfacet endsolid t",
+        )
+        .unwrap();
+        std::fs::write(path("triangle.txt"), "").unwrap();
+
+        let load_path = |name: &str| {
+            load(&Options {
+                input: path(name),
+                ..Options::default()
+            })
+        };
+        let obj = load_path("triangle.obj").map(|mesh| mesh.vertex_count());
+        let stl = load_path("triangle.STL").map(|mesh| mesh.vertex_count());
+        let unsupported = load_path("triangle.txt");
+        let missing = load_path("missing.ply");
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(obj.unwrap(), 3);
+        assert_eq!(stl.unwrap(), 3);
+        assert!(unsupported.is_err());
+        assert!(missing.is_err());
+    }
+}
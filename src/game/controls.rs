@@ -18,6 +18,12 @@ pub enum GameKey {
     Forward,
     Backwards,
     VsyncToggle,
+    ShadingToggle,
+    AmbientOcclusionToggle,
+    RenderPathToggle,
+    /// Toggles the post-processing pass with this index
+    PostProcessToggle(usize),
+    Screenshot,
     Quit,
 }
 
@@ -50,6 +56,18 @@ pub fn init_key_map() -> HashMap<Scancode, GameKey> {
         &[Scancode::W, Scancode::Up][..] => GameKey::Forward,
         &[Scancode::S, Scancode::Down][..] => GameKey::Backwards,
         &[Scancode::V][..] => GameKey::VsyncToggle,
+        &[Scancode::P][..] => GameKey::ShadingToggle,
+        &[Scancode::O][..] => GameKey::AmbientOcclusionToggle,
+        &[Scancode::R][..] => GameKey::RenderPathToggle,
+        &[Scancode::F1][..] => GameKey::PostProcessToggle(0),
+        &[Scancode::F2][..] => GameKey::PostProcessToggle(1),
+        &[Scancode::F3][..] => GameKey::PostProcessToggle(2),
+        &[Scancode::F4][..] => GameKey::PostProcessToggle(3),
+        &[Scancode::F5][..] => GameKey::PostProcessToggle(4),
+        &[Scancode::F6][..] => GameKey::PostProcessToggle(5),
+        &[Scancode::F7][..] => GameKey::PostProcessToggle(6),
+        &[Scancode::F8][..] => GameKey::PostProcessToggle(7),
+        &[Scancode::F12][..] => GameKey::Screenshot,
         &[Scancode::LShift, Scancode::RShift][..] => GameKey::Run,
         &[Scancode::LCtrl, Scancode::RCtrl][..] => GameKey::Walk,
         &[Scancode::Q, Scancode::Escape][..] => GameKey::Quit,
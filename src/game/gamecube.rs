@@ -1,15 +1,25 @@
-use crate::models::suzanne::Suzanne;
+use crate::models::material::MaterialHandle;
+use crate::models::mesh_registry::MeshHandle;
 use crate::models::world_model::{Model, Spatial};
+use crate::primitives::light::Color;
 use nalgebra::Matrix4;
 
 pub(crate) struct GameCube {
-    pub(crate) cube: Suzanne,
+    pub(crate) mesh: MeshHandle,
+    pub(crate) material: MaterialHandle,
     pub(crate) spatial: Spatial,
+    /// Tints the material's base color
+    pub(crate) color: Color,
 }
 
 impl GameCube {
-    pub fn new(spatial: Spatial, cube: Suzanne) -> Self {
-        GameCube { cube, spatial }
+    pub fn new(spatial: Spatial, mesh: MeshHandle, material: MaterialHandle, color: Color) -> Self {
+        GameCube {
+            mesh,
+            material,
+            spatial,
+            color,
+        }
     }
 }
 
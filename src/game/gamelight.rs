@@ -1,11 +1,13 @@
 use nalgebra::Matrix4;
 
+use crate::models::mesh_registry::MeshHandle;
 use crate::models::world_model::{Model, Spatial};
 use crate::primitives::spatial::{Location, Orientation};
 use crate::primitives::spotlight::{spot_radius_to_cube_scale, Spotlight};
 
 pub struct GameLight {
     pub(crate) spotlight: Spotlight,
+    pub(crate) mesh: MeshHandle,
     pub(crate) angle: f32,
     pub(crate) center: Location,
     pub(crate) location: Location,
@@ -45,9 +47,10 @@ impl GameLight {
         self.location = Self::location_from_angle(self.center, self.angle, self.spin_radius);
     }
 
-    pub(crate) fn new(angle: f32, center: Location, spin_radius: f32, spin_speed: f32, spotlight: Spotlight) -> Self {
+    pub(crate) fn new(angle: f32, center: Location, spin_radius: f32, spin_speed: f32, spotlight: Spotlight, mesh: MeshHandle) -> Self {
         Self {
             spotlight,
+            mesh,
             angle,
             center,
             spin_radius,
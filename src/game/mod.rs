@@ -1,35 +1,32 @@
 use std::f32::consts::TAU;
 
-use image::{GenericImageView, Pixel};
-use nalgebra::{Vector3, Vector4};
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use image::RgbaImage;
+use nalgebra::Vector3;
 use sdl2::mouse::MouseWheelDirection;
 
 use controls::GameKey;
 use controls::KeyMap;
 
 use crate::game::controls::{init_key_map, GameKeyStack};
-use crate::game::gamecube::GameCube;
-use crate::game::gamelight::GameLight;
-use crate::models::suzanne::Suzanne;
-use crate::models::world_model::{Model, Spatial};
-use crate::primitives::camera::Camera;
+use crate::game::scene::Scene;
+use crate::game::screenshots::{ScreenshotSettings, Screenshots};
+use crate::primitives::deferred::RenderPath;
+use crate::primitives::hdr::HdrSettings;
 use crate::primitives::input::{KeyStack, MouseMovement};
-use crate::primitives::light::consts::WHITE;
-use crate::primitives::light::Color;
-use crate::primitives::object_draw::ObjectsDraw;
-use crate::primitives::projection::perspective;
-use crate::primitives::spatial::{Location, Orientation};
-use crate::primitives::spotlight::Spotlight;
-use crate::primitives::spotlight_draw::SpotlightDraw;
+use crate::primitives::object_draw::ShadingModel;
+use crate::primitives::post_process::PostProcessConfig;
+use crate::primitives::shadows::ShadowSettings;
+use crate::primitives::ssao::SsaoSettings;
 use crate::primitives::time::GameTime;
-use crate::primitives::triangle::VertexData;
+use crate::renderer::opengl::GlRenderer;
+use crate::renderer::RenderSettings;
 use crate::resources::Resources;
 
 mod controls;
 mod gamecube;
 mod gamelight;
+pub(crate) mod scene;
+mod screenshots;
 
 const MOVEMENT_PER_SECOND: f32 = 10f32;
 const SPIN_PER_MOUSE_PIXEL: f32 = TAU / 2600f32;
@@ -37,20 +34,65 @@ const ZOOM_PER_SCROLL_PIXEL: f32 = 0.1f32;
 const RUN_MULTIPLIER: f32 = 10f32;
 const WALK_MULTIPLIER: f32 = 0.1f32;
 
-struct Settings {
-    vsync: bool,
+/// How the game starts out, everything else can only be changed while playing
+#[derive(Debug, Clone)]
+pub struct SceneOptions {
+    /// Seeds the placement and colors of objects and lights, `None` picks a random seed
+    pub seed: Option<u64>,
+    pub render_path: RenderPath,
+    pub shading: ShadingModel,
+    /// Runs the passes of post_process.cfg
+    pub post_process: bool,
+    pub framing: Framing,
+}
+
+/// Where the camera starts out
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Framing {
+    /// Far off to the side, where the game starts
+    Start,
+    /// Straight in front of the mosaic, which fills the frame. Meant for tests, which need every
+    /// pixel to show something.
+    Mosaic,
+}
+
+impl Default for SceneOptions {
+    fn default() -> Self {
+        SceneOptions {
+            seed: None,
+            render_path: RenderPath::Forward,
+            shading: ShadingModel::Classic,
+            post_process: true,
+            framing: Framing::Start,
+        }
+    }
+}
+
+/// The settings the scene is rendered with at first
+pub(crate) fn render_settings(res: &Resources, options: &SceneOptions) -> Result<RenderSettings, failure::Error> {
+    Ok(RenderSettings {
+        instanced_objects: true,
+        render_path: options.render_path,
+        shadows: ShadowSettings::default(),
+        ssao: SsaoSettings::default(),
+        shading: options.shading,
+        hdr: HdrSettings::default(),
+        light_emissive_strength: 8.0,
+        post_process: if options.post_process {
+            PostProcessConfig::from_res(res, "post_process.cfg")?
+        } else {
+            PostProcessConfig::default()
+        },
+    })
 }
 
 pub(crate) struct Game {
     pub ongoing: bool,
 
-    objects_draw: ObjectsDraw,
-    spotslights_draw: SpotlightDraw,
-    gamecubes: Vec<GameCube>,
-    gamelights: Vec<GameLight>,
-
-    // camera
-    camera: Camera,
+    scene: Scene,
+    renderer: GlRenderer,
+    screenshots: Screenshots,
+    screenshot_requested: bool,
 
     // controls
     key_map: KeyMap,
@@ -72,9 +114,7 @@ pub(crate) struct Game {
 
     game_time: GameTime,
 
-    // Game settings
-    settings: Settings,
-    rng: ThreadRng,
+    vsync: bool,
 }
 
 impl Game {
@@ -83,257 +123,71 @@ impl Game {
 
         self.apply_camera_rotations(second_fraction);
         self.apply_camera_movement(second_fraction);
-        self.camera = self.camera.normalize();
+        self.scene.camera = self.scene.camera.normalize();
 
-        self.wiggle_cubes(second_fraction);
-        self.move_lights(second_fraction);
+        self.scene.update(second_fraction);
     }
 
     pub fn apply_camera_rotations(&mut self, second_fraction: f32) {
-        self.camera.orientation.pitch += self.pitch_per_second * second_fraction;
-        self.camera.orientation.yaw += self.yaw_per_second * second_fraction;
-        self.camera.orientation.roll += self.roll_per_second * second_fraction;
+        self.scene.camera.orientation.pitch += self.pitch_per_second * second_fraction;
+        self.scene.camera.orientation.yaw += self.yaw_per_second * second_fraction;
+        self.scene.camera.orientation.roll += self.roll_per_second * second_fraction;
     }
 
     pub fn apply_camera_movement(&mut self, second_fraction: f32) {
-        let movement = self.move_per_second * self.camera.rotation_matrix() * Vector3::<f32>::new(0.0, 0.0, 1.0);
-        let strafe = self.strafe_per_second * self.camera.rotation_matrix() * Vector3::<f32>::new(1.0, 0.0, 0.0);
-        let fly = self.fly_per_second * self.camera.rotation_matrix() * Vector3::<f32>::new(0.0, 1.0, 0.0);
+        let movement = self.move_per_second * self.scene.camera.rotation_matrix() * Vector3::<f32>::new(0.0, 0.0, 1.0);
+        let strafe = self.strafe_per_second * self.scene.camera.rotation_matrix() * Vector3::<f32>::new(1.0, 0.0, 0.0);
+        let fly = self.fly_per_second * self.scene.camera.rotation_matrix() * Vector3::<f32>::new(0.0, 1.0, 0.0);
 
         let combined = (movement + strafe + fly) * second_fraction;
 
-        self.camera.location.x += combined.x;
-        self.camera.location.y += combined.y;
-        self.camera.location.z += combined.z;
-    }
-
-    fn wiggle_cubes(&mut self, second_fraction: f32) {
-        let mut rng = self.rng.clone();
-        let rotspeed = std::f32::consts::TAU * second_fraction * 0.03;
-        let movspeed = second_fraction * 0.1;
-        let scalespeed = second_fraction * 0.1;
-
-        self.gamecubes.iter_mut().for_each(|gamecube| {
-            gamecube.spatial.orientation = Orientation {
-                pitch: gamecube.spatial.orientation.pitch + rng.gen_range(0f32..rotspeed),
-                roll: gamecube.spatial.orientation.roll + rng.gen_range(0f32..rotspeed),
-                yaw: gamecube.spatial.orientation.yaw + rng.gen_range(0f32..rotspeed),
-            };
-            gamecube.spatial.location = Location {
-                x: gamecube.spatial.location.x + rng.gen_range(-movspeed..movspeed),
-                y: gamecube.spatial.location.y + rng.gen_range(-movspeed..movspeed),
-                z: gamecube.spatial.location.z + rng.gen_range(-movspeed..movspeed),
-            };
-            gamecube.spatial.scale += rng.gen_range(-scalespeed..scalespeed);
-        });
-
-        self.rng = rng;
-    }
-
-    fn move_lights(&mut self, second_fraction: f32) {
-        self.gamelights.iter_mut().for_each(|gamelight| {
-            gamelight.set_angle((gamelight.angle + second_fraction * gamelight.spin_speed) % TAU);
-            gamelight.set_location(Location::new(
-                gamelight.center.x + gamelight.x_speed * second_fraction,
-                gamelight.center.y + gamelight.y_speed * second_fraction,
-                gamelight.center.z + gamelight.z_speed * second_fraction,
-            ));
-        });
+        self.scene.camera.location.x += combined.x;
+        self.scene.camera.location.y += combined.y;
+        self.scene.camera.location.z += combined.z;
     }
 
-    pub(crate) fn draw(&self, gl: &gl::Gl) {
-        let (view_rotation, view_translation, view_location) = self.camera.view();
-
-        self.objects_draw.set_view(&view_rotation, &view_translation, &view_location);
-
-        self.objects_draw
-            .set_spotlights(self.gamelights.iter().map(|gamelight| (&gamelight.spotlight, &gamelight.location)));
-
-        let num_vertices = self.gamecubes[0].cube.verticies.len();
-        self.objects_draw.prepare_for_draws();
-        self.gamecubes.iter().enumerate().for_each(|(i, cube)| {
-            let (model_scale, model_translation, model_rotation) = cube.model();
-            self.objects_draw
-                .draw(gl, model_scale, &model_translation, &model_rotation, num_vertices, num_vertices * i);
-        });
-
-        self.spotslights_draw.set_view(&view_translation, &view_rotation);
+    pub(crate) fn draw(&mut self) -> Result<(), failure::Error> {
+        self.scene.draw(&mut self.renderer);
 
-        let num_vertices = self.gamelights[0].spotlight.cube.verticies.len();
-        self.spotslights_draw.prepare_for_draws();
-        self.gamelights.iter().enumerate().for_each(|(i, spotlight)| {
-            let (model_scale, model_translation, model_rotation) = spotlight.model();
-
-            self.spotslights_draw.set_solid_color(&Vector4::<f32>::new(
-                spotlight.spotlight.color.r,
-                spotlight.spotlight.color.g,
-                spotlight.spotlight.color.b,
-                spotlight.spotlight.color.a,
-            ));
-
-            self.spotslights_draw
-                .draw(gl, model_scale, &model_translation, &model_rotation, num_vertices, num_vertices * i);
-        });
-    }
-
-    fn lerp(t: f32, a: Location, b: Location) -> Location {
-        Location {
-            x: a.x * t + b.x * (1.0 - t),
-            y: a.y * t + b.y * (1.0 - t),
-            z: a.z * t + b.z * (1.0 - t),
+        if std::mem::take(&mut self.screenshot_requested) {
+            self.screenshot()?;
         }
-    }
+        self.screenshots.poll()?;
 
-    fn cubic_bezier(t: f32, a: Location, b: Location, c: Location, d: Location) -> Location {
-        let e = Self::lerp(t, a, b);
-        let f = Self::lerp(t, b, c);
-        let g = Self::lerp(t, c, d);
-        let h = Self::lerp(t, e, f);
-        let i = Self::lerp(t, f, g);
-        Self::lerp(t, h, i)
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    fn get_lights(rng: &mut ThreadRng) -> Vec<GameLight> {
-        let spot_radius = 15.0;
-        let spin_speed = TAU / 100.0;
-        let z = 2.0;
-        let center = Location { x: 0.0, y: 0.0, z };
-
-        let mut game_lights = vec![];
-        game_lights.push(GameLight::new(
-            TAU * 0.0 / 3.0,
-            Location::new(center.x, center.y, 10.0),
-            20.0,
-            TAU / 100.0,
-            Spotlight::new(WHITE, 100.0),
-        ));
-
-        let step = 3;
-        for i in (1..200).step_by(step) {
-            let spin_radius = 1.0 * i as f32 / step as f32;
-            let angle_offset = (TAU / 1.61803) * i as f32 / step as f32;
-            game_lights.push(GameLight::new(
-                TAU * 0.0 / 3.0 + angle_offset,
-                center,
-                spin_radius,
-                spin_speed * i as f32 / step as f32,
-                Spotlight::new(Color::random(rng), spot_radius),
-            ));
-            game_lights.push(GameLight::new(
-                TAU * 0.0 / 3.0 + angle_offset,
-                center,
-                spin_radius,
-                spin_speed * i as f32 / step as f32,
-                Spotlight::new(Color::random(rng), spot_radius),
-            ));
-            game_lights.push(GameLight::new(
-                TAU * 2.0 / 3.0 + angle_offset,
-                center,
-                spin_radius,
-                spin_speed * i as f32 / step as f32,
-                Spotlight::new(Color::random(rng), spot_radius),
-            ));
-        }
-
-        game_lights
+    /// Saves a screenshot of the next frame drawn
+    pub(crate) fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
     }
 
-    fn get_lights2(rng: &mut ThreadRng) -> Vec<GameLight> {
-        let spot_radius = 15.0;
-        let spin_speed = TAU / 100.0;
-
-        let mut game_lights = vec![];
-
-        let step = 3;
-        for i in (1..200).step_by(step) {
-            let t = i as f32 / 200f32;
-            let location = Self::cubic_bezier(
-                t,
-                Location {
-                    x: 0.0f32,
-                    y: 0.0f32,
-                    z: 0.0f32,
-                },
-                Location {
-                    x: 0.0f32,
-                    y: 15.0f32,
-                    z: 0.0f32,
-                },
-                Location {
-                    x: 20.0f32,
-                    y: 0.0f32,
-                    z: 0.0f32,
-                },
-                Location {
-                    x: 0.0f32,
-                    y: 0.0f32,
-                    z: 15.0f32,
-                },
-            );
-            game_lights.push(GameLight::new(
-                0.0,
-                location,
-                0.0,
-                spin_speed * i as f32 / step as f32,
-                Spotlight::new(Color::random(rng), spot_radius),
-            ));
-        }
-
-        game_lights
-    }
-
-    fn get_cubes() -> Vec<GameCube> {
-        let img = image::load_from_memory(include_bytes!("rs.png")).unwrap();
-
-        let mut game_cubes = vec![];
-
-        let (w, h) = img.dimensions();
-
-        let step = 50;
-        for i in (0..w).step_by(step) {
-            for j in (0..h).step_by(step) {
-                let spatial = Spatial::new(
-                    Location {
-                        x: 0f32 + (i as f32 / step as f32) * 3.3,
-                        y: 0f32 + (j as f32 / step as f32) * 3.3,
-                        z: 0.0,
-                    },
-                    Orientation::default(),
-                    5.0,
-                );
-
-                let color = Color {
-                    r: (img.get_pixel(i, h - j - 1).to_rgb()[0] as f32) / 255f32,
-                    g: (img.get_pixel(i, h - j - 1).to_rgb()[1] as f32) / 255f32,
-                    b: (img.get_pixel(i, h - j - 1).to_rgb()[2] as f32) / 255f32,
-                    a: 1.0,
-                };
-
-                let cube = Suzanne::new(color);
-
-                game_cubes.push(GameCube::new(spatial, cube));
+    // Reads back the frame that was just drawn, unless it has to be rendered again supersampled
+    fn screenshot(&mut self) -> Result<(), failure::Error> {
+        match self.screenshots.settings.supersampling {
+            1 => self.screenshots.read_back(self.renderer.read_frame()),
+            supersampling => {
+                let image = self.capture(supersampling)?;
+                self.screenshots.save(image);
             }
         }
 
-        game_cubes
+        Ok(())
     }
 
-    pub fn default_camera() -> Camera {
-        Camera {
-            location: Location {
-                x: 130f32,
-                y: 0f32,
-                z: 130f32,
-            },
-            orientation: Orientation {
-                pitch: TAU / 8.0,
-                roll: 0f32,
-                yaw: 0f32,
-            },
-        }
+    /// Renders the current frame offscreen at `supersampling` times the viewport size, and scales
+    /// it back down to the viewport size
+    pub(crate) fn capture(&mut self, supersampling: u32) -> Result<RgbaImage, failure::Error> {
+        let (width, height) = self.renderer.viewport_size();
+        let scene = &self.scene;
+        let frame = self
+            .renderer
+            .render_offscreen((width * supersampling, height * supersampling), |renderer| scene.draw(renderer))?;
+
+        Ok(screenshots::downsample(frame, (width, height)))
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         res: Resources,
         gl: &gl::Gl,
@@ -341,28 +195,20 @@ impl Game {
         timer_frequency: u64,
         tick_length_us: u64,
         video_subsystem: sdl2::VideoSubsystem,
-        aspect: f32,
+        viewport_size: (u32, u32),
+        options: &SceneOptions,
     ) -> Result<Game, failure::Error> {
-        let img_cubes = Self::get_cubes();
-        let img_verticies: Vec<VertexData> = img_cubes.iter().flat_map(|c| c.cube.verticies.clone()).collect();
-
-        let mut rng = rand::thread_rng();
-
-        let gamelights = Self::get_lights2(&mut rng);
-        let spot_verticies: Vec<VertexData> = gamelights.iter().flat_map(|g| g.spotlight.cube.verticies.clone()).collect();
-
-        let spotlight_draw = SpotlightDraw::new(&res, gl, spot_verticies)?;
+        let (scene, assets) = Scene::new(options);
+        let renderer = GlRenderer::new(&res, gl, assets, render_settings(&res, options)?, viewport_size)?;
 
         let mut game = Self {
-            rng,
-
             ongoing: true,
 
             key_map: init_key_map(),
-            objects_draw: ObjectsDraw::new(&res, gl, img_verticies)?,
-            spotslights_draw: spotlight_draw,
-            gamecubes: img_cubes,
-            gamelights,
+            scene,
+            renderer,
+            screenshots: Screenshots::new(ScreenshotSettings::from_env()?),
+            screenshot_requested: false,
 
             // Default rotation speed
             roll_per_second: 0f32,
@@ -374,31 +220,26 @@ impl Game {
             strafe_per_second: 0f32,
             fly_per_second: 0f32,
 
-            camera: Self::default_camera(),
-
             video_subsystem,
 
-            settings: Settings { vsync: false },
+            vsync: false,
             game_time: GameTime::new(timer_frequency, tick_length_us, initial_time),
             key_stack: KeyStack::new(),
             mouse_down: false,
         };
 
         game.enable_vsync();
-        game.set_aspect_ratio(aspect);
 
         Ok(game)
     }
 
-    pub fn set_aspect_ratio(&mut self, aspect: f32) {
-        let projection = &perspective(aspect);
-        self.objects_draw.set_projection(projection);
-        self.spotslights_draw.set_projection(projection);
+    pub fn set_viewport_size(&mut self, width: u32, height: u32) -> Result<(), failure::Error> {
+        self.renderer.resize((width, height))
     }
 
     pub fn enable_vsync(&mut self) {
         if self.video_subsystem.gl_set_swap_interval(sdl2::video::SwapInterval::VSync).is_ok() {
-            self.settings.vsync = true;
+            self.vsync = true;
         } else {
             println!("Failed to enable vsync")
         }
@@ -410,14 +251,14 @@ impl Game {
             .gl_set_swap_interval(sdl2::video::SwapInterval::Immediate)
             .is_ok()
         {
-            self.settings.vsync = false;
+            self.vsync = false;
         } else {
             println!("Failed to disable vsync")
         }
     }
 
     pub fn toggle_vsync(&mut self) {
-        if self.settings.vsync {
+        if self.vsync {
             self.disable_vsync()
         } else {
             self.enable_vsync()
@@ -451,7 +292,7 @@ impl Game {
         }
     }
 
-    pub fn keyboard_handler(&mut self) {
+    pub fn keyboard_handler(&mut self) -> Result<(), failure::Error> {
         let normalized = self.key_stack.normalize();
 
         if normalized.is_pressed(GameKey::VsyncToggle) {
@@ -459,23 +300,52 @@ impl Game {
             self.toggle_vsync();
         }
 
+        if normalized.is_pressed(GameKey::ShadingToggle) {
+            self.key_stack = self.key_stack.depress(GameKey::ShadingToggle);
+            self.renderer.toggle_shading();
+        }
+
+        if normalized.is_pressed(GameKey::RenderPathToggle) {
+            self.key_stack = self.key_stack.depress(GameKey::RenderPathToggle);
+            self.renderer.toggle_render_path()?;
+        }
+
+        if normalized.is_pressed(GameKey::AmbientOcclusionToggle) {
+            self.key_stack = self.key_stack.depress(GameKey::AmbientOcclusionToggle);
+            self.renderer.toggle_ambient_occlusion();
+        }
+
+        for index in 0..self.renderer.post_process_passes().len() {
+            if normalized.is_pressed(GameKey::PostProcessToggle(index)) {
+                self.key_stack = self.key_stack.depress(GameKey::PostProcessToggle(index));
+                self.renderer.toggle_post_process(index);
+            }
+        }
+
+        if normalized.is_pressed(GameKey::Screenshot) {
+            self.key_stack = self.key_stack.depress(GameKey::Screenshot);
+            self.request_screenshot();
+        }
+
         if normalized.is_pressed(GameKey::Quit) {
             self.key_stack = self.key_stack.depress(GameKey::Quit);
             self.ongoing = false;
         }
 
         self.handle_keyboard_movement(normalized);
+
+        Ok(())
     }
 
     pub fn mouse_moved(&mut self, movement: MouseMovement) {
         let x_diff = SPIN_PER_MOUSE_PIXEL * (movement.0 as f32);
         let y_diff = SPIN_PER_MOUSE_PIXEL * (movement.1 as f32);
-        self.camera.orientation.yaw -= x_diff;
-        self.camera.orientation.pitch -= y_diff;
+        self.scene.camera.orientation.yaw -= x_diff;
+        self.scene.camera.orientation.pitch -= y_diff;
     }
 
     pub fn mouse_scrolled(&mut self, movement: MouseWheelDirection, _x: i32, y: i32) {
-        self.camera.location.y += (match movement {
+        self.scene.camera.location.y += (match movement {
             MouseWheelDirection::Normal => y as f32,
             MouseWheelDirection::Flipped => -y as f32,
             MouseWheelDirection::Unknown(..) => 0f32,
@@ -489,7 +359,7 @@ impl Game {
             }
     }
 
-    pub fn input_handler(&mut self, event: sdl2::event::Event) {
+    pub fn input_handler(&mut self, event: sdl2::event::Event) -> Result<(), failure::Error> {
         match event {
             sdl2::event::Event::MouseButtonDown { .. } => self.mouse_down = true,
             sdl2::event::Event::MouseButtonUp { .. } => self.mouse_down = false,
@@ -513,6 +383,6 @@ impl Game {
             _ => {}
         };
 
-        self.keyboard_handler();
+        self.keyboard_handler()
     }
 }
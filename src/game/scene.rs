@@ -0,0 +1,326 @@
+use std::f32::consts::TAU;
+
+use image::{GenericImageView, Pixel};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::game::gamecube::GameCube;
+use crate::game::gamelight::GameLight;
+use crate::game::{Framing, SceneOptions};
+use crate::models::cube::Cube;
+use crate::models::material::{Material, MaterialHandle, MaterialRegistry};
+use crate::models::mesh_registry::{MeshHandle, MeshRegistry};
+use crate::models::suzanne::Suzanne;
+use crate::models::world_model::Spatial;
+use crate::primitives::camera::Camera;
+use crate::primitives::environment::Environment;
+use crate::primitives::light::consts::{DARK_GRAY, WHITE};
+use crate::primitives::light::Color;
+use crate::primitives::spatial::{Location, Orientation};
+use crate::primitives::spotlight::Spotlight;
+use crate::renderer::{Renderer, SceneAssets, SceneLight, Transform};
+
+/// What the game shows: a mosaic of Suzannes lit by a string of lights, and the camera looking at
+/// it. Knows nothing about how it's rendered.
+pub(crate) struct Scene {
+    pub camera: Camera,
+    gamecubes: Vec<GameCube>,
+    gamelights: Vec<GameLight>,
+    rng: StdRng,
+}
+
+impl Scene {
+    /// Builds the scene, along with the meshes, materials and environment it's rendered with
+    pub fn new(options: &SceneOptions) -> (Scene, SceneAssets) {
+        let mut meshes = MeshRegistry::new();
+        let suzanne = meshes.load("suzanne", || Suzanne::new(DARK_GRAY).verticies);
+        let cube = meshes.load("cube", || Cube::new(WHITE).verticies);
+
+        let mut materials = MaterialRegistry::new();
+        let plastic = materials.add("plastic", Material::default());
+        let metal = materials.add(
+            "metal",
+            Material {
+                base_color: Color::new(0.9, 0.9, 1.0),
+                ambient: 0.2,
+                specular_strength: 300.0,
+                specular_roughness: 0.4,
+                metallic: 1.0,
+                roughness: 0.3,
+                ..Material::default()
+            },
+        );
+
+        let mut rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        let scene = Scene {
+            camera: match options.framing {
+                Framing::Start => Self::default_camera(),
+                Framing::Mosaic => Self::mosaic_camera(),
+            },
+            gamecubes: Self::get_cubes(suzanne, [plastic, metal]),
+            gamelights: Self::get_lights2(&mut rng, cube),
+            rng,
+        };
+        let assets = SceneAssets {
+            meshes,
+            materials,
+            environment: Environment::sky(1.0),
+        };
+
+        (scene, assets)
+    }
+
+    /// Moves everything along by `second_fraction` of a second
+    pub fn update(&mut self, second_fraction: f32) {
+        // Nothing moves without time passing, and the wiggles' random ranges would be empty
+        if second_fraction <= 0.0 {
+            return;
+        }
+
+        self.wiggle_cubes(second_fraction);
+        self.move_lights(second_fraction);
+    }
+
+    /// Draws a frame of the scene as it is now
+    pub fn draw(&self, renderer: &mut impl Renderer) {
+        renderer.clear();
+        renderer.set_camera(&self.camera);
+
+        let lights: Vec<SceneLight> = self
+            .gamelights
+            .iter()
+            .map(|gamelight| SceneLight {
+                spotlight: gamelight.spotlight,
+                location: gamelight.location,
+            })
+            .collect();
+        renderer.set_lights(&lights);
+
+        self.gamecubes
+            .iter()
+            .for_each(|cube| renderer.draw_mesh(cube.mesh, cube.material, &Transform::of(cube), cube.color));
+
+        // Lights are drawn in a bright solid color
+        self.gamelights
+            .iter()
+            .for_each(|gamelight| renderer.draw_light_mesh(gamelight.mesh, gamelight.spotlight.color, &Transform::of(gamelight)));
+
+        renderer.present();
+    }
+
+    fn wiggle_cubes(&mut self, second_fraction: f32) {
+        let mut rng = self.rng.clone();
+        let rotspeed = std::f32::consts::TAU * second_fraction * 0.03;
+        let movspeed = second_fraction * 0.1;
+        let scalespeed = second_fraction * 0.1;
+
+        self.gamecubes.iter_mut().for_each(|gamecube| {
+            gamecube.spatial.orientation = Orientation {
+                pitch: gamecube.spatial.orientation.pitch + rng.gen_range(0f32..rotspeed),
+                roll: gamecube.spatial.orientation.roll + rng.gen_range(0f32..rotspeed),
+                yaw: gamecube.spatial.orientation.yaw + rng.gen_range(0f32..rotspeed),
+            };
+            gamecube.spatial.location = Location {
+                x: gamecube.spatial.location.x + rng.gen_range(-movspeed..movspeed),
+                y: gamecube.spatial.location.y + rng.gen_range(-movspeed..movspeed),
+                z: gamecube.spatial.location.z + rng.gen_range(-movspeed..movspeed),
+            };
+            gamecube.spatial.scale += rng.gen_range(-scalespeed..scalespeed);
+        });
+
+        self.rng = rng;
+    }
+
+    fn move_lights(&mut self, second_fraction: f32) {
+        self.gamelights.iter_mut().for_each(|gamelight| {
+            gamelight.set_angle((gamelight.angle + second_fraction * gamelight.spin_speed) % TAU);
+            gamelight.set_location(Location::new(
+                gamelight.center.x + gamelight.x_speed * second_fraction,
+                gamelight.center.y + gamelight.y_speed * second_fraction,
+                gamelight.center.z + gamelight.z_speed * second_fraction,
+            ));
+        });
+    }
+
+    fn lerp(t: f32, a: Location, b: Location) -> Location {
+        Location {
+            x: a.x * t + b.x * (1.0 - t),
+            y: a.y * t + b.y * (1.0 - t),
+            z: a.z * t + b.z * (1.0 - t),
+        }
+    }
+
+    fn cubic_bezier(t: f32, a: Location, b: Location, c: Location, d: Location) -> Location {
+        let e = Self::lerp(t, a, b);
+        let f = Self::lerp(t, b, c);
+        let g = Self::lerp(t, c, d);
+        let h = Self::lerp(t, e, f);
+        let i = Self::lerp(t, f, g);
+        Self::lerp(t, h, i)
+    }
+
+    #[allow(dead_code)]
+    fn get_lights(rng: &mut impl Rng, mesh: MeshHandle) -> Vec<GameLight> {
+        let spot_radius = 15.0;
+        let spin_speed = TAU / 100.0;
+        let z = 2.0;
+        let center = Location { x: 0.0, y: 0.0, z };
+
+        let mut game_lights = vec![];
+        game_lights.push(GameLight::new(
+            TAU * 0.0 / 3.0,
+            Location::new(center.x, center.y, 10.0),
+            20.0,
+            TAU / 100.0,
+            Spotlight::new(WHITE, 100.0).with_shadows(),
+            mesh,
+        ));
+
+        let step = 3;
+        for i in (1..200).step_by(step) {
+            let spin_radius = 1.0 * i as f32 / step as f32;
+            let angle_offset = (TAU / 1.61803) * i as f32 / step as f32;
+            game_lights.push(GameLight::new(
+                TAU * 0.0 / 3.0 + angle_offset,
+                center,
+                spin_radius,
+                spin_speed * i as f32 / step as f32,
+                Spotlight::new(Color::random(rng), spot_radius),
+                mesh,
+            ));
+            game_lights.push(GameLight::new(
+                TAU * 0.0 / 3.0 + angle_offset,
+                center,
+                spin_radius,
+                spin_speed * i as f32 / step as f32,
+                Spotlight::new(Color::random(rng), spot_radius),
+                mesh,
+            ));
+            game_lights.push(GameLight::new(
+                TAU * 2.0 / 3.0 + angle_offset,
+                center,
+                spin_radius,
+                spin_speed * i as f32 / step as f32,
+                Spotlight::new(Color::random(rng), spot_radius),
+                mesh,
+            ));
+        }
+
+        game_lights
+    }
+
+    fn get_lights2(rng: &mut impl Rng, mesh: MeshHandle) -> Vec<GameLight> {
+        let spot_radius = 15.0;
+        let spin_speed = TAU / 100.0;
+
+        let mut game_lights = vec![];
+
+        let step = 3;
+        for i in (1..200).step_by(step) {
+            let t = i as f32 / 200f32;
+            let location = Self::cubic_bezier(
+                t,
+                Location {
+                    x: 0.0f32,
+                    y: 0.0f32,
+                    z: 0.0f32,
+                },
+                Location {
+                    x: 0.0f32,
+                    y: 15.0f32,
+                    z: 0.0f32,
+                },
+                Location {
+                    x: 20.0f32,
+                    y: 0.0f32,
+                    z: 0.0f32,
+                },
+                Location {
+                    x: 0.0f32,
+                    y: 0.0f32,
+                    z: 15.0f32,
+                },
+            );
+            let mut spotlight = Spotlight::new(Color::random(rng), spot_radius);
+            // Spread the shadow casters along the curve
+            if i % 48 == 1 {
+                spotlight = spotlight.with_shadows();
+            }
+
+            game_lights.push(GameLight::new(
+                0.0,
+                location,
+                0.0,
+                spin_speed * i as f32 / step as f32,
+                spotlight,
+                mesh,
+            ));
+        }
+
+        game_lights
+    }
+
+    /// Suzannes in the colors of rs.png, in a checkerboard of the two `materials`
+    fn get_cubes(mesh: MeshHandle, materials: [MaterialHandle; 2]) -> Vec<GameCube> {
+        let img = image::load_from_memory(include_bytes!("rs.png")).unwrap();
+
+        let mut game_cubes = vec![];
+
+        let (w, h) = img.dimensions();
+
+        let step = 50;
+        for i in (0..w).step_by(step) {
+            for j in (0..h).step_by(step) {
+                let spatial = Spatial::new(
+                    Location {
+                        x: 0f32 + (i as f32 / step as f32) * 3.3,
+                        y: 0f32 + (j as f32 / step as f32) * 3.3,
+                        z: 0.0,
+                    },
+                    Orientation::default(),
+                    5.0,
+                );
+
+                let color = Color {
+                    r: (img.get_pixel(i, h - j - 1).to_rgb()[0] as f32) / 255f32,
+                    g: (img.get_pixel(i, h - j - 1).to_rgb()[1] as f32) / 255f32,
+                    b: (img.get_pixel(i, h - j - 1).to_rgb()[2] as f32) / 255f32,
+                    a: 1.0,
+                };
+
+                let material = materials[((i + j) as usize / step) % 2];
+
+                game_cubes.push(GameCube::new(spatial, mesh, material, color));
+            }
+        }
+
+        game_cubes
+    }
+
+    /// Looking down at the middle of the mosaic, from close enough that it fills the frame's height
+    pub fn mosaic_camera() -> Camera {
+        Camera {
+            location: Location { x: 8.25, y: 8.25, z: 15.0 },
+            orientation: Orientation::default(),
+        }
+    }
+
+    pub fn default_camera() -> Camera {
+        Camera {
+            location: Location {
+                x: 130f32,
+                y: 0f32,
+                z: 130f32,
+            },
+            orientation: Orientation {
+                pitch: TAU / 8.0,
+                roll: 0f32,
+                yaw: 0f32,
+            },
+        }
+    }
+}
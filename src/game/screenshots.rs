@@ -0,0 +1,239 @@
+use std::io;
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+
+use crate::render_gl::readback;
+use crate::render_gl::FrameReadback;
+
+/// Overrides the directory screenshots are saved into
+const DIRECTORY_VAR: &str = "CGI_SCREENSHOT_DIR";
+
+/// Overrides the supersampling factor of screenshots
+const SUPERSAMPLING_VAR: &str = "CGI_SCREENSHOT_SUPERSAMPLING";
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Invalid screenshot supersampling \"{}\", it has to be a whole number from 1 on", value)]
+    InvalidSupersampling { value: String },
+    #[fail(display = "Failed to read back a screenshot")]
+    ReadBack(#[cause] readback::Error),
+    #[fail(display = "Failed to create the screenshot directory {:?}", path)]
+    CreateDirectory {
+        path: PathBuf,
+        #[cause]
+        inner: io::Error,
+    },
+    #[fail(display = "Failed to save screenshot {:?}", path)]
+    Save {
+        path: PathBuf,
+        #[cause]
+        inner: image::ImageError,
+    },
+}
+
+impl From<readback::Error> for Error {
+    fn from(other: readback::Error) -> Self {
+        Error::ReadBack(other)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScreenshotSettings {
+    /// Created when the first screenshot is saved
+    pub directory: PathBuf,
+    /// Screenshots are rendered at this multiple of the window size and scaled back down to it,
+    /// which smooths edges beyond what MSAA does. At 1 they're what the window shows.
+    pub supersampling: u32,
+}
+
+impl Default for ScreenshotSettings {
+    fn default() -> Self {
+        ScreenshotSettings {
+            directory: PathBuf::from("screenshots"),
+            supersampling: 1,
+        }
+    }
+}
+
+impl ScreenshotSettings {
+    /// The defaults, with the directory from `CGI_SCREENSHOT_DIR` and the supersampling from
+    /// `CGI_SCREENSHOT_SUPERSAMPLING` when they're set
+    pub fn from_env() -> Result<ScreenshotSettings, Error> {
+        let mut settings = ScreenshotSettings::default();
+
+        if let Some(directory) = std::env::var_os(DIRECTORY_VAR) {
+            settings.directory = PathBuf::from(directory);
+        }
+        if let Ok(value) = std::env::var(SUPERSAMPLING_VAR) {
+            settings.supersampling = match value.parse() {
+                Ok(supersampling) if supersampling >= 1 => supersampling,
+                _ => return Err(Error::InvalidSupersampling { value }),
+            };
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Saves screenshots into timestamped PNGs. Frames read back from the GPU are only saved once
+/// their readback finished, and encoding and writing happens on threads of their own, so taking a
+/// screenshot doesn't make the game stutter.
+pub(crate) struct Screenshots {
+    pub settings: ScreenshotSettings,
+    pending: Vec<FrameReadback>,
+    saving: Vec<JoinHandle<Result<(), Error>>>,
+}
+
+impl Screenshots {
+    pub fn new(settings: ScreenshotSettings) -> Screenshots {
+        Screenshots {
+            settings,
+            pending: vec![],
+            saving: vec![],
+        }
+    }
+
+    /// Saves the frame once `readback` finishes, as seen by [`Self::poll`]
+    pub fn read_back(&mut self, readback: FrameReadback) {
+        self.pending.push(readback);
+    }
+
+    /// Saves the frames whose readback finished, meant to be called once a frame. Returns the
+    /// first of the errors from those, and from the saves that finished since the last call.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        let (ready, pending): (Vec<FrameReadback>, Vec<FrameReadback>) = self.pending.drain(..).partition(FrameReadback::is_ready);
+        self.pending = pending;
+        let mut result = Ok(());
+        for readback in ready {
+            result = result.and(self.finish(readback));
+        }
+
+        let (finished, saving): (Vec<_>, Vec<_>) = self.saving.drain(..).partition(|thread| thread.is_finished());
+        self.saving = saving;
+        for thread in finished {
+            // A panic was already reported by the thread itself
+            result = result.and(thread.join().unwrap_or(Ok(())));
+        }
+
+        result
+    }
+
+    fn finish(&mut self, readback: FrameReadback) -> Result<(), Error> {
+        self.save(readback.finish()?);
+        Ok(())
+    }
+
+    /// Writes `image` into a new file in the directory, named after the current time
+    pub fn save(&mut self, image: RgbaImage) {
+        let directory = self.settings.directory.clone();
+        let path = directory.join(format!("screenshot_{}.png", utc_timestamp(since_epoch())));
+
+        self.saving.push(std::thread::spawn(move || {
+            std::fs::create_dir_all(&directory).map_err(|inner| Error::CreateDirectory { path: directory, inner })?;
+            image.save(&path).map_err(|inner| Error::Save { path, inner })
+        }));
+    }
+}
+
+impl Drop for Screenshots {
+    // Screenshots taken right before quitting still get saved, though their errors have nowhere
+    // left to go
+    fn drop(&mut self) {
+        let pending: Vec<FrameReadback> = self.pending.drain(..).collect();
+        pending.into_iter().for_each(|readback| {
+            let _ = self.finish(readback);
+        });
+
+        self.saving.drain(..).for_each(|thread| {
+            let _ = thread.join();
+        });
+    }
+}
+
+/// Scales a frame rendered at a multiple of `size` back down to it, averaging the pixels
+pub(crate) fn downsample(frame: RgbaImage, size: (u32, u32)) -> RgbaImage {
+    if frame.dimensions() == size {
+        frame
+    } else {
+        image::imageops::thumbnail(&frame, size.0, size.1)
+    }
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+// As YYYY-MM-DD_HH-MM-SS.mmm in UTC, which sorts like the times do
+fn utc_timestamp(since_epoch: Duration) -> String {
+    let seconds = since_epoch.as_secs();
+    let (days, time) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Howard Hinnant's civil_from_days, in eras of 400 years starting on March 1st
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use image::{Rgba, RgbaImage};
+
+    use super::{downsample, utc_timestamp};
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(utc_timestamp(Duration::ZERO), "1970-01-01_00-00-00.000");
+        assert_eq!(utc_timestamp(Duration::from_millis(951_782_400_250)), "2000-02-29_00-00-00.250");
+        assert_eq!(utc_timestamp(Duration::from_secs(1_700_000_000)), "2023-11-14_22-13-20.000");
+    }
+
+    #[test]
+    fn supersampled_frames_average_their_pixels() {
+        // 2x2 blocks, a solid red one and a black and white checkerboard
+        let frame = RgbaImage::from_fn(4, 2, |x, y| match x {
+            0 | 1 => Rgba([255, 0, 0, 255]),
+            _ if (x + y) % 2 == 0 => Rgba([255, 255, 255, 255]),
+            _ => Rgba([0, 0, 0, 255]),
+        });
+
+        let image = downsample(frame, (2, 1));
+
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(*image.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        let gray = image.get_pixel(1, 0);
+        assert!(gray.0[..3].iter().all(|channel| (127..=128).contains(channel)), "{:?}", gray);
+        assert_eq!(gray.0[3], 255);
+    }
+
+    #[test]
+    fn frames_at_the_size_are_kept() {
+        let frame = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        assert_eq!(downsample(frame.clone(), (3, 2)), frame);
+    }
+}
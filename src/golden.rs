@@ -0,0 +1,234 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::{Rgba, RgbaImage};
+
+// The largest possible YIQ delta, between fully saturated colors
+const MAX_DELTA: f32 = 35215.0;
+
+/// Setting this environment variable makes [`check`] overwrite the references instead of
+/// comparing against them
+pub const BLESS_VAR: &str = "CGI_BLESS";
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "No reference image {:?} for {}, run with CGI_BLESS=1 to create it", path, name)]
+    MissingReference { name: String, path: PathBuf },
+    #[fail(
+        display = "{} is {}x{} but its reference is {}x{}",
+        name, actual_width, actual_height, reference_width, reference_height
+    )]
+    SizeMismatch {
+        name: String,
+        actual_width: u32,
+        actual_height: u32,
+        reference_width: u32,
+        reference_height: u32,
+    },
+    #[fail(
+        display = "{} differs from its reference in {} of {} pixels, see {:?}",
+        name, differing, total, diff
+    )]
+    Differs {
+        name: String,
+        differing: usize,
+        total: usize,
+        diff: PathBuf,
+    },
+    #[fail(display = "Failed to read or write an image")]
+    Image(#[cause] image::ImageError),
+    #[fail(display = "I/O error")]
+    Io(#[cause] io::Error),
+}
+
+impl From<image::ImageError> for Error {
+    fn from(other: image::ImageError) -> Self {
+        Error::Image(other)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(other: io::Error) -> Self {
+        Error::Io(other)
+    }
+}
+
+/// How different a rendered image may be from its reference, to allow for drivers rounding
+/// differently
+#[derive(Debug, Copy, Clone)]
+pub struct Tolerance {
+    /// How different a pixel may look before it counts as differing, from 0 for identical to 1
+    /// for the most different colors
+    pub pixel_threshold: f32,
+    /// The fraction of the pixels which may differ
+    pub max_differing: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            pixel_threshold: 0.1,
+            max_differing: 0.001,
+        }
+    }
+}
+
+/// The outcome of comparing two images of the same size
+pub struct Comparison {
+    /// Number of pixels past the threshold
+    pub differing: usize,
+    /// The reference faded out, with the differing pixels in red
+    pub diff: RgbaImage,
+}
+
+impl Comparison {
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        let total = (self.diff.width() * self.diff.height()) as f32;
+        self.differing as f32 <= total * tolerance.max_differing
+    }
+}
+
+// Pixels are blended onto white first, so that differences in invisible colors don't count
+fn yiq(pixel: &Rgba<u8>) -> (f32, f32, f32) {
+    let alpha = pixel[3] as f32 / 255.0;
+    let blend = |channel: u8| 255.0 + (channel as f32 - 255.0) * alpha;
+    let (r, g, b) = (blend(pixel[0]), blend(pixel[1]), blend(pixel[2]));
+
+    (
+        r * 0.2988953 + g * 0.5866225 + b * 0.1144822,
+        r * 0.595978 - g * 0.2741761 - b * 0.3218019,
+        r * 0.2114702 - g * 0.5226171 + b * 0.3111469,
+    )
+}
+
+/// How different two pixels look, from 0 to 1. Weighs brightness over hue, like the eye does.
+pub fn pixel_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let (ya, ia, qa) = yiq(a);
+    let (yb, ib, qb) = yiq(b);
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+
+    (0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / MAX_DELTA
+}
+
+/// Compares two images of the same size pixel by pixel
+pub fn compare(actual: &RgbaImage, reference: &RgbaImage, pixel_threshold: f32) -> Comparison {
+    let mut differing = 0;
+    let diff = RgbaImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let expected = reference.get_pixel(x, y);
+        if pixel_delta(actual.get_pixel(x, y), expected) > pixel_threshold {
+            differing += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let (brightness, _, _) = yiq(expected);
+            let faded = (255.0 - (255.0 - brightness) * 0.1) as u8;
+            Rgba([faded, faded, faded, 255])
+        }
+    });
+
+    Comparison { differing, diff }
+}
+
+/// Compares `actual` to the reference `dir/name.png`. If it's too different, or has no reference,
+/// what was rendered is written to `dir/failures/name.actual.png`, and the differences to
+/// `dir/failures/name.diff.png`.
+///
+/// With [`BLESS_VAR`] set, `actual` becomes the new reference instead.
+pub fn check(name: &str, actual: &RgbaImage, dir: &Path, tolerance: &Tolerance) -> Result<(), Error> {
+    let reference_path = dir.join(format!("{}.png", name));
+
+    if std::env::var_os(BLESS_VAR).is_some() {
+        std::fs::create_dir_all(dir)?;
+        actual.save(&reference_path)?;
+        return Ok(());
+    }
+
+    let failures = dir.join("failures");
+    let write_actual = || -> Result<(), Error> {
+        std::fs::create_dir_all(&failures)?;
+        actual.save(failures.join(format!("{}.actual.png", name)))?;
+        Ok(())
+    };
+
+    if !reference_path.exists() {
+        write_actual()?;
+        return Err(Error::MissingReference {
+            name: name.to_string(),
+            path: reference_path,
+        });
+    }
+
+    let reference = image::open(&reference_path)?.into_rgba8();
+    if actual.dimensions() != reference.dimensions() {
+        write_actual()?;
+        return Err(Error::SizeMismatch {
+            name: name.to_string(),
+            actual_width: actual.width(),
+            actual_height: actual.height(),
+            reference_width: reference.width(),
+            reference_height: reference.height(),
+        });
+    }
+
+    let comparison = compare(actual, &reference, tolerance.pixel_threshold);
+    if comparison.passes(tolerance) {
+        return Ok(());
+    }
+
+    write_actual()?;
+    let diff = failures.join(format!("{}.diff.png", name));
+    comparison.diff.save(&diff)?;
+
+    Err(Error::Differs {
+        name: name.to_string(),
+        differing: comparison.differing,
+        total: (actual.width() * actual.height()) as usize,
+        diff,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(8, 8, Rgba(color))
+    }
+
+    #[test]
+    fn black_and_white_are_far_apart() {
+        let delta = pixel_delta(&Rgba([0, 0, 0, 255]), &Rgba([255, 255, 255, 255]));
+        assert!(delta > 0.9 && delta <= 1.0, "{}", delta);
+    }
+
+    #[test]
+    fn transparent_pixels_look_the_same() {
+        assert_eq!(pixel_delta(&Rgba([0, 0, 0, 0]), &Rgba([255, 0, 0, 0])), 0.0);
+    }
+
+    #[test]
+    fn rounding_is_within_tolerance() {
+        let tolerance = Tolerance::default();
+        let comparison = compare(
+            &filled([100, 150, 200, 255]),
+            &filled([101, 149, 200, 255]),
+            tolerance.pixel_threshold,
+        );
+
+        assert_eq!(comparison.differing, 0);
+        assert!(comparison.passes(&tolerance));
+    }
+
+    #[test]
+    fn differing_pixels_are_marked() {
+        let tolerance = Tolerance::default();
+        let mut actual = filled([0, 0, 0, 255]);
+        actual.put_pixel(3, 4, Rgba([255, 255, 255, 255]));
+
+        let comparison = compare(&actual, &filled([0, 0, 0, 255]), tolerance.pixel_threshold);
+
+        assert_eq!(comparison.differing, 1);
+        assert_eq!(*comparison.diff.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+        assert_ne!(*comparison.diff.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert!(!comparison.passes(&tolerance));
+    }
+}
@@ -0,0 +1,284 @@
+use std::path::{Path, PathBuf};
+
+use failure::err_msg;
+use image::RgbaImage;
+
+use crate::game;
+use crate::game::scene::Scene;
+pub use crate::game::{Framing, SceneOptions};
+use crate::primitives::deferred::RenderPath;
+use crate::primitives::object_draw::ShadingModel;
+use crate::primitives::time::GameTime;
+use crate::render_gl;
+use crate::renderer::software::SoftwareRenderer;
+use crate::resources::Resources;
+
+// llvmpipe, Mesa's software rasterizer, doesn't go past 4.5, which is all the renderer uses anyway
+const GL_VERSION: (u8, u8) = (4, 5);
+
+// Headless frames are timed by a simulated clock ticking in microseconds
+const TIMER_FREQUENCY: u64 = 1_000_000;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Unknown option {}", flag)]
+    UnknownFlag { flag: String },
+    #[fail(display = "Option {} needs a value", flag)]
+    MissingValue { flag: String },
+    #[fail(display = "Invalid value \"{}\" for option {}", value, flag)]
+    InvalidValue { flag: String, value: String },
+}
+
+/// What renders the frames
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Backend {
+    /// OpenGL if a context can be created, the software renderer otherwise
+    Auto,
+    /// The full pipeline, through an OpenGL context
+    OpenGl,
+    /// The CPU rasterizer, which needs neither a GPU nor SDL, but leaves out shadows, SSAO and
+    /// post-processing
+    Software,
+}
+
+/// What to render without a display
+#[derive(Debug, Clone)]
+pub struct HeadlessOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Number of frames to render, each of which is written as a PNG
+    pub frames: u32,
+    /// Frames are this far apart in simulated time
+    pub frame_rate: u32,
+    /// Directory the frames are written into, as frame_0000.png and so on
+    pub output: PathBuf,
+    /// Frames are rendered at this multiple of the size and scaled back down to it
+    pub supersampling: u32,
+    pub backend: Backend,
+    pub scene: SceneOptions,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        HeadlessOptions {
+            width: 1280,
+            height: 720,
+            frames: 1,
+            frame_rate: 60,
+            output: PathBuf::from("frames"),
+            supersampling: 1,
+            backend: Backend::Auto,
+            scene: SceneOptions::default(),
+        }
+    }
+}
+
+impl HeadlessOptions {
+    /// Parses `--size WIDTHxHEIGHT`, `--frames N`, `--frame-rate N`, `--output DIR`,
+    /// `--supersampling N`, `--renderer auto|gl|software`, `--seed N`, `--render-path forward|deferred`,
+    /// `--shading classic|pbr` and `--post-process on|off`, anything left out keeps its default
+    pub fn from_args(args: &[String]) -> Result<HeadlessOptions, Error> {
+        let mut options = HeadlessOptions::default();
+        let mut args = args.iter();
+
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| Error::MissingValue { flag: flag.clone() })?;
+            let invalid = || Error::InvalidValue {
+                flag: flag.clone(),
+                value: value.clone(),
+            };
+
+            match flag.as_str() {
+                "--size" => {
+                    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+                    options.width = width.parse().map_err(|_| invalid())?;
+                    options.height = height.parse().map_err(|_| invalid())?;
+                }
+                "--frames" => options.frames = value.parse().map_err(|_| invalid())?,
+                "--frame-rate" => options.frame_rate = value.parse().map_err(|_| invalid())?,
+                "--output" => options.output = PathBuf::from(value),
+                "--supersampling" => options.supersampling = value.parse().map_err(|_| invalid())?,
+                "--renderer" => {
+                    options.backend = match value.as_str() {
+                        "auto" => Backend::Auto,
+                        "gl" => Backend::OpenGl,
+                        "software" => Backend::Software,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--seed" => options.scene.seed = Some(value.parse().map_err(|_| invalid())?),
+                "--render-path" => {
+                    options.scene.render_path = match value.as_str() {
+                        "forward" => RenderPath::Forward,
+                        "deferred" => RenderPath::Deferred,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--shading" => {
+                    options.scene.shading = match value.as_str() {
+                        "classic" => ShadingModel::Classic,
+                        "pbr" => ShadingModel::Pbr,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--post-process" => {
+                    options.scene.post_process = match value.as_str() {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => return Err(Error::UnknownFlag { flag: flag.clone() }),
+            }
+
+            if options.width == 0 || options.height == 0 || options.frame_rate == 0 || options.supersampling == 0 {
+                return Err(invalid());
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// The backend the frames were rendered with
+#[derive(Debug)]
+pub enum Rendered {
+    OpenGl,
+    Software,
+    /// [`Backend::Auto`] fell back to the software renderer, as there was no OpenGL context
+    SoftwareFallback(failure::Error),
+}
+
+/// Renders the game's scene without a display, into PNGs.
+///
+/// The OpenGL backend uses SDL's offscreen video driver, which creates an EGL pbuffer context. It
+/// needs libEGL and a driver for OpenGL 4.5, such as Mesa's llvmpipe on machines without a GPU,
+/// as there's no surfaceless EGL or OSMesa context to fall back to. Where that's missing, the
+/// default [`Backend::Auto`] renders with the software renderer instead.
+pub fn run(options: &HeadlessOptions) -> Result<Rendered, failure::Error> {
+    let res = Resources::from_relative_exe_path(Path::new("assets"))?;
+
+    std::fs::create_dir_all(&options.output)?;
+
+    render(res, options, |frame, image| {
+        let path = options.output.join(format!("frame_{:04}.png", frame));
+        image.save(&path)?;
+
+        Ok(())
+    })
+}
+
+/// Like [`run`], but hands every frame to `on_frame` instead of writing it, along with its index.
+/// `options.output` is ignored. SDL can only be used by one thread at a time, so calls on different
+/// threads can't overlap when rendering through OpenGL.
+pub fn render(
+    res: Resources,
+    options: &HeadlessOptions,
+    on_frame: impl FnMut(u32, RgbaImage) -> Result<(), failure::Error>,
+) -> Result<Rendered, failure::Error> {
+    match options.backend {
+        Backend::Auto => match OffscreenContext::new(options) {
+            Ok(context) => render_opengl(context, res, options, on_frame).map(|()| Rendered::OpenGl),
+            Err(e) => render_software(&res, options, on_frame).map(|()| Rendered::SoftwareFallback(e)),
+        },
+        Backend::OpenGl => render_opengl(OffscreenContext::new(options)?, res, options, on_frame).map(|()| Rendered::OpenGl),
+        Backend::Software => render_software(&res, options, on_frame).map(|()| Rendered::Software),
+    }
+}
+
+// An OpenGL context without a display, through SDL's offscreen video driver. Fields are dropped
+// in order, the context before its window and both before SDL.
+struct OffscreenContext {
+    gl: gl::Gl,
+    _gl_context: sdl2::video::GLContext,
+    _window: sdl2::video::Window,
+    video_subsystem: sdl2::VideoSubsystem,
+    _sdl: sdl2::Sdl,
+}
+
+impl OffscreenContext {
+    fn new(options: &HeadlessOptions) -> Result<OffscreenContext, failure::Error> {
+        sdl2::hint::set("SDL_VIDEODRIVER", "offscreen");
+        let sdl = sdl2::init().map_err(err_msg)?;
+        let video_subsystem = sdl.video().map_err(err_msg)?;
+
+        let gl_attr = video_subsystem.gl_attr();
+        gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
+        gl_attr.set_context_version(GL_VERSION.0, GL_VERSION.1);
+
+        let window = video_subsystem
+            .window("Game", options.width, options.height)
+            .opengl()
+            .hidden()
+            .build()
+            .map_err(err_msg)?;
+
+        let gl_context = window.gl_create_context().map_err(err_msg)?;
+        let gl = gl::Gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const std::os::raw::c_void);
+
+        Ok(OffscreenContext {
+            gl,
+            _gl_context: gl_context,
+            _window: window,
+            video_subsystem,
+            _sdl: sdl,
+        })
+    }
+}
+
+fn render_opengl(
+    context: OffscreenContext,
+    res: Resources,
+    options: &HeadlessOptions,
+    mut on_frame: impl FnMut(u32, RgbaImage) -> Result<(), failure::Error>,
+) -> Result<(), failure::Error> {
+    let gl = context.gl.clone();
+    crate::init_gl_state(&gl);
+
+    let size = (options.width, options.height);
+    render_gl::Viewport::for_window(options.width as i32, options.height as i32).set_used(&gl);
+
+    let mut game = game::Game::new(
+        res,
+        &gl,
+        0,
+        TIMER_FREQUENCY,
+        crate::TICK_LENGTH_US,
+        context.video_subsystem.clone(),
+        size,
+        &options.scene,
+    )?;
+
+    let frame_length = TIMER_FREQUENCY / options.frame_rate as u64;
+    for frame in 0..options.frames {
+        game.process(frame as u64 * frame_length);
+        on_frame(frame, game.capture(options.supersampling)?)?;
+    }
+
+    Ok(())
+}
+
+// Moves the scene along the same simulated clock as the game does, so both backends render the
+// same frames
+fn render_software(
+    res: &Resources,
+    options: &HeadlessOptions,
+    mut on_frame: impl FnMut(u32, RgbaImage) -> Result<(), failure::Error>,
+) -> Result<(), failure::Error> {
+    let (mut scene, assets) = Scene::new(&options.scene);
+    let settings = game::render_settings(res, &options.scene)?;
+    let supersampled = (options.width * options.supersampling, options.height * options.supersampling);
+    let mut renderer = SoftwareRenderer::new(assets, settings, supersampled);
+    let mut game_time = GameTime::new(TIMER_FREQUENCY, crate::TICK_LENGTH_US, 0);
+
+    let frame_length = TIMER_FREQUENCY / options.frame_rate as u64;
+    for frame in 0..options.frames {
+        let ticks = game_time.update_ticks(frame as u64 * frame_length);
+        scene.update(ticks as f32 * game_time.tick_second_ratio);
+        scene.draw(&mut renderer);
+
+        on_frame(frame, image::imageops::thumbnail(renderer.image(), options.width, options.height))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,879 @@
+//! The `.cgi` mesh file format. All values are little-endian:
+//!
+//! | Field           | Type                           |
+//! |-----------------|--------------------------------|
+//! | Magic           | `b"CGIMESH\0"`                 |
+//! | Version         | u16                            |
+//! | Attribute count | u16                            |
+//! | Attributes      | (u8 attribute, u8 element type) for each attribute |
+//! | Vertex count    | u32                            |
+//! | Index type      | u8 (0 - none, 1 - u16, 2 - u32)|
+//! | Index count     | u32                            |
+//! | Bounding box    | 6 x f32 (min xyz, max xyz)     |
+//! | Checksum        | u32, CRC-32 of everything that follows |
+//! | Vertex data     | Interleaved, in attribute order |
+//! | Index data      |                                |
+//!
+//! Older files have no header at all, and are just a stream of f64 position + normal records.
+//! Those can only be read explicitly through [`CgiMesh::read_legacy`].
+
+use std::collections::HashMap;
+
+use nalgebra::{Vector2, Vector3, Vector4};
+
+use crate::primitives::light::Color;
+use crate::primitives::spatial::Location;
+use crate::primitives::triangle::{Triangle, Vertex, VertexData};
+
+pub const MAGIC: &[u8; 8] = b"CGIMESH\0";
+pub const VERSION: u16 = 1;
+
+// Legacy files are made of records of 6 f64s, a position followed by a normal
+const LEGACY_RECORD_SIZE: usize = 6 * 8;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Not a .cgi mesh file (bad magic), legacy files must be read explicitly")]
+    BadMagic,
+    #[fail(display = "Unsupported .cgi version {}, expected {}", version, expected)]
+    UnsupportedVersion { version: u16, expected: u16 },
+    #[fail(display = "Unknown attribute id {}", id)]
+    UnknownAttribute { id: u8 },
+    #[fail(display = "Unknown element type id {}", id)]
+    UnknownElementType { id: u8 },
+    #[fail(display = "Unknown index type id {}", id)]
+    UnknownIndexType { id: u8 },
+    #[fail(display = "Attribute {:?} can't be stored as {:?}", attribute, element_type)]
+    UnsupportedElementType { attribute: Attribute, element_type: ElementType },
+    #[fail(display = "Attribute {:?} is declared more than once", attribute)]
+    DuplicateAttribute { attribute: Attribute },
+    #[fail(display = "Mesh has no position attribute")]
+    MissingPosition,
+    #[fail(display = "Attribute {:?} has {} values, expected {}", attribute, actual, expected)]
+    AttributeLengthMismatch {
+        attribute: Attribute,
+        actual: usize,
+        expected: usize,
+    },
+    #[fail(display = "Index count {} is not a multiple of 3, indices have to form triangles", count)]
+    IndexCount { count: usize },
+    #[fail(
+        display = "Vertex count {} of an unindexed mesh is not a multiple of 3, verticies have to form triangles",
+        count
+    )]
+    VertexCount { count: usize },
+    #[fail(display = "Index {} is out of range for {} verticies", index, vertex_count)]
+    IndexOutOfRange { index: u32, vertex_count: usize },
+    #[fail(display = "File is truncated, needed {} bytes at offset {}", needed, offset)]
+    Truncated { needed: usize, offset: usize },
+    #[fail(display = "File has {} unexpected trailing bytes", count)]
+    TrailingBytes { count: usize },
+    #[fail(display = "Checksum mismatch, header says {:#010x} but data is {:#010x}", expected, actual)]
+    ChecksumMismatch { expected: u32, actual: u32 },
+    #[fail(display = "Legacy file size {} is not a multiple of {}", size, record_size)]
+    LegacySize { size: usize, record_size: usize },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Attribute {
+    Position,
+    Normal,
+    Color,
+    Uv,
+    Tangent,
+}
+
+impl Attribute {
+    fn from_id(id: u8) -> Result<Attribute, Error> {
+        match id {
+            0 => Ok(Attribute::Position),
+            1 => Ok(Attribute::Normal),
+            2 => Ok(Attribute::Color),
+            3 => Ok(Attribute::Uv),
+            4 => Ok(Attribute::Tangent),
+            _ => Err(Error::UnknownAttribute { id }),
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            Attribute::Position => 0,
+            Attribute::Normal => 1,
+            Attribute::Color => 2,
+            Attribute::Uv => 3,
+            Attribute::Tangent => 4,
+        }
+    }
+
+    pub fn components(self) -> usize {
+        match self {
+            Attribute::Position | Attribute::Normal => 3,
+            Attribute::Uv => 2,
+            Attribute::Color | Attribute::Tangent => 4,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ElementType {
+    F32,
+    F64,
+    /// Four unsigned normalized components packed into a u32, like `UNSIGNED_INT_2_10_10_10_REV`.
+    /// Only colors can be packed.
+    Packed,
+}
+
+impl ElementType {
+    fn from_id(id: u8) -> Result<ElementType, Error> {
+        match id {
+            0 => Ok(ElementType::F32),
+            1 => Ok(ElementType::F64),
+            2 => Ok(ElementType::Packed),
+            _ => Err(Error::UnknownElementType { id }),
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            ElementType::F32 => 0,
+            ElementType::F64 => 1,
+            ElementType::Packed => 2,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AttributeLayout {
+    pub attribute: Attribute,
+    pub element_type: ElementType,
+}
+
+impl AttributeLayout {
+    pub fn new(attribute: Attribute, element_type: ElementType) -> Result<AttributeLayout, Error> {
+        if element_type == ElementType::Packed && attribute != Attribute::Color {
+            return Err(Error::UnsupportedElementType { attribute, element_type });
+        }
+
+        Ok(AttributeLayout { attribute, element_type })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IndexType {
+    U16,
+    U32,
+}
+
+impl IndexType {
+    /// Size of a single index in bytes
+    pub fn size(self) -> usize {
+        match self {
+            IndexType::U16 => 2,
+            IndexType::U32 => 4,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl BoundingBox {
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vector3<f32>>) -> BoundingBox {
+        let mut points = points.into_iter();
+
+        let first = match points.next() {
+            Some(first) => *first,
+            None => {
+                return BoundingBox {
+                    min: Vector3::zeros(),
+                    max: Vector3::zeros(),
+                }
+            }
+        };
+
+        points.fold(BoundingBox { min: first, max: first }, |bounds, point| BoundingBox {
+            min: bounds.min.inf(point),
+            max: bounds.max.sup(point),
+        })
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+}
+
+/// A mesh as stored in a `.cgi` file. Optional attributes are empty when the mesh doesn't have
+/// them, `layout` describes which attributes are written and how.
+#[derive(Debug, Clone)]
+pub struct CgiMesh {
+    pub layout: Vec<AttributeLayout>,
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub colors: Vec<Vector4<f32>>,
+    pub uvs: Vec<Vector2<f32>>,
+    pub tangents: Vec<Vector4<f32>>,
+    /// Triangles as triplets of indices into the verticies. Without indices, every 3 consecutive
+    /// verticies form a triangle.
+    pub indices: Option<Vec<u32>>,
+}
+
+impl CgiMesh {
+    pub fn bounds(&self) -> BoundingBox {
+        BoundingBox::from_points(&self.positions)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        match &self.indices {
+            Some(indices) => indices.len() / 3,
+            None => self.positions.len() / 3,
+        }
+    }
+
+    /// The smallest index type able to address all verticies
+    pub fn index_type(&self) -> Option<IndexType> {
+        self.indices.as_ref().map(|_| {
+            if self.vertex_count() <= u16::MAX as usize + 1 {
+                IndexType::U16
+            } else {
+                IndexType::U32
+            }
+        })
+    }
+
+    /// Reads a mesh in the current format
+    pub fn read(bytes: &[u8]) -> Result<CgiMesh, Error> {
+        let mut reader = ByteReader::new(bytes);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion {
+                version,
+                expected: VERSION,
+            });
+        }
+
+        let attribute_count = reader.u16()?;
+        let layout = (0..attribute_count)
+            .map(|_| {
+                let attribute = Attribute::from_id(reader.u8()?)?;
+                let element_type = ElementType::from_id(reader.u8()?)?;
+                AttributeLayout::new(attribute, element_type)
+            })
+            .collect::<Result<Vec<AttributeLayout>, Error>>()?;
+        validate_layout(&layout)?;
+
+        let vertex_count = reader.u32()? as usize;
+        let index_type = match reader.u8()? {
+            0 => None,
+            1 => Some(IndexType::U16),
+            2 => Some(IndexType::U32),
+            id => return Err(Error::UnknownIndexType { id }),
+        };
+        let index_count = reader.u32()? as usize;
+
+        // The bounding box is only there for tools that want to peek at the header, it's always
+        // recalculated from the positions
+        for _ in 0..6 {
+            reader.f32()?;
+        }
+
+        let checksum = reader.u32()?;
+        let actual = crc32(reader.remaining());
+        if checksum != actual {
+            return Err(Error::ChecksumMismatch {
+                expected: checksum,
+                actual,
+            });
+        }
+
+        let mut mesh = CgiMesh::new(layout.clone());
+        for _ in 0..vertex_count {
+            for attribute_layout in &layout {
+                let values = reader.attribute(attribute_layout)?;
+                mesh.push_attribute(attribute_layout.attribute, &values);
+            }
+        }
+
+        mesh.indices = match index_type {
+            Some(IndexType::U16) => Some(
+                (0..index_count)
+                    .map(|_| reader.u16().map(u32::from))
+                    .collect::<Result<Vec<u32>, Error>>()?,
+            ),
+            Some(IndexType::U32) => Some((0..index_count).map(|_| reader.u32()).collect::<Result<Vec<u32>, Error>>()?),
+            None => None,
+        };
+
+        if !reader.remaining().is_empty() {
+            return Err(Error::TrailingBytes {
+                count: reader.remaining().len(),
+            });
+        }
+
+        mesh.validate()?;
+
+        Ok(mesh)
+    }
+
+    /// Reads a legacy headerless file, which is just f64 position + normal records
+    pub fn read_legacy(bytes: &[u8]) -> Result<CgiMesh, Error> {
+        if !bytes.len().is_multiple_of(LEGACY_RECORD_SIZE) {
+            return Err(Error::LegacySize {
+                size: bytes.len(),
+                record_size: LEGACY_RECORD_SIZE,
+            });
+        }
+
+        let layout = vec![
+            AttributeLayout::new(Attribute::Position, ElementType::F64)?,
+            AttributeLayout::new(Attribute::Normal, ElementType::F64)?,
+        ];
+
+        let mut mesh = CgiMesh::new(layout);
+        let mut reader = ByteReader::new(bytes);
+        while !reader.remaining().is_empty() {
+            let record = (0..6)
+                .map(|_| reader.f64().map(|value| value as f32))
+                .collect::<Result<Vec<f32>, Error>>()?;
+            mesh.positions.push(Vector3::new(record[0], record[1], record[2]));
+            mesh.normals.push(Vector3::new(record[3], record[4], record[5]));
+        }
+
+        mesh.validate()?;
+
+        Ok(mesh)
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>, Error> {
+        self.validate()?;
+
+        let mut body = vec![];
+        for i in 0..self.vertex_count() {
+            for attribute_layout in &self.layout {
+                write_attribute(&mut body, attribute_layout, &self.attribute_values(attribute_layout.attribute, i));
+            }
+        }
+
+        let index_type = self.index_type();
+        if let Some(indices) = &self.indices {
+            for index in indices {
+                match index_type {
+                    Some(IndexType::U16) => body.extend_from_slice(&(*index as u16).to_le_bytes()),
+                    _ => body.extend_from_slice(&index.to_le_bytes()),
+                }
+            }
+        }
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.layout.len() as u16).to_le_bytes());
+        for attribute_layout in &self.layout {
+            bytes.push(attribute_layout.attribute.id());
+            bytes.push(attribute_layout.element_type.id());
+        }
+        bytes.extend_from_slice(&(self.vertex_count() as u32).to_le_bytes());
+        bytes.push(match index_type {
+            None => 0,
+            Some(IndexType::U16) => 1,
+            Some(IndexType::U32) => 2,
+        });
+        bytes.extend_from_slice(&(self.indices.as_ref().map_or(0, Vec::len) as u32).to_le_bytes());
+        let bounds = self.bounds();
+        for value in bounds.min.iter().chain(bounds.max.iter()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+
+        Ok(bytes)
+    }
+
+    /// Expands the mesh into triangle soup for drawing. Mesh colors take precedence over `color`,
+    /// meshes without normals get flat face normals and meshes without UVs get zero UVs.
+    pub fn verticies(&self, color: Color) -> Vec<VertexData> {
+        self.triangle_order()
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                let vertex = |i: usize| Vertex {
+                    pos: Location::new(self.positions[i].x, self.positions[i].y, self.positions[i].z),
+                    clr: self.colors.get(i).map_or(color, |c| Color::new_with_alpha(c.x, c.y, c.z, c.w)),
+                    uv: self.uvs.get(i).copied().unwrap_or_else(Vector2::zeros),
+                };
+
+                if self.normals.is_empty() {
+                    Triangle::new([vertex(triangle[0]), vertex(triangle[1]), vertex(triangle[2])]).vertices()
+                } else {
+                    triangle.iter().map(|i| VertexData::new(&vertex(*i), self.normals[*i])).collect()
+                }
+            })
+            .collect()
+    }
+
+    pub fn new(layout: Vec<AttributeLayout>) -> CgiMesh {
+        CgiMesh {
+            layout,
+            positions: vec![],
+            normals: vec![],
+            colors: vec![],
+            uvs: vec![],
+            tangents: vec![],
+            indices: None,
+        }
+    }
+
+    /// Declares every attribute the mesh has values for in the layout, using f32 elements (and
+    /// packed colors) for attributes which aren't declared yet
+    pub fn update_layout(&mut self) {
+        let present = [
+            (Attribute::Position, !self.positions.is_empty(), ElementType::F32),
+            (Attribute::Normal, !self.normals.is_empty(), ElementType::F32),
+            (Attribute::Color, !self.colors.is_empty(), ElementType::Packed),
+            (Attribute::Uv, !self.uvs.is_empty(), ElementType::F32),
+            (Attribute::Tangent, !self.tangents.is_empty(), ElementType::F32),
+        ];
+
+        for (attribute, is_present, element_type) in present.iter() {
+            let declared = self.layout.iter().any(|layout| layout.attribute == *attribute);
+
+            if *is_present && !declared {
+                self.layout.push(AttributeLayout {
+                    attribute: *attribute,
+                    element_type: *element_type,
+                });
+            } else if !*is_present && declared && *attribute != Attribute::Position {
+                self.layout.retain(|layout| layout.attribute != *attribute);
+            }
+        }
+    }
+
+    /// Replaces the normals with ones calculated from the triangles. Verticies shared between
+    /// triangles of an indexed mesh get smooth, area weighted normals, triangle soup gets flat
+    /// normals.
+    pub fn recompute_normals(&mut self) {
+        let mut normals = vec![Vector3::zeros(); self.vertex_count()];
+
+        for triangle in self.triangle_order().chunks_exact(3) {
+            let a = self.positions[triangle[0]];
+            let b = self.positions[triangle[1]];
+            let c = self.positions[triangle[2]];
+
+            // The length of the cross product is twice the area of the triangle
+            let normal = (b - a).cross(&(c - a));
+            triangle.iter().for_each(|i| normals[*i] += normal);
+        }
+
+        self.normals = normals
+            .iter()
+            .map(|normal| normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::z))
+            .collect();
+        self.update_layout();
+    }
+
+    /// Merges identical verticies, turning the mesh into an indexed one. Only attributes declared
+    /// in the layout are kept.
+    pub fn deduplicate(&mut self) {
+        let mut deduplicated = CgiMesh::new(self.layout.clone());
+        let mut unique: HashMap<Vec<u32>, u32> = HashMap::new();
+        let mut indices = vec![];
+
+        for i in self.triangle_order() {
+            let values: Vec<Vec<f32>> = self
+                .layout
+                .iter()
+                .map(|layout| self.attribute_values(layout.attribute, i))
+                .collect();
+            let key = values.iter().flatten().map(|value| value.to_bits()).collect();
+
+            let index = *unique.entry(key).or_insert_with(|| {
+                self.layout
+                    .iter()
+                    .zip(values.iter())
+                    .for_each(|(layout, values)| deduplicated.push_attribute(layout.attribute, values));
+                (deduplicated.vertex_count() - 1) as u32
+            });
+
+            indices.push(index);
+        }
+
+        deduplicated.indices = Some(indices);
+        *self = deduplicated;
+    }
+
+    /// Centers the mesh around the origin and scales it so that its largest dimension is 1
+    pub fn normalize_size(&mut self) {
+        let bounds = self.bounds();
+        let center = bounds.center();
+        let largest = bounds.size().max();
+        let scale = if largest > 0.0 { 1.0 / largest } else { 1.0 };
+
+        self.positions
+            .iter_mut()
+            .for_each(|position| *position = (*position - center) * scale);
+    }
+
+    /// The vertex index of every triangle corner, in order
+    fn triangle_order(&self) -> Vec<usize> {
+        match &self.indices {
+            Some(indices) => indices.iter().map(|index| *index as usize).collect(),
+            None => (0..self.vertex_count()).collect(),
+        }
+    }
+
+    /// Makes sure the layout is sane, every attribute in the layout has a value for every vertex,
+    /// and that the indices, or the verticies themselves without indices, form triangles of
+    /// verticies in range
+    fn validate(&self) -> Result<(), Error> {
+        validate_layout(&self.layout)?;
+
+        let expected = self.vertex_count();
+        for attribute_layout in &self.layout {
+            let actual = match attribute_layout.attribute {
+                Attribute::Position => self.positions.len(),
+                Attribute::Normal => self.normals.len(),
+                Attribute::Color => self.colors.len(),
+                Attribute::Uv => self.uvs.len(),
+                Attribute::Tangent => self.tangents.len(),
+            };
+
+            if actual != expected {
+                return Err(Error::AttributeLengthMismatch {
+                    attribute: attribute_layout.attribute,
+                    actual,
+                    expected,
+                });
+            }
+        }
+
+        match &self.indices {
+            Some(indices) if !indices.len().is_multiple_of(3) => return Err(Error::IndexCount { count: indices.len() }),
+            None if !expected.is_multiple_of(3) => return Err(Error::VertexCount { count: expected }),
+            _ => {}
+        }
+
+        if let Some(index) = self.indices.iter().flatten().find(|index| **index as usize >= expected) {
+            return Err(Error::IndexOutOfRange {
+                index: *index,
+                vertex_count: expected,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn push_attribute(&mut self, attribute: Attribute, values: &[f32]) {
+        match attribute {
+            Attribute::Position => self.positions.push(Vector3::new(values[0], values[1], values[2])),
+            Attribute::Normal => self.normals.push(Vector3::new(values[0], values[1], values[2])),
+            Attribute::Color => self.colors.push(Vector4::new(values[0], values[1], values[2], values[3])),
+            Attribute::Uv => self.uvs.push(Vector2::new(values[0], values[1])),
+            Attribute::Tangent => self.tangents.push(Vector4::new(values[0], values[1], values[2], values[3])),
+        }
+    }
+
+    fn attribute_values(&self, attribute: Attribute, i: usize) -> Vec<f32> {
+        match attribute {
+            Attribute::Position => self.positions[i].as_slice().to_vec(),
+            Attribute::Normal => self.normals[i].as_slice().to_vec(),
+            Attribute::Color => self.colors[i].as_slice().to_vec(),
+            Attribute::Uv => self.uvs[i].as_slice().to_vec(),
+            Attribute::Tangent => self.tangents[i].as_slice().to_vec(),
+        }
+    }
+}
+
+fn validate_layout(layout: &[AttributeLayout]) -> Result<(), Error> {
+    for (i, attribute_layout) in layout.iter().enumerate() {
+        if layout[..i].iter().any(|other| other.attribute == attribute_layout.attribute) {
+            return Err(Error::DuplicateAttribute {
+                attribute: attribute_layout.attribute,
+            });
+        }
+    }
+
+    if !layout
+        .iter()
+        .any(|attribute_layout| attribute_layout.attribute == Attribute::Position)
+    {
+        return Err(Error::MissingPosition);
+    }
+
+    Ok(())
+}
+
+fn write_attribute(bytes: &mut Vec<u8>, layout: &AttributeLayout, values: &[f32]) {
+    match layout.element_type {
+        ElementType::F32 => values.iter().for_each(|value| bytes.extend_from_slice(&value.to_le_bytes())),
+        ElementType::F64 => values
+            .iter()
+            .for_each(|value| bytes.extend_from_slice(&(*value as f64).to_le_bytes())),
+        ElementType::Packed => bytes.extend_from_slice(&pack_2_10_10_10(values).to_le_bytes()),
+    }
+}
+
+fn pack_2_10_10_10(values: &[f32]) -> u32 {
+    let unorm = |value: f32, bits: u32| {
+        let max = ((1u32 << bits) - 1) as f32;
+        (value.clamp(0.0, 1.0) * max).round() as u32
+    };
+
+    unorm(values[0], 10) | unorm(values[1], 10) << 10 | unorm(values[2], 10) << 20 | unorm(values[3], 2) << 30
+}
+
+fn unpack_2_10_10_10(packed: u32) -> Vec<f32> {
+    let unorm = |shift: u32, bits: u32| {
+        let max = (1u32 << bits) - 1;
+        ((packed >> shift) & max) as f32 / max as f32
+    };
+
+    vec![unorm(0, 10), unorm(10, 10), unorm(20, 10), unorm(30, 2)]
+}
+
+/// CRC-32 (IEEE 802.3), the same checksum used by zip and png
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(
+            crc ^ *byte as u32,
+            |crc, _| {
+                if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                }
+            },
+        )
+    })
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes, offset: 0 }
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+
+    fn take(&mut self, needed: usize) -> Result<&'a [u8], Error> {
+        if self.remaining().len() < needed {
+            return Err(Error::Truncated {
+                needed,
+                offset: self.offset,
+            });
+        }
+
+        let taken = &self.bytes[self.offset..self.offset + needed];
+        self.offset += needed;
+
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, Error> {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(raw))
+    }
+
+    fn attribute(&mut self, layout: &AttributeLayout) -> Result<Vec<f32>, Error> {
+        let components = layout.attribute.components();
+
+        match layout.element_type {
+            ElementType::F32 => (0..components).map(|_| self.f32()).collect(),
+            ElementType::F64 => (0..components).map(|_| self.f64().map(|value| value as f32)).collect(),
+            ElementType::Packed => Ok(unpack_2_10_10_10(self.u32()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Vector2, Vector3, Vector4};
+
+    use super::{crc32, Attribute, AttributeLayout, CgiMesh, ElementType, Error, MAGIC, VERSION};
+
+    // Offset of the checksum, after the magic, version, attribute count, 2 attributes, vertex
+    // count, index type, index count and bounding box
+    const CHECKSUM_OFFSET: usize = 8 + 2 + 2 + 2 * 2 + 4 + 1 + 4 + 6 * 4;
+
+    fn triangle() -> CgiMesh {
+        let mut mesh = CgiMesh::new(vec![]);
+        mesh.positions = vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        ];
+        mesh.uvs = vec![Vector2::new(0.0, 0.0), Vector2::new(1.0, 0.0), Vector2::new(0.0, 1.0)];
+        mesh.indices = Some(vec![0, 1, 2, 2, 1, 0]);
+        mesh.update_layout();
+        mesh
+    }
+
+    #[test]
+    fn written_meshes_read_back_the_same() {
+        let mut mesh = triangle();
+        mesh.colors = vec![Vector4::new(1.0, 0.0, 0.0, 1.0); 3];
+        mesh.update_layout();
+
+        let bytes = mesh.write().unwrap();
+        assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+
+        let read = CgiMesh::read(&bytes).unwrap();
+        assert_eq!(read.layout, mesh.layout);
+        assert_eq!(read.positions, mesh.positions);
+        assert_eq!(read.uvs, mesh.uvs);
+        // Packed colors are exact for 0 and 1
+        assert_eq!(read.colors, mesh.colors);
+        assert_eq!(read.indices, mesh.indices);
+    }
+
+    #[test]
+    fn corrupted_data_fails_the_checksum() {
+        let mut bytes = triangle().write().unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let expected = u32::from_le_bytes([
+            bytes[CHECKSUM_OFFSET],
+            bytes[CHECKSUM_OFFSET + 1],
+            bytes[CHECKSUM_OFFSET + 2],
+            bytes[CHECKSUM_OFFSET + 3],
+        ]);
+        let actual = crc32(&bytes[CHECKSUM_OFFSET + 4..]);
+        assert!(matches!(
+            CgiMesh::read(&bytes),
+            Err(Error::ChecksumMismatch { expected: e, actual: a }) if e == expected && a == actual
+        ));
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = triangle().write().unwrap();
+
+        // In the header
+        assert!(matches!(
+            CgiMesh::read(&bytes[..10]),
+            Err(Error::Truncated { needed: 1, offset: 10 })
+        ));
+
+        // In the data, which the checksum notices first unless it's fixed up
+        let mut bytes = bytes[..bytes.len() - 2].to_vec();
+        let checksum = crc32(&bytes[CHECKSUM_OFFSET + 4..]).to_le_bytes();
+        bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum);
+        assert!(matches!(CgiMesh::read(&bytes), Err(Error::Truncated { needed: 1, .. })));
+    }
+
+    #[test]
+    fn other_versions_and_files_are_rejected() {
+        let mut bytes = triangle().write().unwrap();
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            CgiMesh::read(&bytes),
+            Err(Error::UnsupportedVersion { version, expected: VERSION }) if version == VERSION + 1
+        ));
+
+        assert!(matches!(CgiMesh::read(b"ply\nformat ascii 1.0\n"), Err(Error::BadMagic)));
+    }
+
+    #[test]
+    fn indices_have_to_form_triangles() {
+        let mut mesh = triangle();
+        mesh.indices = Some(vec![0, 1, 2, 0]);
+        assert!(matches!(mesh.write(), Err(Error::IndexCount { count: 4 })));
+
+        // Written by hand, as the writer refuses to
+        let mut bytes = triangle().write().unwrap();
+        let index_count_offset = CHECKSUM_OFFSET - 6 * 4 - 4;
+        bytes[index_count_offset..index_count_offset + 4].copy_from_slice(&5u32.to_le_bytes());
+        let len = bytes.len();
+        bytes.truncate(len - 2);
+        let checksum = crc32(&bytes[CHECKSUM_OFFSET + 4..]).to_le_bytes();
+        bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum);
+        assert!(matches!(CgiMesh::read(&bytes), Err(Error::IndexCount { count: 5 })));
+    }
+
+    #[test]
+    fn unindexed_verticies_have_to_form_triangles() {
+        let mut mesh = triangle();
+        mesh.indices = None;
+        mesh.positions.push(Vector3::new(1.0, 1.0, 0.0));
+        mesh.uvs.push(Vector2::new(1.0, 1.0));
+        assert!(matches!(mesh.write(), Err(Error::VertexCount { count: 4 })));
+
+        // Written by hand, with a trailing vertex appended and the counts fixed up
+        let mut mesh = triangle();
+        mesh.indices = None;
+        let mut bytes = mesh.write().unwrap();
+        let vertex_count_offset = CHECKSUM_OFFSET - 6 * 4 - 4 - 1 - 4;
+        bytes[vertex_count_offset..vertex_count_offset + 4].copy_from_slice(&4u32.to_le_bytes());
+        let vertex = bytes[bytes.len() - 20..].to_vec();
+        bytes.extend_from_slice(&vertex);
+        let checksum = crc32(&bytes[CHECKSUM_OFFSET + 4..]).to_le_bytes();
+        bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum);
+        assert!(matches!(CgiMesh::read(&bytes), Err(Error::VertexCount { count: 4 })));
+
+        // Legacy files are triangle soup too
+        let record = [1.0f64, 2.0, 3.0, 0.0, 0.0, 1.0];
+        let bytes: Vec<u8> = record.iter().cycle().take(4 * 6).flat_map(|value| value.to_le_bytes()).collect();
+        assert!(matches!(CgiMesh::read_legacy(&bytes), Err(Error::VertexCount { count: 4 })));
+    }
+
+    #[test]
+    fn legacy_files_are_position_and_normal_records() {
+        let record = [1.0f64, 2.0, 3.0, 0.0, 0.0, 1.0];
+        let bytes: Vec<u8> = record.iter().cycle().take(3 * 6).flat_map(|value| value.to_le_bytes()).collect();
+
+        let mesh = CgiMesh::read_legacy(&bytes).unwrap();
+        assert_eq!(
+            mesh.layout,
+            vec![
+                AttributeLayout::new(Attribute::Position, ElementType::F64).unwrap(),
+                AttributeLayout::new(Attribute::Normal, ElementType::F64).unwrap(),
+            ]
+        );
+        assert_eq!(mesh.positions, vec![Vector3::new(1.0, 2.0, 3.0); 3]);
+        assert_eq!(mesh.normals, vec![Vector3::z(); 3]);
+
+        assert!(matches!(
+            CgiMesh::read_legacy(&bytes[..50]),
+            Err(Error::LegacySize { size: 50, record_size: 48 })
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use nalgebra::Vector4;
+use nalgebra::{Vector2, Vector4};
 
 use crate::primitives::light::Color;
 use crate::primitives::spatial::Location;
@@ -49,13 +49,18 @@ impl Cube {
 
         let triangles: Vec<Triangle> = positions
             .iter()
-            .map(|triangle_verticies| triangle_verticies.iter().map(|v| Location::from(*v) - offset))
+            .map(|triangle_verticies| {
+                triangle_verticies
+                    .iter()
+                    .map(move |v| (Location::from(*v) - offset, face_uv(triangle_verticies, *v)))
+            })
             .zip(colors)
             .map(|(p, _c)| {
                 Triangle::new(
-                    p.map(|v| Vertex {
+                    p.map(|(v, uv)| Vertex {
                         pos: v,
                         clr: Vector4::<f32>::new(v.x, v.y, v.z, 1.0).component_mul(&color_vec).as_slice().into(),
+                        uv,
                     })
                     .collect::<Vec<Vertex>>()
                     .try_into()
@@ -71,3 +76,17 @@ impl Cube {
         cube
     }
 }
+
+/// Every face gets the whole texture, mapped from the two coordinates which vary along the face
+fn face_uv(triangle: &[(f32, f32, f32); 3], vertex: (f32, f32, f32)) -> Vector2<f32> {
+    let flat_x = triangle.iter().all(|v| v.0 == triangle[0].0);
+    let flat_y = triangle.iter().all(|v| v.1 == triangle[0].1);
+
+    if flat_x {
+        Vector2::new(vertex.1, vertex.2)
+    } else if flat_y {
+        Vector2::new(vertex.0, vertex.2)
+    } else {
+        Vector2::new(vertex.0, vertex.1)
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use crate::models::obj::ObjMaterial;
+use crate::models::texture_registry::TextureHandle;
+use crate::primitives::light::consts::{BLACK, WHITE};
+use crate::primitives::light::Color;
+
+/// How the surface of an object reacts to light
+#[derive(Debug, Copy, Clone)]
+pub struct Material {
+    /// Multiplied with the vertex colors and the diffuse map
+    pub base_color: Color,
+    /// Fraction of the base color which is visible without any light reaching the surface
+    pub ambient: f32,
+    pub specular_strength: f32,
+    /// How wide the specular highlights spread, higher is rougher
+    pub specular_roughness: f32,
+    /// 0 for dielectrics, 1 for metals. Only used by PBR shading.
+    pub metallic: f32,
+    /// Microfacet roughness, from 0 (mirror) to 1. Only used by PBR shading.
+    pub roughness: f32,
+    /// Light given off by the surface itself, regardless of any lights
+    pub emissive: Color,
+    /// `None` for untextured materials
+    pub diffuse_map: Option<TextureHandle>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            base_color: WHITE,
+            ambient: 0.35,
+            specular_strength: 100.0,
+            specular_roughness: 0.9,
+            metallic: 0.0,
+            roughness: 0.5,
+            emissive: BLACK,
+            diffuse_map: None,
+        }
+    }
+}
+
+impl Material {
+    /// Converts an MTL material, with its diffuse map already loaded as `diffuse_map`. The diffuse
+    /// color becomes the base color, and the specular color scales the specular strength. The PBR
+    /// parameters come from the `Pm` and `Pr` extension, or are derived from `Ns` without it.
+    pub fn from_obj(material: &ObjMaterial, diffuse_map: Option<TextureHandle>) -> Material {
+        let default = Material::default();
+
+        let mut base_color = material.diffuse.unwrap_or(default.base_color);
+        base_color.a = material.opacity.unwrap_or(1.0);
+
+        Material {
+            base_color,
+            // Exporters fill Ka with anything from black to white, so it's not a useful ambient
+            ambient: default.ambient,
+            specular_strength: material.specular.map_or(default.specular_strength, |specular| {
+                default.specular_strength * specular.r.max(specular.g).max(specular.b)
+            }),
+            // The highlight falls off like exp(-64 (angle / roughness)^2), which matches a
+            // Blinn-Phong exponent of Ns for a roughness of 8 * sqrt(2 / (Ns + 2))
+            specular_roughness: material.shininess.map_or(default.specular_roughness, |shininess| {
+                8.0 * (2.0 / (shininess.max(0.0) + 2.0)).sqrt()
+            }),
+            metallic: material.metallic.unwrap_or(default.metallic),
+            // Beckmann roughness from the Blinn-Phong exponent, which is close enough to GGX's
+            roughness: material.roughness.unwrap_or_else(|| {
+                material
+                    .shininess
+                    .map_or(default.roughness, |shininess| (2.0 / (shininess.max(0.0) + 2.0)).sqrt())
+            }),
+            emissive: BLACK,
+            diffuse_map,
+        }
+    }
+
+    /// The material with its base color multiplied by `color`, like the per-object color of the
+    /// GL renderer multiplies it
+    pub fn tinted(&self, color: Color) -> Material {
+        let base = &self.base_color;
+
+        Material {
+            base_color: Color::new_with_alpha(base.r * color.r, base.g * color.g, base.b * color.b, base.a * color.a),
+            ..*self
+        }
+    }
+}
+
+/// A cheap reference to a material in a [`MaterialRegistry`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MaterialHandle(usize);
+
+/// Materials by name, which objects refer to by [`MaterialHandle`]
+pub struct MaterialRegistry {
+    materials: Vec<Material>,
+    handles: HashMap<String, MaterialHandle>,
+}
+
+impl MaterialRegistry {
+    pub fn new() -> Self {
+        let mut registry = MaterialRegistry {
+            materials: vec![],
+            handles: HashMap::new(),
+        };
+        registry.add("default", Material::default());
+
+        registry
+    }
+
+    /// The material called "default", for objects which don't care about their material
+    pub fn default_material(&self) -> MaterialHandle {
+        MaterialHandle(0)
+    }
+
+    /// Adds `material` as `name`, replacing any material which was added under that name before.
+    /// Objects referring to the replaced material get the new one.
+    pub fn add(&mut self, name: &str, material: Material) -> MaterialHandle {
+        if let Some(handle) = self.handles.get(name) {
+            self.materials[handle.0] = material;
+            return *handle;
+        }
+
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(material);
+        self.handles.insert(name.to_string(), handle);
+
+        handle
+    }
+
+    pub fn get(&self, handle: MaterialHandle) -> &Material {
+        &self.materials[handle.0]
+    }
+
+    pub fn find(&self, name: &str) -> Option<MaterialHandle> {
+        self.handles.get(name).copied()
+    }
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
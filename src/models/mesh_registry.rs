@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use crate::models::cgi_format::IndexType;
+use crate::primitives::triangle::VertexData;
+use crate::render_gl::buffer::{ArrayBuffer, ElementArrayBuffer};
+
+/// A cheap reference to a mesh loaded into a [`MeshRegistry`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MeshHandle(usize);
+
+pub struct Mesh {
+    /// Unique verticies, shared between triangles
+    pub verticies: Vec<VertexData>,
+    /// Triangles as triplets of indices into `verticies`
+    pub indices: Vec<u32>,
+    /// Index of the first vertex of this mesh in the registry's vertex buffer
+    pub base_vertex: usize,
+    /// Position of the first index of this mesh in the registry's index buffer
+    pub first_index: usize,
+}
+
+impl Mesh {
+    pub fn num_vertices(&self) -> usize {
+        self.verticies.len()
+    }
+
+    pub fn num_indices(&self) -> usize {
+        self.indices.len()
+    }
+}
+
+/// The part of the registry's buffers a mesh occupies, which is everything needed to draw it
+#[derive(Debug, Copy, Clone)]
+pub struct MeshRange {
+    pub first_index: usize,
+    pub num_indices: usize,
+    pub base_vertex: usize,
+    pub index_type: IndexType,
+}
+
+impl MeshRange {
+    /// The `type` argument of `glDrawElements*`
+    pub fn gl_index_type(&self) -> gl::types::GLenum {
+        match self.index_type {
+            IndexType::U16 => gl::UNSIGNED_SHORT,
+            IndexType::U32 => gl::UNSIGNED_INT,
+        }
+    }
+
+    /// The `indices` argument of `glDrawElements*`, a byte offset into the bound index buffer
+    pub fn gl_index_offset(&self) -> *const gl::types::GLvoid {
+        (self.first_index * self.index_type.size()) as *const gl::types::GLvoid
+    }
+}
+
+/// The registry's index buffer, its element type depends on the largest mesh
+pub enum IndexBuffer {
+    U16(ElementArrayBuffer<u16>),
+    U32(ElementArrayBuffer<u32>),
+}
+
+impl IndexBuffer {
+    pub fn bind(&self) {
+        match self {
+            IndexBuffer::U16(buffer) => buffer.bind(),
+            IndexBuffer::U32(buffer) => buffer.bind(),
+        }
+    }
+}
+
+// The registry's meshes on the GPU
+struct MeshBuffers {
+    vbo: ArrayBuffer<VertexData>,
+    ebo: IndexBuffer,
+}
+
+/// Loads every mesh only once, no matter how many objects use it, and packs all of them into a
+/// single vertex buffer and a single index buffer. Objects keep a [`MeshHandle`] which can be used
+/// to find the range of the buffers their mesh occupies.
+///
+/// Indices are relative to the mesh's first vertex and drawn with `glDrawElementsBaseVertex`, so
+/// 16-bit indices are used unless a single mesh has more verticies than those can address.
+///
+/// The meshes stay in memory after they're uploaded, so that they can be drawn without the GPU too.
+pub struct MeshRegistry {
+    buffers: Option<MeshBuffers>,
+    meshes: Vec<Mesh>,
+    handles: HashMap<String, MeshHandle>,
+    num_vertices: usize,
+    num_indices: usize,
+    index_type: IndexType,
+}
+
+impl MeshRegistry {
+    pub fn new() -> Self {
+        MeshRegistry {
+            buffers: None,
+            meshes: vec![],
+            handles: HashMap::new(),
+            num_vertices: 0,
+            num_indices: 0,
+            index_type: IndexType::U16,
+        }
+    }
+
+    /// Returns the handle of the mesh called `name`, calling `load` to create it only if it hasn't
+    /// been loaded before. `load` returns triangle soup, identical verticies are merged into an
+    /// indexed mesh. Newly loaded meshes only reach the GPU on the next [`Self::upload`]
+    pub fn load<F>(&mut self, name: &str, load: F) -> MeshHandle
+    where
+        F: FnOnce() -> Vec<VertexData>,
+    {
+        if let Some(handle) = self.handles.get(name) {
+            return *handle;
+        }
+
+        let (verticies, indices) = index_verticies(&load());
+        let handle = MeshHandle(self.meshes.len());
+
+        if verticies.len() > u16::MAX as usize + 1 {
+            self.index_type = IndexType::U32;
+        }
+
+        self.meshes.push(Mesh {
+            base_vertex: self.num_vertices,
+            first_index: self.num_indices,
+            verticies,
+            indices,
+        });
+        self.num_vertices += self.meshes[handle.0].num_vertices();
+        self.num_indices += self.meshes[handle.0].num_indices();
+        self.handles.insert(name.to_string(), handle);
+
+        handle
+    }
+
+    pub fn get(&self, handle: MeshHandle) -> &Mesh {
+        &self.meshes[handle.0]
+    }
+
+    pub fn range(&self, handle: MeshHandle) -> MeshRange {
+        let mesh = self.get(handle);
+
+        MeshRange {
+            first_index: mesh.first_index,
+            num_indices: mesh.num_indices(),
+            base_vertex: mesh.base_vertex,
+            index_type: self.index_type,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn find(&self, name: &str) -> Option<MeshHandle> {
+        self.handles.get(name).copied()
+    }
+
+    fn buffers(&self) -> &MeshBuffers {
+        self.buffers.as_ref().expect("Meshes have to be uploaded before drawing them")
+    }
+
+    /// Panics unless the meshes were uploaded
+    pub fn vbo(&self) -> &ArrayBuffer<VertexData> {
+        &self.buffers().vbo
+    }
+
+    /// Has to be bound while a vertex array object using this registry is bound, the binding is
+    /// part of the vertex array's state. Panics unless the meshes were uploaded.
+    pub fn ebo(&self) -> &IndexBuffer {
+        &self.buffers().ebo
+    }
+
+    /// Uploads all loaded meshes into the vertex and index buffers, one after the other. Has to
+    /// happen before any vertex array object binds the index buffer, as it's replaced every time.
+    pub fn upload(&mut self, gl: &gl::Gl) {
+        let verticies: Vec<VertexData> = self.meshes.iter().flat_map(|mesh| mesh.verticies.iter().copied()).collect();
+
+        let vbo = ArrayBuffer::new(gl);
+        vbo.bind();
+        vbo.static_draw_data(&verticies);
+        vbo.unbind();
+
+        let indices = self.meshes.iter().flat_map(|mesh| mesh.indices.iter().copied());
+
+        let ebo = match self.index_type {
+            IndexType::U16 => {
+                let ebo = ElementArrayBuffer::new(gl);
+                ebo.bind();
+                ebo.static_draw_data(&indices.map(|index| index as u16).collect::<Vec<u16>>());
+                ebo.unbind();
+                IndexBuffer::U16(ebo)
+            }
+            IndexType::U32 => {
+                let ebo = ElementArrayBuffer::new(gl);
+                ebo.bind();
+                ebo.static_draw_data(&indices.collect::<Vec<u32>>());
+                ebo.unbind();
+                IndexBuffer::U32(ebo)
+            }
+        };
+
+        self.buffers = Some(MeshBuffers { vbo, ebo });
+    }
+}
+
+impl Default for MeshRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Merges identical verticies of triangle soup, returning the unique verticies along with the
+/// index of every triangle corner
+fn index_verticies(soup: &[VertexData]) -> (Vec<VertexData>, Vec<u32>) {
+    let mut verticies = vec![];
+    let mut unique: HashMap<[u32; 12], u32> = HashMap::new();
+
+    let indices = soup
+        .iter()
+        .map(|vertex| {
+            *unique.entry(vertex_key(vertex)).or_insert_with(|| {
+                verticies.push(*vertex);
+                (verticies.len() - 1) as u32
+            })
+        })
+        .collect();
+
+    (verticies, indices)
+}
+
+// The bits of every attribute value, floats aren't Eq or Hash themselves
+fn vertex_key(vertex: &VertexData) -> [u32; 12] {
+    // VertexData is packed, so its fields are copied out rather than borrowed
+    let (pos, clr, norm, uv) = (vertex.pos, vertex.clr, vertex.norm, vertex.uv);
+
+    [
+        pos.d0,
+        pos.d1,
+        pos.d2,
+        clr.inner.x(),
+        clr.inner.y(),
+        clr.inner.z(),
+        clr.inner.w(),
+        norm.d0,
+        norm.d1,
+        norm.d2,
+        uv.d0,
+        uv.d1,
+    ]
+    .map(f32::to_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Vector2, Vector3};
+
+    use super::index_verticies;
+    use crate::primitives::light::{consts, Color};
+    use crate::primitives::spatial::Location;
+    use crate::primitives::triangle::{Vertex, VertexData};
+
+    fn vertex(x: f32, y: f32, color: Color) -> VertexData {
+        let vertex = Vertex {
+            pos: Location::new(x, y, 0.0),
+            clr: color,
+            uv: Vector2::new(x, y),
+        };
+        VertexData::new(&vertex, Vector3::z())
+    }
+
+    #[test]
+    fn identical_verticies_are_merged() {
+        let soup = [
+            vertex(0.0, 0.0, consts::WHITE),
+            vertex(1.0, 0.0, consts::WHITE),
+            vertex(0.0, 1.0, consts::WHITE),
+            vertex(0.0, 1.0, consts::WHITE),
+            vertex(1.0, 0.0, consts::WHITE),
+            vertex(1.0, 1.0, consts::WHITE),
+            // Same position, different color
+            vertex(0.0, 0.0, consts::RED),
+        ];
+
+        let (verticies, indices) = index_verticies(&soup);
+
+        assert_eq!(verticies.len(), 5);
+        assert_eq!(indices, vec![0, 1, 2, 2, 1, 3, 4]);
+    }
+}
@@ -1,3 +1,10 @@
+pub mod cgi_format;
 pub mod cube;
+pub mod material;
+pub mod mesh_registry;
+pub mod obj;
+pub mod ply;
+pub mod stl;
 pub mod suzanne;
+pub mod texture_registry;
 pub mod world_model;
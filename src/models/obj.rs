@@ -0,0 +1,591 @@
+use nalgebra::{Vector2, Vector3};
+
+use crate::models::cgi_format::CgiMesh;
+use crate::models::material::{Material, MaterialHandle, MaterialRegistry};
+use crate::models::mesh_registry::{MeshHandle, MeshRegistry};
+use crate::models::texture_registry;
+use crate::models::texture_registry::TextureRegistry;
+use crate::primitives::light::consts::WHITE;
+use crate::primitives::light::Color;
+use crate::primitives::spatial::Location;
+use crate::primitives::triangle::{Triangle, Vertex, VertexData};
+use crate::resources;
+use crate::resources::Resources;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Failed to load resource {}", name)]
+    ResourceLoad {
+        name: String,
+        #[cause]
+        inner: resources::Error,
+    },
+    #[fail(display = "Resource {} is not valid UTF-8", name)]
+    InvalidUtf8 { name: String },
+    #[fail(display = "Line {}: expected at least {} values after \"{}\"", line, expected, keyword)]
+    MissingValues { line: usize, keyword: String, expected: usize },
+    #[fail(display = "Line {}: failed to parse number \"{}\"", line, value)]
+    InvalidNumber { line: usize, value: String },
+    #[fail(display = "Line {}: invalid face vertex \"{}\"", line, value)]
+    InvalidFaceVertex { line: usize, value: String },
+    #[fail(display = "Line {}: index {} is out of range", line, index)]
+    IndexOutOfRange { line: usize, index: i64 },
+    #[fail(display = "Line {}: faces need at least 3 verticies, got {}", line, count)]
+    DegenerateFace { line: usize, count: usize },
+    #[fail(display = "Failed to load texture {}", name)]
+    Texture {
+        name: String,
+        #[cause]
+        inner: texture_registry::Error,
+    },
+}
+
+/// A single corner of a face. Indices are already resolved to be 0-based
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct FaceVertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+/// A run of triangles sharing the same object name, group name and material
+#[derive(Debug, Clone, Default)]
+pub struct ObjGroup {
+    pub object: Option<String>,
+    pub name: Option<String>,
+    pub material: Option<String>,
+    pub faces: Vec<[FaceVertex; 3]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub ambient: Option<Color>,
+    pub diffuse: Option<Color>,
+    pub specular: Option<Color>,
+    pub shininess: Option<f32>,
+    pub opacity: Option<f32>,
+    /// From the PBR extension
+    pub roughness: Option<f32>,
+    /// From the PBR extension
+    pub metallic: Option<f32>,
+    pub diffuse_map: Option<String>,
+}
+
+impl ObjMaterial {
+    fn new(name: String) -> Self {
+        ObjMaterial {
+            name,
+            ambient: None,
+            diffuse: None,
+            specular: None,
+            shininess: None,
+            opacity: None,
+            roughness: None,
+            metallic: None,
+            diffuse_map: None,
+        }
+    }
+}
+
+/// A Wavefront OBJ model. Polygons of any arity are fan-triangulated while parsing, so every face
+/// is a triangle.
+#[derive(Debug, Clone, Default)]
+pub struct ObjModel {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub uvs: Vec<Vector2<f32>>,
+    pub groups: Vec<ObjGroup>,
+    pub material_libs: Vec<String>,
+    pub materials: Vec<ObjMaterial>,
+}
+
+impl ObjModel {
+    /// Loads the OBJ resource `name`, along with the materials of every MTL library it refers to.
+    /// Libraries are looked up relative to the directory of the OBJ resource.
+    pub fn from_res(res: &Resources, name: &str) -> Result<ObjModel, Error> {
+        let mut model = Self::parse(&load_string(res, name)?)?;
+        let directory = resource_directory(name);
+
+        for lib in model.material_libs.clone() {
+            let lib_name = format!("{}{}", directory, lib);
+            model.materials.extend(parse_mtl(&load_string(res, &lib_name)?)?);
+        }
+
+        Ok(model)
+    }
+
+    pub fn parse(source: &str) -> Result<ObjModel, Error> {
+        let mut model = ObjModel {
+            groups: vec![ObjGroup::default()],
+            ..ObjModel::default()
+        };
+
+        for (line, keyword, args) in statements(source) {
+            match keyword {
+                "v" => model.positions.push(parse_vector3(line, keyword, &args)?),
+                "vn" => model.normals.push(parse_vector3(line, keyword, &args)?),
+                "vt" => {
+                    // The v coordinate is optional and defaults to 0
+                    let values = parse_floats(line, keyword, &args, 1)?;
+                    model.uvs.push(Vector2::new(values[0], values.get(1).copied().unwrap_or(0.0)));
+                }
+                "f" => model.add_face(line, &args)?,
+                "o" => {
+                    let group = model.start_group();
+                    group.object = Some(args.join(" "));
+                    group.name = None;
+                }
+                "g" => model.start_group().name = Some(args.join(" ")),
+                "usemtl" => model.start_group().material = Some(args.join(" ")),
+                "mtllib" => model.material_libs.extend(args.iter().map(|lib| lib.to_string())),
+                // Smoothing groups, lines, points, free-form geometry etc. are not supported
+                _ => {}
+            }
+        }
+
+        model.groups.retain(|group| !group.faces.is_empty());
+
+        Ok(model)
+    }
+
+    /// Expands all faces into triangle soup. Faces which don't specify normals for all of their
+    /// verticies get a flat face normal instead, face verticies without UVs get zero UVs
+    pub fn verticies(&self, color: Color) -> Vec<VertexData> {
+        self.groups
+            .iter()
+            .flat_map(|group| group.faces.iter())
+            .flat_map(|face| self.face_verticies(face, color))
+            .collect()
+    }
+
+    /// Like [`Self::verticies`], but split by material, in the order materials are first used
+    pub fn parts(&self, color: Color) -> Vec<ObjPart<'_>> {
+        let mut parts: Vec<ObjPart> = vec![];
+
+        for group in &self.groups {
+            let verticies = group.faces.iter().flat_map(|face| self.face_verticies(face, color));
+
+            match parts.iter_mut().find(|part| part.material_name == group.material) {
+                Some(part) => part.verticies.extend(verticies),
+                None => parts.push(ObjPart {
+                    material_name: group.material.clone(),
+                    material: group.material.as_ref().and_then(|material| self.material(material)),
+                    verticies: verticies.collect(),
+                }),
+            }
+        }
+
+        parts
+    }
+
+    /// Converts into a non-indexed .cgi mesh. Normals and UVs are only kept if every face vertex
+    /// has them
+    pub fn to_cgi_mesh(&self) -> CgiMesh {
+        let face_verticies: Vec<&FaceVertex> = self
+            .groups
+            .iter()
+            .flat_map(|group| group.faces.iter())
+            .flat_map(|face| face.iter())
+            .collect();
+
+        let mut mesh = CgiMesh::new(vec![]);
+        mesh.positions = face_verticies
+            .iter()
+            .map(|face_vertex| self.positions[face_vertex.position])
+            .collect();
+        mesh.normals = face_verticies
+            .iter()
+            .map(|face_vertex| face_vertex.normal.map(|normal| self.normals[normal]))
+            .collect::<Option<Vec<Vector3<f32>>>>()
+            .unwrap_or_default();
+        mesh.uvs = face_verticies
+            .iter()
+            .map(|face_vertex| face_vertex.uv.map(|uv| self.uvs[uv]))
+            .collect::<Option<Vec<Vector2<f32>>>>()
+            .unwrap_or_default();
+        mesh.update_layout();
+
+        mesh
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.groups.iter().map(|group| group.faces.len()).sum()
+    }
+
+    pub fn material(&self, name: &str) -> Option<&ObjMaterial> {
+        self.materials.iter().find(|material| material.name == name)
+    }
+
+    fn face_verticies(&self, face: &[FaceVertex; 3], color: Color) -> Vec<VertexData> {
+        let verticies = face.map(|face_vertex| {
+            let position = self.positions[face_vertex.position];
+            Vertex {
+                pos: Location::new(position.x, position.y, position.z),
+                clr: color,
+                uv: face_vertex.uv.map_or_else(Vector2::zeros, |uv| self.uvs[uv]),
+            }
+        });
+
+        match (face[0].normal, face[1].normal, face[2].normal) {
+            (Some(a), Some(b), Some(c)) => vec![
+                VertexData::new(&verticies[0], self.normals[a]),
+                VertexData::new(&verticies[1], self.normals[b]),
+                VertexData::new(&verticies[2], self.normals[c]),
+            ],
+            _ => Triangle::new(verticies).vertices(),
+        }
+    }
+
+    /// Starts a new group which inherits the names and material of the current one, unless the
+    /// current group has no faces yet, in which case it is simply reused
+    fn start_group(&mut self) -> &mut ObjGroup {
+        let current = self.groups.last().expect("there's always a current group");
+
+        if !current.faces.is_empty() {
+            let group = ObjGroup {
+                faces: vec![],
+                ..current.clone()
+            };
+            self.groups.push(group);
+        }
+
+        self.groups.last_mut().expect("there's always a current group")
+    }
+
+    fn add_face(&mut self, line: usize, args: &[&str]) -> Result<(), Error> {
+        if args.len() < 3 {
+            return Err(Error::DegenerateFace { line, count: args.len() });
+        }
+
+        let face_verticies = args
+            .iter()
+            .map(|arg| self.parse_face_vertex(line, arg))
+            .collect::<Result<Vec<FaceVertex>, Error>>()?;
+
+        let group = self.groups.last_mut().expect("there's always a current group");
+        for i in 1..face_verticies.len() - 1 {
+            group.faces.push([face_verticies[0], face_verticies[i], face_verticies[i + 1]]);
+        }
+
+        Ok(())
+    }
+
+    /// Parses one of `v`, `v/vt`, `v//vn` or `v/vt/vn`
+    fn parse_face_vertex(&self, line: usize, arg: &str) -> Result<FaceVertex, Error> {
+        let invalid = || Error::InvalidFaceVertex {
+            line,
+            value: arg.to_string(),
+        };
+
+        let parts: Vec<&str> = arg.split('/').collect();
+        if parts.len() > 3 || parts[0].is_empty() {
+            return Err(invalid());
+        }
+
+        let optional_index = |part: Option<&&str>, count: usize| match part {
+            Some(part) if !part.is_empty() => resolve_index(line, arg, part, count).map(Some),
+            _ => Ok(None),
+        };
+
+        Ok(FaceVertex {
+            position: resolve_index(line, arg, parts[0], self.positions.len())?,
+            uv: optional_index(parts.get(1), self.uvs.len())?,
+            normal: optional_index(parts.get(2), self.normals.len())?,
+        })
+    }
+}
+
+/// The faces of an [`ObjModel`] which use the same material
+pub struct ObjPart<'a> {
+    pub material_name: Option<String>,
+    /// `None` for faces without a material, or whose material isn't in any loaded library
+    pub material: Option<&'a ObjMaterial>,
+    pub verticies: Vec<VertexData>,
+}
+
+/// Loads the OBJ resource `name` into `meshes` as one mesh per material, and every material into
+/// `materials` along with its diffuse map. Parts without a material get the default one. Texture
+/// paths are relative to the directory of the OBJ resource, like material libraries.
+pub fn load_with_materials(
+    res: &Resources,
+    name: &str,
+    meshes: &mut MeshRegistry,
+    textures: &mut TextureRegistry,
+    materials: &mut MaterialRegistry,
+) -> Result<Vec<(MeshHandle, MaterialHandle)>, Error> {
+    let model = ObjModel::from_res(res, name)?;
+    let directory = resource_directory(name);
+
+    // The material's base color takes the place of the vertex colors
+    model
+        .parts(WHITE)
+        .into_iter()
+        .map(|part| {
+            let diffuse_map = match part.material.and_then(|material| material.diffuse_map.as_ref()) {
+                Some(diffuse_map) => {
+                    let texture_name = format!("{}{}", directory, diffuse_map);
+                    Some(textures.load_res(res, &texture_name).map_err(|e| Error::Texture {
+                        name: texture_name,
+                        inner: e,
+                    })?)
+                }
+                None => None,
+            };
+
+            let part_name = format!("{}:{}", name, part.material_name.as_deref().unwrap_or_default());
+            let material = match part.material {
+                Some(material) => materials.add(&part_name, Material::from_obj(material, diffuse_map)),
+                None => materials.default_material(),
+            };
+            let mesh = meshes.load(&part_name, || part.verticies);
+
+            Ok((mesh, material))
+        })
+        .collect()
+}
+
+/// Parses the materials of an MTL library
+pub fn parse_mtl(source: &str) -> Result<Vec<ObjMaterial>, Error> {
+    let mut materials: Vec<ObjMaterial> = vec![];
+
+    for (line, keyword, args) in statements(source) {
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial::new(args.join(" ")));
+            continue;
+        }
+
+        // Statements before the first newmtl have nothing to apply to
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+
+        match keyword {
+            "Ka" => material.ambient = Some(parse_color(line, keyword, &args)?),
+            "Kd" => material.diffuse = Some(parse_color(line, keyword, &args)?),
+            "Ks" => material.specular = Some(parse_color(line, keyword, &args)?),
+            "Ns" => material.shininess = Some(parse_floats(line, keyword, &args, 1)?[0]),
+            "d" => material.opacity = Some(parse_floats(line, keyword, &args, 1)?[0]),
+            "Tr" => material.opacity = Some(1.0 - parse_floats(line, keyword, &args, 1)?[0]),
+            "Pr" => material.roughness = Some(parse_floats(line, keyword, &args, 1)?[0]),
+            "Pm" => material.metallic = Some(parse_floats(line, keyword, &args, 1)?[0]),
+            // The file name comes last, after any options
+            "map_Kd" => material.diffuse_map = args.last().map(|arg| arg.to_string()),
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+/// The directory part of a resource name, including the trailing separator
+fn resource_directory(name: &str) -> &str {
+    match name.rfind('/') {
+        Some(separator) => &name[..=separator],
+        None => "",
+    }
+}
+
+fn load_string(res: &Resources, name: &str) -> Result<String, Error> {
+    let bytes = res.load_bytes(name).map_err(|e| Error::ResourceLoad {
+        name: name.into(),
+        inner: e,
+    })?;
+
+    String::from_utf8(bytes).map_err(|_| Error::InvalidUtf8 { name: name.into() })
+}
+
+/// Splits `source` into (line number, keyword, arguments) statements, skipping comments and
+/// blank lines
+fn statements(source: &str) -> impl Iterator<Item = (usize, &str, Vec<&str>)> {
+    source.lines().enumerate().filter_map(|(i, line)| {
+        let without_comment = line.split('#').next().unwrap_or_default();
+        let mut tokens = without_comment.split_whitespace();
+
+        tokens.next().map(|keyword| (i + 1, keyword, tokens.collect()))
+    })
+}
+
+/// Resolves a 1-based OBJ index, or a negative one relative to the end of the `count` elements
+/// defined so far, into a 0-based index
+fn resolve_index(line: usize, arg: &str, raw: &str, count: usize) -> Result<usize, Error> {
+    let index: i64 = raw.parse().map_err(|_| Error::InvalidFaceVertex {
+        line,
+        value: arg.to_string(),
+    })?;
+
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(Error::IndexOutOfRange { line, index });
+    }
+
+    Ok(resolved as usize)
+}
+
+/// Parses the numbers `args` start with, of which there have to be at least `expected`. Whatever
+/// follows the numbers is ignored, as some exporters append names or other extensions.
+fn parse_floats(line: usize, keyword: &str, args: &[&str], expected: usize) -> Result<Vec<f32>, Error> {
+    let mut values = vec![];
+
+    for arg in args {
+        match arg.parse::<f32>() {
+            Ok(value) => values.push(value),
+            Err(_) if values.len() >= expected => break,
+            Err(_) => {
+                return Err(Error::InvalidNumber {
+                    line,
+                    value: arg.to_string(),
+                })
+            }
+        }
+    }
+
+    if values.len() < expected {
+        return Err(Error::MissingValues {
+            line,
+            keyword: keyword.to_string(),
+            expected,
+        });
+    }
+
+    Ok(values)
+}
+
+fn parse_vector3(line: usize, keyword: &str, args: &[&str]) -> Result<Vector3<f32>, Error> {
+    let values = parse_floats(line, keyword, args, 3)?;
+    Ok(Vector3::new(values[0], values[1], values[2]))
+}
+
+fn parse_color(line: usize, keyword: &str, args: &[&str]) -> Result<Color, Error> {
+    let values = parse_floats(line, keyword, args, 3)?;
+    Ok(Color::new(values[0], values[1], values[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Vector2, Vector3};
+
+    use super::{Error, FaceVertex, ObjModel};
+    use crate::primitives::light::consts::WHITE;
+    use crate::resources::Resources;
+
+    const SQUARE: &str = "
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+    ";
+
+    fn face_vertex(position: usize, uv: Option<usize>, normal: Option<usize>) -> FaceVertex {
+        FaceVertex { position, uv, normal }
+    }
+
+    #[test]
+    fn verticies_keep_positions_normals_and_uvs() {
+        let model = ObjModel::parse(SQUARE).unwrap();
+
+        assert_eq!(model.positions[2], Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(model.normals, vec![Vector3::z()]);
+        assert_eq!(model.uvs[1], Vector2::new(1.0, 0.0));
+
+        let verticies = model.verticies(WHITE);
+        let (pos, norm, uv) = (verticies[1].pos, verticies[1].norm, verticies[1].uv);
+        assert_eq!((pos.d0, pos.d1, pos.d2), (1.0, 0.0, 0.0));
+        assert_eq!((norm.d0, norm.d1, norm.d2), (0.0, 0.0, 1.0));
+        assert_eq!((uv.d0, uv.d1), (1.0, 0.0));
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let model = ObjModel::parse(SQUARE).unwrap();
+
+        let corner = |i: usize| face_vertex(i, Some(i), Some(0));
+        assert_eq!(
+            model.groups[0].faces,
+            vec![[corner(0), corner(1), corner(2)], [corner(0), corner(2), corner(3)]]
+        );
+        assert_eq!(model.verticies(WHITE).len(), 6);
+    }
+
+    #[test]
+    fn negative_indices_are_relative_to_the_last_element() {
+        let model = ObjModel::parse(
+            "
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            vn 0 0 1
+            f -3//-1 -2//-1 -1//-1
+            v 0 0 1
+            f 1 -3 -1
+            ",
+        )
+        .unwrap();
+
+        let faces = &model.groups[0].faces;
+        let normal = Some(0);
+        assert_eq!(
+            faces[0],
+            [
+                face_vertex(0, None, normal),
+                face_vertex(1, None, normal),
+                face_vertex(2, None, normal)
+            ]
+        );
+        assert_eq!(
+            faces[1],
+            [face_vertex(0, None, None), face_vertex(1, None, None), face_vertex(3, None, None)]
+        );
+    }
+
+    #[test]
+    fn trailing_tokens_are_ignored() {
+        let model = ObjModel::parse("v 1 2 3 1.0 0.5 0.5 extra\nvt 0.5 garbage").unwrap();
+
+        assert_eq!(model.positions, vec![Vector3::new(1.0, 2.0, 3.0)]);
+        assert_eq!(model.uvs, vec![Vector2::new(0.5, 0.0)]);
+    }
+
+    #[test]
+    fn invalid_statements_are_typed_errors() {
+        let error = |source: &str| ObjModel::parse(source).unwrap_err();
+
+        assert!(matches!(
+            error("v 1 2"),
+            Error::MissingValues { line: 1, ref keyword, expected: 3 } if keyword == "v"
+        ));
+        assert!(matches!(error("v 1 two 3"), Error::InvalidNumber { line: 1, ref value } if value == "two"));
+        assert!(matches!(
+            error("v 0 0 0\nf 1/1/1/1 1 1"),
+            Error::InvalidFaceVertex { line: 2, ref value } if value == "1/1/1/1"
+        ));
+        assert!(matches!(error("v 0 0 0\nf 1 a 1"), Error::InvalidFaceVertex { line: 2, .. }));
+        assert!(matches!(error("v 0 0 0\nf 1 1 2"), Error::IndexOutOfRange { line: 2, index: 2 }));
+        assert!(matches!(error("v 0 0 0\nf 1 1 0"), Error::IndexOutOfRange { line: 2, index: 0 }));
+        assert!(matches!(error("v 0 0 0\nf 1 1 -2"), Error::IndexOutOfRange { line: 2, index: -2 }));
+        assert!(matches!(error("v 0 0 0\nf 1 1"), Error::DegenerateFace { line: 2, count: 2 }));
+    }
+
+    #[test]
+    fn unreadable_resources_are_typed_errors() {
+        let directory = std::env::temp_dir().join(format!("cgi-obj-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("invalid.obj"), [b'v', b' ', 0xff, 0xfe]).unwrap();
+        let res = Resources::from_path(&directory);
+
+        let missing = ObjModel::from_res(&res, "missing.obj").unwrap_err();
+        let invalid = ObjModel::from_res(&res, "invalid.obj").unwrap_err();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert!(matches!(missing, Error::ResourceLoad { ref name, .. } if name == "missing.obj"));
+        assert!(matches!(invalid, Error::InvalidUtf8 { ref name } if name == "invalid.obj"));
+    }
+}
@@ -0,0 +1,525 @@
+use nalgebra::{Vector2, Vector3, Vector4};
+
+use crate::models::cgi_format::{Attribute, CgiMesh, ElementType};
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Not a PLY file (bad magic)")]
+    BadMagic,
+    #[fail(display = "Header has no end_header line")]
+    MissingEndHeader,
+    #[fail(display = "Unsupported PLY format \"{}\"", format)]
+    UnsupportedFormat { format: String },
+    #[fail(display = "Invalid header line \"{}\"", line)]
+    InvalidHeader { line: String },
+    #[fail(display = "Unknown property type \"{}\"", name)]
+    UnknownType { name: String },
+    #[fail(display = "Element \"{}\" has no \"{}\" property", element, property)]
+    MissingProperty { element: String, property: String },
+    #[fail(display = "Failed to parse number \"{}\"", value)]
+    InvalidNumber { value: String },
+    #[fail(display = "File is truncated")]
+    Truncated,
+    #[fail(display = "ASCII body is not valid UTF-8")]
+    InvalidUtf8,
+    #[fail(display = "Face vertex index {} is not a whole number from 0 on", value)]
+    InvalidIndex { value: f64 },
+    #[fail(display = "Face refers to vertex {}, but there are only {} verticies", index, count)]
+    IndexOutOfRange { index: usize, count: usize },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Type {
+    fn from_name(name: &str) -> Result<Type, Error> {
+        match name {
+            "char" | "int8" => Ok(Type::I8),
+            "uchar" | "uint8" => Ok(Type::U8),
+            "short" | "int16" => Ok(Type::I16),
+            "ushort" | "uint16" => Ok(Type::U16),
+            "int" | "int32" => Ok(Type::I32),
+            "uint" | "uint32" => Ok(Type::U32),
+            "float" | "float32" => Ok(Type::F32),
+            "double" | "float64" => Ok(Type::F64),
+            _ => Err(Error::UnknownType { name: name.to_string() }),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+
+    /// The value that integer colors are divided by to get a 0 to 1 color component
+    fn color_scale(self) -> f64 {
+        match self {
+            Type::I8 => i8::MAX as f64,
+            Type::U8 => u8::MAX as f64,
+            Type::I16 => i16::MAX as f64,
+            Type::U16 => u16::MAX as f64,
+            Type::I32 => i32::MAX as f64,
+            Type::U32 => u32::MAX as f64,
+            Type::F32 | Type::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PropertyKind {
+    Scalar(Type),
+    List { count: Type, item: Type },
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+    /// Every row holds one entry per property, scalars are lists of length 1
+    rows: Vec<Vec<Vec<f64>>>,
+}
+
+impl Element {
+    fn property_index(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|property| property.name == name)
+    }
+
+    fn require_property(&self, name: &str) -> Result<usize, Error> {
+        self.property_index(name).ok_or_else(|| Error::MissingProperty {
+            element: self.name.clone(),
+            property: name.to_string(),
+        })
+    }
+
+    /// The first of `names` this element has, along with its type
+    fn find_scalar(&self, names: &[&str]) -> Option<(usize, Type)> {
+        names.iter().find_map(|name| {
+            let index = self.property_index(name)?;
+            match self.properties[index].kind {
+                PropertyKind::Scalar(kind) => Some((index, kind)),
+                PropertyKind::List { .. } => None,
+            }
+        })
+    }
+}
+
+/// Parses an ASCII or binary PLY file. Verticies may have positions, normals, colors and UVs,
+/// faces of any arity are fan-triangulated into the index buffer.
+pub fn parse(bytes: &[u8]) -> Result<CgiMesh, Error> {
+    if !bytes.starts_with(b"ply") {
+        return Err(Error::BadMagic);
+    }
+
+    let (format, mut elements, body) = parse_header(bytes)?;
+
+    let mut values = ValueReader::new(body, format)?;
+    for element in elements.iter_mut() {
+        for _ in 0..element.count {
+            let row = element
+                .properties
+                .iter()
+                .map(|property| match property.kind {
+                    PropertyKind::Scalar(kind) => Ok(vec![values.read(kind)?]),
+                    PropertyKind::List { count, item } => {
+                        let count = values.read(count)? as usize;
+                        (0..count).map(|_| values.read(item)).collect()
+                    }
+                })
+                .collect::<Result<Vec<Vec<f64>>, Error>>()?;
+            element.rows.push(row);
+        }
+    }
+
+    let vertex = elements
+        .iter()
+        .find(|element| element.name == "vertex")
+        .ok_or_else(|| Error::MissingProperty {
+            element: "vertex".to_string(),
+            property: "x".to_string(),
+        })?;
+
+    let mut mesh = CgiMesh::new(vec![]);
+
+    let (x, y, z) = (
+        vertex.require_property("x")?,
+        vertex.require_property("y")?,
+        vertex.require_property("z")?,
+    );
+    mesh.positions = vertex
+        .rows
+        .iter()
+        .map(|row| Vector3::new(row[x][0] as f32, row[y][0] as f32, row[z][0] as f32))
+        .collect();
+
+    if let (Some((nx, _)), Some((ny, _)), Some((nz, _))) = (
+        vertex.find_scalar(&["nx"]),
+        vertex.find_scalar(&["ny"]),
+        vertex.find_scalar(&["nz"]),
+    ) {
+        mesh.normals = vertex
+            .rows
+            .iter()
+            .map(|row| Vector3::new(row[nx][0] as f32, row[ny][0] as f32, row[nz][0] as f32))
+            .collect();
+    }
+
+    let mut pack_colors = true;
+    if let (Some((r, kind)), Some((g, _)), Some((b, _))) = (
+        vertex.find_scalar(&["red", "r"]),
+        vertex.find_scalar(&["green", "g"]),
+        vertex.find_scalar(&["blue", "b"]),
+    ) {
+        let alpha = vertex.find_scalar(&["alpha", "a"]);
+        let scale = kind.color_scale();
+
+        // Packed colors have 10 bits for RGB and 2 for alpha, which only fits opaque 8 bit colors
+        // without losing precision
+        pack_colors = alpha.is_none() && matches!(kind, Type::U8 | Type::I8);
+
+        mesh.colors = vertex
+            .rows
+            .iter()
+            .map(|row| {
+                let a = alpha.map_or(scale, |(a, _)| row[a][0]);
+                (Vector4::new(row[r][0], row[g][0], row[b][0], a) / scale).cast::<f32>()
+            })
+            .collect();
+    }
+
+    if let (Some((u, _)), Some((v, _))) = (
+        vertex.find_scalar(&["u", "s", "texture_u", "texture_s"]),
+        vertex.find_scalar(&["v", "t", "texture_v", "texture_t"]),
+    ) {
+        mesh.uvs = vertex
+            .rows
+            .iter()
+            .map(|row| Vector2::new(row[u][0] as f32, row[v][0] as f32))
+            .collect();
+    }
+
+    let mut indices = vec![];
+    if let Some(face) = elements.iter().find(|element| element.name == "face") {
+        let list = face
+            .property_index("vertex_indices")
+            .or_else(|| face.property_index("vertex_index"))
+            .ok_or_else(|| Error::MissingProperty {
+                element: "face".to_string(),
+                property: "vertex_indices".to_string(),
+            })?;
+
+        for row in face.rows.iter() {
+            let polygon = row[list]
+                .iter()
+                .map(|value| {
+                    if *value < 0.0 || value.fract() != 0.0 {
+                        return Err(Error::InvalidIndex { value: *value });
+                    }
+
+                    let index = *value as usize;
+                    if index >= mesh.positions.len() {
+                        return Err(Error::IndexOutOfRange {
+                            index,
+                            count: mesh.positions.len(),
+                        });
+                    }
+                    Ok(index as u32)
+                })
+                .collect::<Result<Vec<u32>, Error>>()?;
+
+            for i in 1..polygon.len().saturating_sub(1) {
+                indices.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+            }
+        }
+    }
+
+    mesh.indices = Some(indices);
+    mesh.update_layout();
+    if !pack_colors {
+        mesh.layout
+            .iter_mut()
+            .filter(|layout| layout.attribute == Attribute::Color)
+            .for_each(|layout| layout.element_type = ElementType::F32);
+    }
+
+    Ok(mesh)
+}
+
+/// Parses the header, returning the body format, the declared elements and the body itself
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, &[u8]), Error> {
+    const END_HEADER: &[u8] = b"end_header";
+
+    let end = bytes
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or(Error::MissingEndHeader)?;
+    let body_start = bytes[end..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(bytes.len(), |newline| end + newline + 1);
+
+    let header = String::from_utf8_lossy(&bytes[..end]);
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+
+    for line in header.lines().skip(1) {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || Error::InvalidHeader { line: line.to_string() };
+
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(Error::UnsupportedFormat { format: name.to_string() }),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid())?,
+                properties: vec![],
+                rows: vec![],
+            }),
+            ["property", "list", count, item, name] => elements.last_mut().ok_or_else(invalid)?.properties.push(Property {
+                name: name.to_string(),
+                kind: PropertyKind::List {
+                    count: Type::from_name(count)?,
+                    item: Type::from_name(item)?,
+                },
+            }),
+            ["property", kind, name] => elements.last_mut().ok_or_else(invalid)?.properties.push(Property {
+                name: name.to_string(),
+                kind: PropertyKind::Scalar(Type::from_name(kind)?),
+            }),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(invalid()),
+        }
+    }
+
+    let format = format.ok_or_else(|| Error::InvalidHeader {
+        line: "format".to_string(),
+    })?;
+
+    Ok((format, elements, &bytes[body_start..]))
+}
+
+struct ValueReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    format: Format,
+    tokens: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> ValueReader<'a> {
+    fn new(bytes: &'a [u8], format: Format) -> Result<Self, Error> {
+        // ASCII bodies are read token by token, binary ones byte by byte
+        let text = match format {
+            Format::Ascii => std::str::from_utf8(bytes).map_err(|_| Error::InvalidUtf8)?,
+            _ => "",
+        };
+
+        Ok(ValueReader {
+            bytes,
+            offset: 0,
+            format,
+            tokens: text.split_whitespace(),
+        })
+    }
+
+    fn read(&mut self, kind: Type) -> Result<f64, Error> {
+        if self.format == Format::Ascii {
+            let token = self.tokens.next().ok_or(Error::Truncated)?;
+            return token.parse::<f64>().map_err(|_| Error::InvalidNumber { value: token.to_string() });
+        }
+
+        let size = kind.size();
+        if self.bytes.len() < self.offset + size {
+            return Err(Error::Truncated);
+        }
+
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.bytes[self.offset..self.offset + size]);
+        self.offset += size;
+
+        if self.format == Format::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+
+        let (b, c) = ([raw[0], raw[1]], [raw[0], raw[1], raw[2], raw[3]]);
+        Ok(match kind {
+            Type::I8 => raw[0] as i8 as f64,
+            Type::U8 => raw[0] as f64,
+            Type::I16 => i16::from_le_bytes(b) as f64,
+            Type::U16 => u16::from_le_bytes(b) as f64,
+            Type::I32 => i32::from_le_bytes(c) as f64,
+            Type::U32 => u32::from_le_bytes(c) as f64,
+            Type::F32 => f32::from_le_bytes(c) as f64,
+            Type::F64 => f64::from_le_bytes(raw),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Vector3, Vector4};
+
+    use super::{parse, Error};
+    use crate::models::cgi_format::{Attribute, ElementType};
+
+    const HEADER: &str = "ply
+format ascii 1.0
+comment a unit square
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    fn color_type(mesh: &crate::models::cgi_format::CgiMesh) -> Option<ElementType> {
+        mesh.layout
+            .iter()
+            .find(|layout| layout.attribute == Attribute::Color)
+            .map(|layout| layout.element_type)
+    }
+
+    #[test]
+    fn ascii_polygons_are_triangulated() {
+        let source = format!(
+            "{}0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n",
+            HEADER
+        );
+        let mesh = parse(source.as_bytes()).unwrap();
+
+        assert_eq!(mesh.positions[2], Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(mesh.colors[1], Vector4::new(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(mesh.indices, Some(vec![0, 1, 2, 0, 2, 3]));
+        assert_eq!(color_type(&mesh), Some(ElementType::Packed));
+    }
+
+    #[test]
+    fn binary_bodies_are_read_in_their_byte_order() {
+        let header = |format: &str| {
+            format!(
+                "ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                 element face 1\nproperty list uchar ushort vertex_indices\nend_header\n",
+                format
+            )
+        };
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+        let mut little = header("binary_little_endian").into_bytes();
+        positions
+            .iter()
+            .flatten()
+            .for_each(|value| little.extend_from_slice(&value.to_le_bytes()));
+        little.push(3);
+        [0u16, 1, 2].iter().for_each(|index| little.extend_from_slice(&index.to_le_bytes()));
+
+        let mut big = header("binary_big_endian").into_bytes();
+        positions
+            .iter()
+            .flatten()
+            .for_each(|value| big.extend_from_slice(&value.to_be_bytes()));
+        big.push(3);
+        [0u16, 1, 2].iter().for_each(|index| big.extend_from_slice(&index.to_be_bytes()));
+
+        for bytes in [little, big] {
+            let mesh = parse(&bytes).unwrap();
+            assert_eq!(mesh.positions[1], Vector3::new(1.0, 0.0, 0.0));
+            assert_eq!(mesh.indices, Some(vec![0, 1, 2]));
+        }
+    }
+
+    #[test]
+    fn colors_with_alpha_are_not_packed() {
+        let source = "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+property uchar alpha
+end_header
+0 0 0 255 0 0 51
+1 0 0 255 0 0 51
+0 1 0 255 0 0 51
+";
+        let mesh = parse(source.as_bytes()).unwrap();
+
+        assert_eq!(mesh.colors[0], Vector4::new(1.0, 0.0, 0.0, 0.2));
+        assert_eq!(color_type(&mesh), Some(ElementType::F32));
+    }
+
+    #[test]
+    fn invalid_bodies_are_typed_errors() {
+        let body = |faces: &str| format!("{}0 0 0 0 0 0\n1 0 0 0 0 0\n1 1 0 0 0 0\n0 1 0 0 0 0\n{}\n", HEADER, faces);
+
+        assert!(matches!(parse(body("3 0 1 -1").as_bytes()), Err(Error::InvalidIndex { value }) if value == -1.0));
+        assert!(matches!(parse(body("3 0 1 1.5").as_bytes()), Err(Error::InvalidIndex { value }) if value == 1.5));
+        assert!(matches!(
+            parse(body("3 0 1 4").as_bytes()),
+            Err(Error::IndexOutOfRange { index: 4, count: 4 })
+        ));
+        assert!(matches!(parse(body("3 0 1").as_bytes()), Err(Error::Truncated)));
+        assert!(matches!(parse(body("3 0 1 x").as_bytes()), Err(Error::InvalidNumber { ref value }) if value == "x"));
+
+        let mut invalid_utf8 = HEADER.as_bytes().to_vec();
+        invalid_utf8.extend_from_slice(&[b'0', b' ', 0xff]);
+        assert!(matches!(parse(&invalid_utf8), Err(Error::InvalidUtf8)));
+    }
+
+    #[test]
+    fn invalid_headers_are_typed_errors() {
+        assert!(matches!(parse(b"solid cube"), Err(Error::BadMagic)));
+        assert!(matches!(parse(b"ply\nformat ascii 1.0\n"), Err(Error::MissingEndHeader)));
+        assert!(matches!(
+            parse(b"ply\nformat binary_middle_endian 1.0\nend_header\n"),
+            Err(Error::UnsupportedFormat { ref format }) if format == "binary_middle_endian"
+        ));
+        assert!(matches!(
+            parse(b"ply\nformat ascii 1.0\nelement vertex 0\nproperty quad x\nend_header\n"),
+            Err(Error::UnknownType { ref name }) if name == "quad"
+        ));
+        assert!(matches!(
+            parse(b"ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\nend_header\n"),
+            Err(Error::MissingProperty { ref property, .. }) if property == "y"
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+use nalgebra::Vector3;
+
+use crate::models::cgi_format::CgiMesh;
+
+// 80 byte header followed by the u32 triangle count
+const BINARY_HEADER_SIZE: usize = 84;
+// Normal and 3 verticies of 3 f32s each, then a u16 "attribute byte count"
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Not an STL file, it's neither a valid binary STL nor starts with \"solid\"")]
+    UnknownFormat,
+    #[fail(display = "Failed to parse number \"{}\"", value)]
+    InvalidNumber { value: String },
+    #[fail(display = "Expected {} after \"{}\"", expected, keyword)]
+    MissingValues { keyword: String, expected: usize },
+    #[fail(display = "Facet {} has {} verticies, expected 3", facet, count)]
+    NotATriangle { facet: usize, count: usize },
+    #[fail(display = "File is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+/// Parses a binary or ASCII STL file into a non-indexed mesh. Facets with a zero normal (which
+/// many exporters write) get a normal calculated from their verticies instead.
+pub fn parse(bytes: &[u8]) -> Result<CgiMesh, Error> {
+    if is_binary(bytes) {
+        Ok(parse_binary(bytes))
+    } else if bytes.starts_with(b"solid") {
+        parse_ascii(std::str::from_utf8(bytes).map_err(|_| Error::InvalidUtf8)?)
+    } else {
+        Err(Error::UnknownFormat)
+    }
+}
+
+/// ASCII files start with "solid", but so do plenty of binary files, so the size is what decides
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < BINARY_HEADER_SIZE {
+        return false;
+    }
+
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    bytes.len() == BINARY_HEADER_SIZE + count * BINARY_TRIANGLE_SIZE
+}
+
+fn parse_binary(bytes: &[u8]) -> CgiMesh {
+    let mut mesh = CgiMesh::new(vec![]);
+
+    for triangle in bytes[BINARY_HEADER_SIZE..].chunks_exact(BINARY_TRIANGLE_SIZE) {
+        let vector = |offset: usize| {
+            let float = |i: usize| {
+                let start = offset + i * 4;
+                f32::from_le_bytes([triangle[start], triangle[start + 1], triangle[start + 2], triangle[start + 3]])
+            };
+            Vector3::new(float(0), float(1), float(2))
+        };
+
+        add_facet(&mut mesh, vector(0), [vector(12), vector(24), vector(36)]);
+    }
+
+    mesh.update_layout();
+    mesh
+}
+
+fn parse_ascii(source: &str) -> Result<CgiMesh, Error> {
+    let mut mesh = CgiMesh::new(vec![]);
+    let mut tokens = source.split_whitespace();
+
+    let mut normal = Vector3::zeros();
+    let mut verticies = vec![];
+    let mut facet = 0;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "facet" => {
+                verticies.clear();
+                normal = Vector3::zeros();
+            }
+            "normal" => normal = parse_vector3(&mut tokens, token)?,
+            "vertex" => verticies.push(parse_vector3(&mut tokens, token)?),
+            "endfacet" => {
+                if verticies.len() != 3 {
+                    return Err(Error::NotATriangle {
+                        facet,
+                        count: verticies.len(),
+                    });
+                }
+
+                add_facet(&mut mesh, normal, [verticies[0], verticies[1], verticies[2]]);
+                facet += 1;
+            }
+            // solid / outer loop / endloop / endsolid and the solid's name carry no geometry
+            _ => {}
+        }
+    }
+
+    mesh.update_layout();
+    Ok(mesh)
+}
+
+fn add_facet(mesh: &mut CgiMesh, normal: Vector3<f32>, verticies: [Vector3<f32>; 3]) {
+    let normal = normal.try_normalize(f32::EPSILON).unwrap_or_else(|| {
+        (verticies[1] - verticies[0])
+            .cross(&(verticies[2] - verticies[0]))
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::z)
+    });
+
+    for vertex in verticies.iter() {
+        mesh.positions.push(*vertex);
+        mesh.normals.push(normal);
+    }
+}
+
+fn parse_vector3<'a>(tokens: &mut impl Iterator<Item = &'a str>, keyword: &str) -> Result<Vector3<f32>, Error> {
+    let mut value = || {
+        let token = tokens.next().ok_or_else(|| Error::MissingValues {
+            keyword: keyword.to_string(),
+            expected: 3,
+        })?;
+
+        token.parse::<f32>().map_err(|_| Error::InvalidNumber { value: token.to_string() })
+    };
+
+    Ok(Vector3::new(value()?, value()?, value()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::{parse, Error};
+
+    const ASCII: &str = "solid triangle
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid triangle
+";
+
+    #[test]
+    fn ascii_facets_get_normals_from_their_verticies() {
+        let mesh = parse(ASCII.as_bytes()).unwrap();
+
+        assert_eq!(mesh.positions, vec![Vector3::zeros(), Vector3::x(), Vector3::y()]);
+        assert_eq!(mesh.normals, vec![Vector3::z(); 3]);
+        assert_eq!(mesh.indices, None);
+    }
+
+    #[test]
+    fn binary_files_are_recognized_by_their_size() {
+        // Starts with "solid" like many binary exporters write
+        let mut bytes = b"solid".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        [[0.0f32, 0.0, 2.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .iter()
+            .flatten()
+            .for_each(|value| bytes.extend_from_slice(&value.to_le_bytes()));
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+
+        let mesh = parse(&bytes).unwrap();
+
+        assert_eq!(mesh.positions, vec![Vector3::zeros(), Vector3::x(), Vector3::y()]);
+        // Normalized
+        assert_eq!(mesh.normals, vec![Vector3::z(); 3]);
+    }
+
+    #[test]
+    fn invalid_files_are_typed_errors() {
+        assert!(matches!(parse(b"ply"), Err(Error::UnknownFormat)));
+        assert!(matches!(parse(b"solid \xff"), Err(Error::InvalidUtf8)));
+        assert!(matches!(
+            parse(b"solid x facet normal 0 0 1 outer loop vertex 0 0 0 vertex 1 0 0 endloop endfacet"),
+            Err(Error::NotATriangle { facet: 0, count: 2 })
+        ));
+        assert!(matches!(
+            parse(b"solid x facet normal 0 0 one"),
+            Err(Error::InvalidNumber { ref value }) if value == "one"
+        ));
+        assert!(matches!(
+            parse(b"solid x facet normal 0 0"),
+            Err(Error::MissingValues { ref keyword, expected: 3 }) if keyword == "normal"
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use image::DynamicImage;
+
+use crate::render_gl::{ColorSpace, Sampling, Texture};
+use crate::resources;
+use crate::resources::Resources;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Failed to load resource {}", name)]
+    ResourceLoad {
+        name: String,
+        #[cause]
+        inner: resources::Error,
+    },
+    #[fail(display = "Failed to decode image {}", name)]
+    Decode {
+        name: String,
+        #[cause]
+        inner: image::ImageError,
+    },
+}
+
+/// A cheap reference to a texture loaded into a [`TextureRegistry`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TextureHandle(usize);
+
+/// Loads every texture only once, no matter how many objects use it. Unlike meshes, textures are
+/// uploaded as soon as they're loaded.
+pub struct TextureRegistry {
+    gl: gl::Gl,
+    sampling: Sampling,
+    textures: Vec<Texture>,
+    handles: HashMap<String, TextureHandle>,
+}
+
+impl TextureRegistry {
+    /// All textures are sampled according to `sampling`
+    pub fn new(gl: &gl::Gl, sampling: Sampling) -> Self {
+        TextureRegistry {
+            gl: gl.clone(),
+            sampling,
+            // Untextured objects sample the white texture, which leaves their colors as they are
+            textures: vec![Texture::white(gl)],
+            handles: HashMap::new(),
+        }
+    }
+
+    pub fn white(&self) -> TextureHandle {
+        TextureHandle(0)
+    }
+
+    /// Returns the handle of the texture called `name`, calling `load` to create it only if it
+    /// hasn't been loaded before. Images are taken to be sRGB encoded, like any color map.
+    pub fn load<F>(&mut self, name: &str, load: F) -> TextureHandle
+    where
+        F: FnOnce() -> DynamicImage,
+    {
+        if let Some(handle) = self.handles.get(name) {
+            return *handle;
+        }
+
+        let handle = TextureHandle(self.textures.len());
+        self.textures
+            .push(Texture::from_image(&self.gl, &load(), &self.sampling, ColorSpace::Srgb));
+        self.handles.insert(name.to_string(), handle);
+
+        handle
+    }
+
+    /// Like [`Self::load`], with the image decoded from the resource `name`, in any format the
+    /// `image` crate recognizes
+    pub fn load_res(&mut self, res: &Resources, name: &str) -> Result<TextureHandle, Error> {
+        if let Some(handle) = self.handles.get(name) {
+            return Ok(*handle);
+        }
+
+        let bytes = res.load_bytes(name).map_err(|e| Error::ResourceLoad {
+            name: name.into(),
+            inner: e,
+        })?;
+        let image = image::load_from_memory(&bytes).map_err(|e| Error::Decode {
+            name: name.into(),
+            inner: e,
+        })?;
+
+        Ok(self.load(name, || image))
+    }
+
+    pub fn get(&self, handle: TextureHandle) -> &Texture {
+        &self.textures[handle.0]
+    }
+}
@@ -1,9 +1,26 @@
-use nalgebra::{Matrix4, Rotation3, Translation3, Vector3};
+use nalgebra::{Matrix3, Matrix4, Rotation3, Translation3, Vector3};
 
 use crate::primitives::spatial::{Location, Orientation};
 
 pub trait Model {
     fn model(&self) -> (f32, Matrix4<f32>, Matrix4<f32>);
+
+    /// The scale, translation and rotation from [`Self::model()`] combined into a single matrix
+    fn model_matrix(&self) -> Matrix4<f32> {
+        let (scale, translation, rotation) = self.model();
+        translation * rotation * Matrix4::new_scaling(scale)
+    }
+
+    /// Transforms normals so they stay perpendicular to the surfaces [`Self::model_matrix()`]
+    /// transforms, the inverse transpose of its upper 3x3. The normals aren't unit length unless
+    /// the scale is 1, and models scaled down to nothing leave them all zeros.
+    fn normal_matrix(&self) -> Matrix3<f32> {
+        let linear: Matrix3<f32> = self.model_matrix().fixed_view::<3, 3>(0, 0).into();
+        linear
+            .try_inverse()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or_else(Matrix3::zeros)
+    }
 }
 
 pub struct Spatial {
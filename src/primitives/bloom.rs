@@ -0,0 +1,181 @@
+use failure::Error;
+use nalgebra::Vector2;
+
+use crate::render_gl::buffer::VertexArray;
+use crate::render_gl::framebuffer;
+use crate::render_gl::framebuffer::Attachment;
+use crate::render_gl::{Framebuffer, Program, Texture2D, TextureFormat};
+use crate::resources::Resources;
+
+/// The texture unit the source of every bloom pass is bound to
+const SOURCE_UNIT: u32 = 0;
+
+#[derive(Debug, Copy, Clone)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    /// Brightness above which colors start to bloom
+    pub threshold: f32,
+    /// Width of the transition around the threshold, relative to it. 0 gives a hard cut.
+    pub knee: f32,
+    /// How much of the blurred bright parts is added back onto the scene
+    pub intensity: f32,
+    /// Number of times the bright parts are halved in size, more levels spread the glow wider
+    pub levels: usize,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        BloomSettings {
+            enabled: true,
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.05,
+            levels: 6,
+        }
+    }
+}
+
+// A level of the blur chain, half the size of the previous one
+struct BloomLevel {
+    texture: Texture2D,
+    framebuffer: Framebuffer,
+}
+
+impl BloomLevel {
+    fn new(gl: &gl::Gl, width: u32, height: u32) -> Result<BloomLevel, framebuffer::Error> {
+        let texture = Texture2D::new(gl, width, height, TextureFormat::Rgba16F);
+
+        let framebuffer = Framebuffer::new(gl);
+        framebuffer.bind();
+        framebuffer.attach_texture(Attachment::Color(0), &texture);
+        let complete = framebuffer.check_complete();
+        framebuffer.unbind();
+        complete?;
+
+        Ok(BloomLevel { texture, framebuffer })
+    }
+}
+
+/// Makes bright parts of the image glow: whatever is brighter than the threshold is extracted
+/// while downsampling, blurred with a dual Kawase filter across a chain of ever smaller textures,
+/// and upsampled back into the first level, ready to be added onto the scene.
+pub struct Bloom {
+    gl: gl::Gl,
+    levels: Vec<BloomLevel>,
+    downsample: Program,
+    upsample: Program,
+    vao: VertexArray,
+    downsample_texel_size: i32,
+    downsample_bright_pass: i32,
+    downsample_threshold: i32,
+    downsample_knee: i32,
+    upsample_texel_size: i32,
+}
+
+impl Bloom {
+    pub fn new(res: &Resources, gl: &gl::Gl, settings: &BloomSettings, size: (u32, u32)) -> Result<Bloom, Error> {
+        let downsample = Program::from_res_shaders(
+            gl,
+            res,
+            "shaders/bloom_downsample",
+            &["shaders/fullscreen.vert", "shaders/bloom_downsample.frag"],
+        )?;
+        let upsample = Program::from_res_shaders(
+            gl,
+            res,
+            "shaders/bloom_upsample",
+            &["shaders/fullscreen.vert", "shaders/bloom_upsample.frag"],
+        )?;
+
+        for program in [&downsample, &upsample] {
+            program.set_used();
+            program.set_int_uniform(program.get_uniform_loc("source")?, SOURCE_UNIT as i32);
+        }
+
+        Ok(Bloom {
+            gl: gl.clone(),
+            levels: Self::levels(gl, settings, size)?,
+            downsample_texel_size: downsample.get_uniform_loc("texel_size")?,
+            downsample_bright_pass: downsample.get_uniform_loc("bright_pass")?,
+            downsample_threshold: downsample.get_uniform_loc("threshold")?,
+            downsample_knee: downsample.get_uniform_loc("knee")?,
+            upsample_texel_size: upsample.get_uniform_loc("texel_size")?,
+            downsample,
+            upsample,
+            vao: VertexArray::new(gl),
+        })
+    }
+
+    fn levels(gl: &gl::Gl, settings: &BloomSettings, size: (u32, u32)) -> Result<Vec<BloomLevel>, framebuffer::Error> {
+        (1..=settings.levels.max(1))
+            .map(|level| BloomLevel::new(gl, (size.0 >> level).max(1), (size.1 >> level).max(1)))
+            .collect()
+    }
+
+    /// Recreates the blur chain for a new scene size
+    pub fn resize(&mut self, settings: &BloomSettings, size: (u32, u32)) -> Result<(), framebuffer::Error> {
+        self.levels = Self::levels(&self.gl, settings, size)?;
+
+        Ok(())
+    }
+
+    /// The result of the last [`Self::render`]
+    pub fn texture(&self) -> &Texture2D {
+        &self.levels[0].texture
+    }
+
+    /// Blurs the bright parts of `scene` into [`Self::texture`]. Uses additive blending and changes
+    /// the viewport, which are both restored to the window's defaults (alpha blending and
+    /// `viewport_size`) afterwards.
+    pub fn render(&self, settings: &BloomSettings, scene: &Texture2D, viewport_size: (u32, u32)) {
+        self.vao.bind();
+        unsafe {
+            self.gl.Disable(gl::DEPTH_TEST);
+        }
+
+        // Downsample from the scene into the first level, and from every level into the next
+        self.downsample.set_used();
+        self.downsample.set_float_uniform(self.downsample_threshold, settings.threshold);
+        self.downsample.set_float_uniform(self.downsample_knee, settings.knee);
+        let mut source = scene;
+        for (i, level) in self.levels.iter().enumerate() {
+            self.downsample
+                .set_int_uniform(self.downsample_bright_pass, if i == 0 { 1 } else { 0 });
+            self.pass(&self.downsample, self.downsample_texel_size, source, level);
+            source = &level.texture;
+        }
+
+        // Upsample every level and add it onto the previous one, all the way back to the first
+        self.upsample.set_used();
+        unsafe {
+            self.gl.BlendFunc(gl::ONE, gl::ONE);
+        }
+        for pair in self.levels.windows(2).rev() {
+            self.pass(&self.upsample, self.upsample_texel_size, &pair[1].texture, &pair[0]);
+        }
+
+        unsafe {
+            self.gl.BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            self.gl.Enable(gl::DEPTH_TEST);
+            self.gl.Viewport(0, 0, viewport_size.0 as i32, viewport_size.1 as i32);
+        }
+        self.levels[0].framebuffer.unbind();
+        self.vao.unbind();
+    }
+
+    // Draws `source` into `target` with `program`, which has to be in use
+    fn pass(&self, program: &Program, texel_size_loc: i32, source: &Texture2D, target: &BloomLevel) {
+        program.set_vec2_uniform(
+            texel_size_loc,
+            &Vector2::new(1.0 / source.width() as f32, 1.0 / source.height() as f32),
+        );
+        source.bind_to_unit(SOURCE_UNIT);
+
+        target.framebuffer.bind();
+        unsafe {
+            self.gl
+                .Viewport(0, 0, target.texture.width() as i32, target.texture.height() as i32);
+            self.gl.DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+    }
+}
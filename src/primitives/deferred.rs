@@ -0,0 +1,272 @@
+use failure::Error;
+
+use crate::models::mesh_registry::{MeshHandle, MeshRange, MeshRegistry};
+use crate::primitives::environment::{Environment, ENVIRONMENT_UNIT};
+use crate::primitives::light_culling::LIGHTS_BINDING;
+use crate::primitives::object_draw::{ObjectsDraw, ShadingModel};
+use crate::primitives::shadows::{ShadowSettings, SHADOW_MAPS_UNIT};
+use crate::primitives::ssao::AMBIENT_OCCLUSION_UNIT;
+use crate::primitives::triangle::VertexData;
+use crate::primitives::uniform_blocks::CAMERA_BINDING;
+use crate::render_gl::buffer::VertexArray;
+use crate::render_gl::framebuffer;
+use crate::render_gl::framebuffer::Attachment;
+use crate::render_gl::{Framebuffer, Program, RenderTarget, Renderbuffer, Texture2D, TextureFormat};
+use crate::resources::Resources;
+
+/// How lit objects are drawn
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderPath {
+    /// Every fragment is lit as it's drawn, by the lights of its screen tile
+    Forward,
+    /// Surfaces are drawn into a G-buffer first, and then every light only lights the pixels its
+    /// volume covers. Lighting costs don't grow with overdraw, but there's no MSAA and no
+    /// translucency.
+    Deferred,
+}
+
+// The G-buffer textures, in the order of their color attachments and of the gbuffer.frag outputs,
+// bound to consecutive texture units starting at GBUFFER_FIRST_UNIT while lighting
+const GBUFFER_LAYERS: [(&str, TextureFormat); 5] = [
+    // Surface color, and the material's ambient in alpha
+    ("gbuffer_albedo", TextureFormat::Rgba8),
+    ("gbuffer_normal", TextureFormat::Rgba16F),
+    // World space location, alpha is 0 where nothing was drawn
+    ("gbuffer_position", TextureFormat::Rgba32F),
+    // Metallic, roughness, specular strength and specular roughness
+    ("gbuffer_material", TextureFormat::Rgba16F),
+    ("gbuffer_emissive", TextureFormat::Rgba16F),
+];
+
+// Past the units of the shadow maps, the environment and the ambient occlusion
+const GBUFFER_FIRST_UNIT: u32 = 4;
+
+// Targets which depend on the viewport size
+struct GBuffer {
+    framebuffer: Framebuffer,
+    layers: Vec<Texture2D>,
+    // Matches the depth of RenderTarget, so that it can be copied into the scene
+    depth: Renderbuffer,
+}
+
+impl GBuffer {
+    fn new(gl: &gl::Gl, size: (u32, u32)) -> Result<GBuffer, framebuffer::Error> {
+        // Minimized windows have no size, but targets can't be empty
+        let (width, height) = (size.0.max(1), size.1.max(1));
+
+        let layers: Vec<Texture2D> = GBUFFER_LAYERS
+            .iter()
+            .map(|(_, format)| Texture2D::new(gl, width, height, *format))
+            .collect();
+        let depth = Renderbuffer::new(gl, width, height, TextureFormat::Depth24Stencil8, 0);
+
+        let framebuffer = Framebuffer::new(gl);
+        framebuffer.bind();
+        for (i, layer) in layers.iter().enumerate() {
+            framebuffer.attach_texture(Attachment::Color(i as u32), layer);
+        }
+        framebuffer.attach_renderbuffer(Attachment::DepthStencil, &depth);
+        let complete = framebuffer.check_complete();
+        framebuffer.unbind();
+        complete?;
+
+        Ok(GBuffer {
+            framebuffer,
+            layers,
+            depth,
+        })
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.depth.width(), self.depth.height())
+    }
+}
+
+/// Locations of the uniforms both lighting programs have
+struct LightingUniforms {
+    shading_model: i32,
+    environment_intensity: i32,
+}
+
+impl LightingUniforms {
+    fn new(program: &Program) -> Result<Self, Error> {
+        program.set_uniform_block_binding("Camera", CAMERA_BINDING)?;
+
+        // Each program only uses the uniforms of its own part of the lighting
+        let optional_loc = |name: &str| program.get_uniform_loc(name).unwrap_or(-1);
+
+        program.set_used();
+        for (i, (name, _)) in GBUFFER_LAYERS.iter().enumerate() {
+            program.set_int_uniform(optional_loc(name), (GBUFFER_FIRST_UNIT + i as u32) as i32);
+        }
+        program.set_int_uniform(optional_loc("environment_map"), ENVIRONMENT_UNIT as i32);
+        program.set_int_uniform(optional_loc("ambient_occlusion"), AMBIENT_OCCLUSION_UNIT as i32);
+        program.set_int_uniform(optional_loc("shadow_maps"), SHADOW_MAPS_UNIT as i32);
+
+        Ok(LightingUniforms {
+            shading_model: program.get_uniform_loc("shading_model")?,
+            environment_intensity: optional_loc("environment_intensity"),
+        })
+    }
+}
+
+/// Deferred shading. Objects draw their surfaces into a G-buffer, which is then lit in two passes
+/// adding up in the scene: a fullscreen one for the ambient light and the emissive surfaces, and
+/// one drawing a cube around every light's radius, lighting only the pixels within.
+pub struct Deferred {
+    gl: gl::Gl,
+    gbuffer: GBuffer,
+    gbuffer_draw: ObjectsDraw,
+    ambient: Program,
+    ambient_uniforms: LightingUniforms,
+    lights: Program,
+    lights_uniforms: LightingUniforms,
+    shadow_bias_loc: i32,
+    shadow_pcf_radius_loc: i32,
+    // The fullscreen triangle is generated from gl_VertexID, but drawing needs some vertex array
+    fullscreen_vao: VertexArray,
+    volume_vao: VertexArray,
+    volume: MeshRange,
+}
+
+impl Deferred {
+    /// Light volumes are drawn using the cube mesh `volume`, a unit cube around the origin
+    pub fn new(
+        res: &Resources,
+        gl: &gl::Gl,
+        meshes: &MeshRegistry,
+        volume: MeshHandle,
+        instanced: bool,
+        size: (u32, u32),
+    ) -> Result<Deferred, Error> {
+        let ambient = Program::from_res_shaders(
+            gl,
+            res,
+            "shaders/deferred_ambient",
+            &["shaders/fullscreen.vert", "shaders/deferred_ambient.frag"],
+        )?;
+        let lights = Program::from_res(gl, res, "shaders/deferred_light")?;
+        lights.set_storage_block_binding("Lights", LIGHTS_BINDING)?;
+
+        let volume_vao = VertexArray::new(gl);
+        volume_vao.bind();
+        meshes.vbo().bind();
+        meshes.ebo().bind();
+        VertexData::vertex_attrib_pointers(gl);
+        meshes.vbo().unbind();
+        volume_vao.unbind();
+
+        Ok(Deferred {
+            gl: gl.clone(),
+            gbuffer: GBuffer::new(gl, size)?,
+            gbuffer_draw: ObjectsDraw::new_gbuffer(res, gl, meshes, instanced)?,
+            ambient_uniforms: LightingUniforms::new(&ambient)?,
+            lights_uniforms: LightingUniforms::new(&lights)?,
+            shadow_bias_loc: lights.get_uniform_loc("shadow_bias")?,
+            shadow_pcf_radius_loc: lights.get_uniform_loc("shadow_pcf_radius")?,
+            ambient,
+            lights,
+            fullscreen_vao: VertexArray::new(gl),
+            volume_vao,
+            volume: meshes.range(volume),
+        })
+    }
+
+    /// Recreates the G-buffer to match a new window size
+    pub fn resize(&mut self, size: (u32, u32)) -> Result<(), framebuffer::Error> {
+        self.gbuffer = GBuffer::new(&self.gl, size)?;
+
+        Ok(())
+    }
+
+    pub fn set_shadow_settings(&self, settings: &ShadowSettings) {
+        self.lights.set_used();
+        self.lights.set_float_uniform(self.shadow_bias_loc, settings.depth_bias);
+        self.lights.set_float_uniform(self.shadow_pcf_radius_loc, settings.pcf_radius);
+    }
+
+    /// Like [`ObjectsDraw::set_shading`]
+    pub fn set_shading(&self, shading_model: ShadingModel, environment: &Environment) {
+        for (program, uniforms) in [(&self.ambient, &self.ambient_uniforms), (&self.lights, &self.lights_uniforms)] {
+            program.set_used();
+            program.set_int_uniform(uniforms.shading_model, shading_model.id());
+            program.set_float_uniform(uniforms.environment_intensity, environment.intensity);
+        }
+    }
+
+    /// Draws the G-buffer, `draw` has to draw all objects using the [`ObjectsDraw`] it's passed.
+    /// Binds the window afterwards.
+    pub(crate) fn render_gbuffer(&self, draw: impl Fn(&ObjectsDraw)) {
+        self.gbuffer.framebuffer.bind();
+        for i in 0..GBUFFER_LAYERS.len() {
+            self.gbuffer.framebuffer.clear_color(i as u32, [0.0; 4]);
+        }
+        unsafe {
+            // Alpha holds data rather than coverage
+            self.gl.Disable(gl::BLEND);
+            self.gl.Clear(gl::DEPTH_BUFFER_BIT);
+        }
+
+        draw(&self.gbuffer_draw);
+
+        unsafe {
+            self.gl.Enable(gl::BLEND);
+        }
+        self.gbuffer.framebuffer.unbind();
+    }
+
+    /// Lights the G-buffer into `scene`, which has to be bound, have no samples and be the same
+    /// size. Its depth is replaced by that of the G-buffer, so that anything drawn afterwards is
+    /// hidden behind the objects. The lights, shadow maps, environment and ambient occlusion have
+    /// to be bound, `lights_count` is the number of lights.
+    pub fn light(&self, scene: &RenderTarget, lights_count: usize) {
+        let size = self.gbuffer.size();
+        self.gbuffer
+            .framebuffer
+            .blit(Some(scene.framebuffer()), size, size, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+        scene.bind();
+
+        for (i, layer) in self.gbuffer.layers.iter().enumerate() {
+            layer.bind_to_unit(GBUFFER_FIRST_UNIT + i as u32);
+        }
+
+        unsafe {
+            self.gl.Disable(gl::DEPTH_TEST);
+        }
+
+        self.ambient.set_used();
+        self.fullscreen_vao.bind();
+        unsafe {
+            self.gl.DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+        self.fullscreen_vao.unbind();
+
+        if lights_count > 0 {
+            self.lights.set_used();
+            self.volume_vao.bind();
+            unsafe {
+                self.gl.BlendFunc(gl::ONE, gl::ONE);
+                // Back faces only, so that every covered pixel is lit once, even with the camera
+                // inside the volume
+                self.gl.Enable(gl::CULL_FACE);
+                self.gl.CullFace(gl::FRONT);
+                self.gl.DrawElementsInstancedBaseVertex(
+                    gl::TRIANGLES,
+                    self.volume.num_indices as i32,
+                    self.volume.gl_index_type(),
+                    self.volume.gl_index_offset(),
+                    lights_count as i32,
+                    self.volume.base_vertex as i32,
+                );
+                self.gl.CullFace(gl::BACK);
+                self.gl.Disable(gl::CULL_FACE);
+                self.gl.BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            }
+            self.volume_vao.unbind();
+        }
+
+        unsafe {
+            self.gl.Enable(gl::DEPTH_TEST);
+        }
+    }
+}
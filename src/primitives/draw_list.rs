@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use crate::models::material::MaterialHandle;
+use crate::models::mesh_registry::MeshHandle;
+
+/// All the objects of a [`DrawList`] which share the same mesh and material. The range of the
+/// registry's buffers the mesh occupies is looked up when drawing, as loading another mesh may
+/// change its index type.
+pub(crate) struct Batch<T> {
+    pub mesh: MeshHandle,
+    pub material: MaterialHandle,
+    pub objects: Vec<T>,
+}
+
+/// The objects drawn by a single program, grouped by their mesh and material so that every group
+/// can be drawn with as few state changes (or, when instancing, draw calls) as possible. Batches
+/// keep the order in which their mesh and material were first encountered.
+///
+/// The list is meant to be cleared and refilled every frame, which keeps the batches around so
+/// that their allocations are reused. Batches left empty are skipped.
+pub(crate) struct DrawList<T> {
+    batches: Vec<Batch<T>>,
+    batch_indices: HashMap<(MeshHandle, MaterialHandle), usize>,
+}
+
+impl<T> DrawList<T> {
+    pub fn new() -> Self {
+        DrawList {
+            batches: vec![],
+            batch_indices: HashMap::new(),
+        }
+    }
+
+    /// Removes all objects, keeping the allocations for the next ones
+    pub fn clear(&mut self) {
+        self.batches.iter_mut().for_each(|batch| batch.objects.clear());
+    }
+
+    pub fn push(&mut self, mesh: MeshHandle, material: MaterialHandle, object: T) {
+        let batches = &mut self.batches;
+        let index = *self.batch_indices.entry((mesh, material)).or_insert_with(|| {
+            batches.push(Batch {
+                mesh,
+                material,
+                objects: vec![],
+            });
+            batches.len() - 1
+        });
+
+        batches[index].objects.push(object);
+    }
+
+    pub fn batches(&self) -> impl Iterator<Item = &Batch<T>> {
+        self.batches.iter().filter(|batch| !batch.objects.is_empty())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches().next().is_none()
+    }
+}
+
+impl<T> Default for DrawList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Vector2, Vector3};
+
+    use super::DrawList;
+    use crate::models::cgi_format::IndexType;
+    use crate::models::cube::Cube;
+    use crate::models::material::{Material, MaterialRegistry};
+    use crate::models::mesh_registry::MeshRegistry;
+    use crate::primitives::light::consts::WHITE;
+    use crate::primitives::spatial::Location;
+    use crate::primitives::triangle::{Vertex, VertexData};
+
+    #[test]
+    fn objects_are_grouped_by_mesh_and_material() {
+        let mut meshes = MeshRegistry::new();
+        let cube = meshes.load("cube", || Cube::new(WHITE).verticies);
+        let other_cube = meshes.load("other cube", || Cube::new(WHITE).verticies);
+        let mut materials = MaterialRegistry::new();
+        let material = materials.add("material", Material::default());
+        let other_material = materials.add("other material", Material::default());
+
+        let mut list = DrawList::new();
+        list.push(cube, material, 0);
+        list.push(other_cube, material, 1);
+        list.push(cube, other_material, 2);
+        list.push(cube, material, 3);
+
+        let batches: Vec<_> = list
+            .batches()
+            .map(|batch| (batch.mesh, batch.material, batch.objects.clone()))
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                (cube, material, vec![0, 3]),
+                (other_cube, material, vec![1]),
+                (cube, other_material, vec![2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn batches_see_index_types_widened_after_them() {
+        let mut meshes = MeshRegistry::new();
+        let cube = meshes.load("cube", || Cube::new(WHITE).verticies);
+        let material = MaterialRegistry::new().add("material", Material::default());
+
+        let mut list = DrawList::new();
+        list.push(cube, material, ());
+        assert_eq!(meshes.range(cube).index_type, IndexType::U16);
+
+        // More unique verticies than 16-bit indices can address
+        meshes.load("large", || {
+            (0..=u16::MAX as u32 + 1)
+                .map(|i| {
+                    let vertex = Vertex {
+                        pos: Location::new(i as f32, 0.0, 0.0),
+                        clr: WHITE,
+                        uv: Vector2::zeros(),
+                    };
+                    VertexData::new(&vertex, Vector3::z())
+                })
+                .collect()
+        });
+
+        let batch = list.batches().next().unwrap();
+        let range = meshes.range(batch.mesh);
+        assert_eq!(range.index_type, IndexType::U32);
+        assert_eq!(range.first_index, 0);
+        assert_eq!(range.num_indices, meshes.get(cube).num_indices());
+    }
+
+    #[test]
+    fn cleared_lists_are_empty() {
+        let mut meshes = MeshRegistry::new();
+        let cube = meshes.load("cube", || Cube::new(WHITE).verticies);
+        let material = MaterialRegistry::new().add("material", Material::default());
+
+        let mut list = DrawList::new();
+        assert!(list.is_empty());
+        assert_eq!(list.batches().count(), 0);
+
+        list.push(cube, material, ());
+        assert!(!list.is_empty());
+
+        // Like a frame without any objects
+        list.clear();
+        assert!(list.is_empty());
+        assert_eq!(list.batches().count(), 0);
+    }
+}
@@ -0,0 +1,75 @@
+use image::{DynamicImage, ImageBuffer, Rgba};
+use nalgebra::Vector3;
+
+use crate::render_gl::{ColorSpace, Sampling, Texture, Wrap};
+
+/// The texture unit the environment map is bound to while drawing lit objects
+pub const ENVIRONMENT_UNIT: u32 = 2;
+
+/// The surroundings of the scene as an equirectangular image, with Z up. PBR shading takes its
+/// ambient light from the blurred mip levels of the environment map.
+pub struct Environment {
+    image: DynamicImage,
+    /// Scales the environment's colors
+    pub intensity: f32,
+}
+
+impl Environment {
+    /// `image` spans all directions horizontally, and from straight up to straight down
+    /// vertically, top row first. It's sRGB encoded, like an LDR photo.
+    pub fn from_image(image: DynamicImage, intensity: f32) -> Environment {
+        Environment { image, intensity }
+    }
+
+    /// A plain sky, fading from blue at the zenith to a pale horizon and a dark brown ground
+    pub fn sky(intensity: f32) -> Environment {
+        let zenith = Vector3::new(0.25, 0.45, 0.8);
+        let horizon = Vector3::new(0.8, 0.85, 0.9);
+        let ground = Vector3::new(0.25, 0.22, 0.2);
+
+        let (width, height) = (64, 32);
+        let image = ImageBuffer::from_fn(width, height, |_, y| {
+            // From 1 straight up to -1 straight down
+            let elevation = 1.0 - 2.0 * (y as f32 + 0.5) / height as f32;
+            let color = if elevation > 0.0 {
+                horizon.lerp(&zenith, elevation.sqrt())
+            } else {
+                horizon.lerp(&ground, (-elevation).sqrt())
+            };
+
+            Rgba([(color.x * 255.0) as u8, (color.y * 255.0) as u8, (color.z * 255.0) as u8, 255])
+        });
+
+        Environment::from_image(DynamicImage::ImageRgba8(image), intensity)
+    }
+
+    pub fn image(&self) -> &DynamicImage {
+        &self.image
+    }
+}
+
+/// An [`Environment`] uploaded as a texture with mipmaps
+pub struct EnvironmentMap {
+    texture: Texture,
+}
+
+impl EnvironmentMap {
+    pub fn new(gl: &gl::Gl, environment: &Environment) -> EnvironmentMap {
+        // Horizontally the image wraps around, vertically the edges are the poles
+        let sampling = Sampling {
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::ClampToEdge,
+            anisotropy: 1.0,
+            ..Sampling::default()
+        };
+
+        EnvironmentMap {
+            texture: Texture::from_image(gl, &environment.image, &sampling, ColorSpace::Srgb),
+        }
+    }
+
+    /// Binds the environment map for lit objects to sample
+    pub fn bind(&self) {
+        self.texture.bind_to_unit(ENVIRONMENT_UNIT);
+    }
+}
@@ -0,0 +1,169 @@
+use failure::Error;
+
+use crate::primitives::bloom::{Bloom, BloomSettings};
+use crate::render_gl::buffer::VertexArray;
+use crate::render_gl::framebuffer;
+use crate::render_gl::{Program, RenderTarget, TextureFormat};
+use crate::resources::Resources;
+
+/// The texture unit the resolved scene is bound to while tone mapping
+const SCENE_UNIT: u32 = 0;
+/// The texture unit the bloom is bound to while tone mapping
+const BLOOM_UNIT: u32 = 1;
+
+/// How HDR colors are squeezed into the displayable range
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// Colors above 1 are simply cut off
+    Clamp,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+impl ToneMapping {
+    // The TONE_MAPPING_* defines of tonemap.frag
+    fn id(self) -> i32 {
+        match self {
+            ToneMapping::Clamp => 0,
+            ToneMapping::Reinhard => 1,
+            ToneMapping::Aces => 2,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HdrSettings {
+    pub tone_mapping: ToneMapping,
+    /// Scene colors are multiplied by this before tone mapping
+    pub exposure: f32,
+    /// MSAA samples of the scene target, 0 turns multisampling off
+    pub samples: u32,
+    pub bloom: BloomSettings,
+}
+
+impl Default for HdrSettings {
+    fn default() -> Self {
+        HdrSettings {
+            tone_mapping: ToneMapping::Aces,
+            exposure: 1.0,
+            samples: 4,
+            bloom: BloomSettings::default(),
+        }
+    }
+}
+
+/// Renders the scene into a floating point target, so that lighting can go past 1, and then tone
+/// maps it into the window. Colors are linear throughout, the tone mapping pass encodes them as
+/// sRGB. Bright parts of the scene bloom before tone mapping.
+pub struct Hdr {
+    pub settings: HdrSettings,
+    gl: gl::Gl,
+    size: (u32, u32),
+    scene: RenderTarget,
+    resolved: RenderTarget,
+    bloom: Bloom,
+    program: Program,
+    // The fullscreen triangle is generated from gl_VertexID, but drawing needs some vertex array
+    vao: VertexArray,
+    exposure_loc: i32,
+    tone_mapping_loc: i32,
+    bloom_intensity_loc: i32,
+}
+
+impl Hdr {
+    pub fn new(res: &Resources, gl: &gl::Gl, settings: HdrSettings, size: (u32, u32)) -> Result<Hdr, Error> {
+        let (scene, resolved) = Self::targets(gl, &settings, size)?;
+
+        let bloom = Bloom::new(res, gl, &settings.bloom, size)?;
+
+        let program = Program::from_res_shaders(gl, res, "shaders/tonemap", &["shaders/fullscreen.vert", "shaders/tonemap.frag"])?;
+        let exposure_loc = program.get_uniform_loc("exposure")?;
+        let tone_mapping_loc = program.get_uniform_loc("tone_mapping")?;
+        let bloom_intensity_loc = program.get_uniform_loc("bloom_intensity")?;
+
+        program.set_used();
+        program.set_int_uniform(program.get_uniform_loc("scene")?, SCENE_UNIT as i32);
+        program.set_int_uniform(program.get_uniform_loc("bloom")?, BLOOM_UNIT as i32);
+
+        Ok(Hdr {
+            settings,
+            gl: gl.clone(),
+            size,
+            scene,
+            resolved,
+            bloom,
+            program,
+            vao: VertexArray::new(gl),
+            exposure_loc,
+            tone_mapping_loc,
+            bloom_intensity_loc,
+        })
+    }
+
+    fn targets(gl: &gl::Gl, settings: &HdrSettings, size: (u32, u32)) -> Result<(RenderTarget, RenderTarget), framebuffer::Error> {
+        // Minimized windows have no size, but targets can't be empty
+        let (width, height) = (size.0.max(1), size.1.max(1));
+
+        Ok((
+            RenderTarget::new(gl, width, height, TextureFormat::Rgba16F, settings.samples)?,
+            RenderTarget::new(gl, width, height, TextureFormat::Rgba16F, 0)?,
+        ))
+    }
+
+    /// Recreates the targets to match a new window size
+    pub fn resize(&mut self, size: (u32, u32)) -> Result<(), framebuffer::Error> {
+        let (scene, resolved) = Self::targets(&self.gl, &self.settings, size)?;
+        self.scene = scene;
+        self.resolved = resolved;
+        self.bloom.resize(&self.settings.bloom, size)?;
+        self.size = size;
+
+        Ok(())
+    }
+
+    /// The target the scene is drawn into between [`Self::begin`] and [`Self::finish`]
+    pub fn scene(&self) -> &RenderTarget {
+        &self.scene
+    }
+
+    /// Binds and clears the scene target, everything drawn until [`Self::finish`] ends up in it
+    pub fn begin(&self) {
+        self.scene.bind();
+        unsafe {
+            self.gl.Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    /// Tone maps the scene into `output`, or into the window if there's none. The output is bound
+    /// afterwards.
+    pub fn finish(&self, output: Option<&RenderTarget>) {
+        self.scene.resolve_into(&self.resolved);
+        self.scene.unbind();
+
+        let bloom = &self.settings.bloom;
+        if bloom.enabled {
+            self.bloom.render(bloom, self.resolved.color(), self.size);
+        }
+
+        self.program.set_used();
+        self.program.set_float_uniform(self.exposure_loc, self.settings.exposure);
+        self.program.set_int_uniform(self.tone_mapping_loc, self.settings.tone_mapping.id());
+        self.program
+            .set_float_uniform(self.bloom_intensity_loc, if bloom.enabled { bloom.intensity } else { 0.0 });
+        self.resolved.color().bind_to_unit(SCENE_UNIT);
+        self.bloom.texture().bind_to_unit(BLOOM_UNIT);
+
+        if let Some(output) = output {
+            output.bind();
+        }
+
+        self.vao.bind();
+        unsafe {
+            self.gl.Disable(gl::DEPTH_TEST);
+            self.gl.DrawArrays(gl::TRIANGLES, 0, 3);
+            self.gl.Enable(gl::DEPTH_TEST);
+        }
+        self.vao.unbind();
+    }
+}
@@ -1,8 +1,9 @@
-use rand::rngs::ThreadRng;
 use rand::Rng;
 
 use crate::render_gl::data::u2_u10_u10_u10_rev_float;
 
+/// Linear RGB, which is what lighting works in. Converting to sRGB only happens when the final
+/// image is written to the window.
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
     pub r: f32,
@@ -30,7 +31,7 @@ impl Color {
         Self { r, g, b, a: 1.0 }
     }
 
-    pub fn random(rng: &mut ThreadRng) -> Self {
+    pub fn random(rng: &mut impl Rng) -> Self {
         Color::new(rng.gen_range(0.0..1.00), rng.gen_range(0.0..1.00), rng.gen_range(0.0..1.00))
     }
     pub const fn new_with_alpha(r: f32, g: f32, b: f32, a: f32) -> Self {
@@ -0,0 +1,305 @@
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::primitives::spatial::Location;
+use crate::primitives::spotlight::Spotlight;
+use crate::render_gl::buffer::{BufferUsage, ShaderStorageBuffer, UniformBuffer};
+use crate::render_gl::Program;
+
+// Uniform buffer binding point of the LightGrid block
+pub const LIGHT_GRID_BINDING: u32 = 1;
+
+// Shader storage binding points, a separate set from the uniform buffer ones
+pub const LIGHTS_BINDING: u32 = 0;
+pub const LIGHT_TILES_BINDING: u32 = 1;
+pub const LIGHT_INDICES_BINDING: u32 = 2;
+
+/// Width and height of a screen tile in pixels
+pub const TILE_SIZE: u32 = 16;
+
+/// A single light in the `Lights` storage block, laid out according to std430. The radius and the
+/// shadow map layer fill the padding after the location and the color.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct LightData {
+    location: [f32; 3],
+    radius: f32,
+    color: [f32; 3],
+    /// -1 for lights without shadows
+    shadow_layer: i32,
+}
+
+/// The `LightGrid` uniform block, laid out according to std140
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct LightGridBlock {
+    tile_size: u32,
+    tiles_x: u32,
+    tiles_y: u32,
+    lights_count: u32,
+}
+
+/// The lights affecting every tile of the screen. `tiles` holds an (offset, count) pair per tile,
+/// row by row starting at the bottom left, pointing into `indices`, which holds light indices.
+pub struct LightTiles {
+    pub tiles_x: u32,
+    pub tiles_y: u32,
+    pub tiles: Vec<[u32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// Stores any number of lights in shader storage buffers, along with a per-tile list of the lights
+/// whose radius reaches into that tile, so that fragments only have to consider nearby lights.
+///
+/// The binning happens on the CPU every frame: the bounding box of every light's sphere is
+/// projected onto the screen and the light is added to all tiles it overlaps.
+pub struct LightCulling {
+    grid: UniformBuffer<LightGridBlock>,
+    lights: ShaderStorageBuffer<LightData>,
+    tiles: ShaderStorageBuffer<[u32; 2]>,
+    indices: ShaderStorageBuffer<u32>,
+}
+
+impl LightCulling {
+    pub fn new(gl: &gl::Gl) -> LightCulling {
+        let grid = UniformBuffer::new(gl);
+        grid.bind();
+        grid.allocate(1, BufferUsage::Dynamic);
+        grid.unbind();
+
+        LightCulling {
+            grid,
+            lights: ShaderStorageBuffer::new(gl),
+            tiles: ShaderStorageBuffer::new(gl),
+            indices: ShaderStorageBuffer::new(gl),
+        }
+    }
+
+    /// Points the light blocks of `program` to their binding points
+    pub fn bind_program(program: &Program) -> Result<(), failure::Error> {
+        program.set_uniform_block_binding("LightGrid", LIGHT_GRID_BINDING)?;
+        program.set_storage_block_binding("Lights", LIGHTS_BINDING)?;
+        program.set_storage_block_binding("LightTiles", LIGHT_TILES_BINDING)?;
+        program.set_storage_block_binding("LightIndices", LIGHT_INDICES_BINDING)?;
+
+        Ok(())
+    }
+
+    /// Uploads `lights`, along with the layer of their shadow map if they have one, and bins them
+    /// into the tiles of a `width` x `height` viewport, as seen through `view_projection`
+    pub(crate) fn set_spotlights<'a>(
+        &self,
+        lights: impl Iterator<Item = (&'a Spotlight, &'a Location, Option<usize>)>,
+        view_projection: &Matrix4<f32>,
+        width: u32,
+        height: u32,
+    ) {
+        let lights: Vec<LightData> = lights
+            .map(|(light, location, shadow_layer)| LightData {
+                location: [location.x, location.y, location.z],
+                radius: light.spot_radius,
+                color: [light.color.r, light.color.g, light.color.b],
+                shadow_layer: shadow_layer.map_or(-1, |layer| layer as i32),
+            })
+            .collect();
+
+        let tiles = bin_lights(
+            lights.iter().map(|light| (Vector3::from(light.location), light.radius)),
+            view_projection,
+            width,
+            height,
+        );
+
+        self.grid.bind();
+        self.grid
+            .sub_data(
+                0,
+                &[LightGridBlock {
+                    tile_size: TILE_SIZE,
+                    tiles_x: tiles.tiles_x,
+                    tiles_y: tiles.tiles_y,
+                    lights_count: lights.len() as u32,
+                }],
+            )
+            .expect("The grid buffer is allocated with room for a block");
+        self.grid.unbind();
+
+        // Zero sized buffers can't be bound to a binding point, so there's always at least one element
+        stream_non_empty(&self.lights, &lights);
+        stream_non_empty(&self.tiles, &tiles.tiles);
+        stream_non_empty(&self.indices, &tiles.indices);
+    }
+
+    /// Binds the buffers to their binding points, for all programs drawn afterwards
+    pub fn bind(&self) {
+        self.grid.bind_base(LIGHT_GRID_BINDING);
+        self.lights.bind_base(LIGHTS_BINDING);
+        self.tiles.bind_base(LIGHT_TILES_BINDING);
+        self.indices.bind_base(LIGHT_INDICES_BINDING);
+    }
+}
+
+fn stream_non_empty<T: Copy + Default>(buffer: &ShaderStorageBuffer<T>, data: &[T]) {
+    buffer.bind();
+    if data.is_empty() {
+        buffer.stream_draw_data(&[T::default()]);
+    } else {
+        buffer.stream_draw_data(data);
+    }
+    buffer.unbind();
+}
+
+/// Bins lights, given as (location, radius), into the [`TILE_SIZE`] tiles of a `width` x
+/// `height` viewport. Lights behind the camera or outside the viewport end up in no tile at all.
+pub fn bin_lights(
+    lights: impl Iterator<Item = (Vector3<f32>, f32)>,
+    view_projection: &Matrix4<f32>,
+    width: u32,
+    height: u32,
+) -> LightTiles {
+    let tiles_x = width.div_ceil(TILE_SIZE).max(1);
+    let tiles_y = height.div_ceil(TILE_SIZE).max(1);
+
+    let mut tile_lights: Vec<Vec<u32>> = vec![vec![]; (tiles_x * tiles_y) as usize];
+
+    for (i, (center, radius)) in lights.enumerate() {
+        let rect = match screen_rect(center, radius, view_projection) {
+            Some(rect) => rect,
+            None => continue,
+        };
+
+        // From normalized device coordinates to tile coordinates
+        let to_tile = |ndc: f32, tiles: u32, pixels: u32| {
+            let tile = ((ndc * 0.5 + 0.5) * pixels as f32 / TILE_SIZE as f32).floor();
+            tile.clamp(0.0, (tiles - 1) as f32) as u32
+        };
+
+        let (x0, x1) = (to_tile(rect[0], tiles_x, width), to_tile(rect[2], tiles_x, width));
+        let (y0, y1) = (to_tile(rect[1], tiles_y, height), to_tile(rect[3], tiles_y, height));
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                tile_lights[(y * tiles_x + x) as usize].push(i as u32);
+            }
+        }
+    }
+
+    let mut tiles = Vec::with_capacity(tile_lights.len());
+    let mut indices = vec![];
+    for lights in tile_lights {
+        tiles.push([indices.len() as u32, lights.len() as u32]);
+        indices.extend(lights);
+    }
+
+    LightTiles {
+        tiles_x,
+        tiles_y,
+        tiles,
+        indices,
+    }
+}
+
+/// The screen area a sphere covers as (min x, min y, max x, max y) in normalized device
+/// coordinates, conservatively taken from the projected corners of its bounding box. `None` if
+/// the sphere is entirely outside the view.
+fn screen_rect(center: Vector3<f32>, radius: f32, view_projection: &Matrix4<f32>) -> Option<[f32; 4]> {
+    let mut rect = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+    let mut behind = 0;
+
+    for corner in 0..8 {
+        let offset = Vector3::new(
+            if corner & 1 == 0 { -radius } else { radius },
+            if corner & 2 == 0 { -radius } else { radius },
+            if corner & 4 == 0 { -radius } else { radius },
+        );
+        let clip = view_projection * Vector4::new(center.x + offset.x, center.y + offset.y, center.z + offset.z, 1.0);
+
+        if clip.w <= f32::EPSILON {
+            behind += 1;
+            continue;
+        }
+
+        rect[0] = rect[0].min(clip.x / clip.w);
+        rect[1] = rect[1].min(clip.y / clip.w);
+        rect[2] = rect[2].max(clip.x / clip.w);
+        rect[3] = rect[3].max(clip.y / clip.w);
+    }
+
+    match behind {
+        8 => return None,
+        // The box crosses the camera plane, where projecting breaks down, so it may cover anything
+        1..=7 => return Some([-1.0, -1.0, 1.0, 1.0]),
+        _ => {}
+    }
+
+    if rect[0] > 1.0 || rect[1] > 1.0 || rect[2] < -1.0 || rect[3] < -1.0 {
+        return None;
+    }
+
+    Some(rect)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Matrix4, Vector3};
+
+    use super::{bin_lights, LightTiles, TILE_SIZE};
+
+    // 4 x 4 tiles
+    const SIZE: u32 = 4 * TILE_SIZE;
+
+    fn lit_tiles(tiles: &LightTiles) -> Vec<(u32, u32)> {
+        (0..tiles.tiles_y)
+            .flat_map(|y| (0..tiles.tiles_x).map(move |x| (x, y)))
+            .filter(|(x, y)| tiles.tiles[(y * tiles.tiles_x + x) as usize][1] > 0)
+            .collect()
+    }
+
+    // Looking down -z from the origin, so that anything at a positive z is behind the camera
+    fn perspective() -> Matrix4<f32> {
+        Matrix4::new_perspective(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0)
+    }
+
+    #[test]
+    fn lights_are_binned_into_every_tile_they_overlap() {
+        // With an identity view projection, world x and y are normalized device coordinates
+        let tiles = bin_lights(
+            std::iter::once((Vector3::new(0.0, 0.0, 0.0), 0.3)),
+            &Matrix4::identity(),
+            SIZE,
+            SIZE,
+        );
+
+        assert_eq!((tiles.tiles_x, tiles.tiles_y), (4, 4));
+        assert_eq!(lit_tiles(&tiles), vec![(1, 1), (2, 1), (1, 2), (2, 2)]);
+        assert_eq!(tiles.indices, vec![0; 4]);
+    }
+
+    #[test]
+    fn lights_out_of_view_are_in_no_tile() {
+        let off_screen = bin_lights(
+            std::iter::once((Vector3::new(5.0, 0.0, 0.0), 0.5)),
+            &Matrix4::identity(),
+            SIZE,
+            SIZE,
+        );
+        assert!(lit_tiles(&off_screen).is_empty());
+        assert!(off_screen.indices.is_empty());
+
+        let behind = bin_lights(std::iter::once((Vector3::new(0.0, 0.0, 10.0), 1.0)), &perspective(), SIZE, SIZE);
+        assert!(lit_tiles(&behind).is_empty());
+
+        // Around the camera, partly behind it, so it may light anything
+        let around = bin_lights(std::iter::once((Vector3::new(0.0, 0.0, 0.0), 1.0)), &perspective(), SIZE, SIZE);
+        assert_eq!(lit_tiles(&around).len(), 16);
+    }
+
+    #[test]
+    fn tiles_have_no_cap_on_their_lights() {
+        let lights = (0..5000).map(|_| (Vector3::new(-0.9, -0.9, 0.0), 0.01));
+        let tiles = bin_lights(lights, &Matrix4::identity(), SIZE, SIZE);
+
+        assert_eq!(lit_tiles(&tiles), vec![(0, 0)]);
+        assert_eq!(tiles.tiles[0], [0, 5000]);
+        assert_eq!(tiles.indices, (0..5000).collect::<Vec<u32>>());
+    }
+}
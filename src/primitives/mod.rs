@@ -1,10 +1,20 @@
+pub mod bloom;
 pub mod camera;
+pub mod deferred;
+pub mod draw_list;
+pub mod environment;
+pub mod hdr;
 pub mod input;
 pub mod light;
+pub mod light_culling;
 pub mod object_draw;
+pub mod post_process;
 pub mod projection;
+pub mod shadows;
 pub mod spatial;
 pub mod spotlight;
 pub mod spotlight_draw;
+pub mod ssao;
 pub mod time;
 pub mod triangle;
+pub mod uniform_blocks;
@@ -1,10 +1,19 @@
 use failure::Error;
-use nalgebra::{Matrix4, Vector3};
+use nalgebra::{Matrix4, Vector4};
 
-use crate::primitives::spatial::Location;
-use crate::primitives::spotlight::Spotlight;
+use crate::models::material::Material;
+use crate::models::mesh_registry::{MeshRange, MeshRegistry};
+use crate::models::texture_registry::TextureRegistry;
+use crate::primitives::environment::{Environment, ENVIRONMENT_UNIT};
+use crate::primitives::light::consts::WHITE;
+use crate::primitives::light::Color;
+use crate::primitives::light_culling::LightCulling;
+use crate::primitives::shadows::{ShadowSettings, SHADOW_MAPS_UNIT};
+use crate::primitives::ssao::AMBIENT_OCCLUSION_UNIT;
 use crate::primitives::triangle::VertexData;
+use crate::primitives::uniform_blocks::CAMERA_BINDING;
 use crate::render_gl::buffer::{ArrayBuffer, VertexArray};
+use crate::render_gl::data;
 use crate::render_gl::Program;
 use crate::resources::Resources;
 
@@ -12,129 +21,357 @@ pub struct ObjectUniforms {
     pub model_scale: i32,
     pub model_translation: i32,
     pub model_rotation: i32,
-    pub view_translation: i32,
-    pub view_rotation: i32,
-    pub view_location: i32,
-    pub projection: i32,
-    pub lights_count: i32,
-    pub light_locations: i32,
-    pub light_colors: i32,
-    pub light_radiuses: i32,
+    pub model_color: i32,
+    pub shadow_maps: i32,
+    pub shadow_bias: i32,
+    pub shadow_pcf_radius: i32,
+    pub material_base_color: i32,
+    pub material_ambient: i32,
+    pub material_specular_strength: i32,
+    pub material_specular_roughness: i32,
+    pub material_metallic: i32,
+    pub material_roughness: i32,
+    pub material_emissive: i32,
+    pub diffuse_map: i32,
+    pub shading_model: i32,
+    pub environment_map: i32,
+    pub environment_intensity: i32,
+    pub ambient_occlusion: i32,
+}
+
+// What an object program outputs, which decides the uniforms it has
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ObjectPass {
+    /// Depth, normals, or anything else which only depends on the geometry
+    Geometry,
+    /// The surface and its material, to be lit later on
+    Surface,
+    /// Fully lit colors
+    Lit,
 }
 
 impl ObjectUniforms {
-    fn new(program: &Program) -> Result<Self, Error> {
+    fn new(program: &Program, instanced: bool, pass: ObjectPass) -> Result<Self, Error> {
+        // The instanced program takes its model matrix from the per-instance attributes instead, a
+        // location of -1 makes GL silently ignore the model uniforms. The same goes for the
+        // material and lighting uniforms of the programs which don't use them.
+        let model_uniform_loc = |name: &str| if instanced { Ok(-1) } else { program.get_uniform_loc(name) };
+        let material_uniform_loc = |name: &str| {
+            if pass == ObjectPass::Geometry {
+                Ok(-1)
+            } else {
+                program.get_uniform_loc(name)
+            }
+        };
+        let lit_uniform_loc = |name: &str| {
+            if pass == ObjectPass::Lit {
+                program.get_uniform_loc(name)
+            } else {
+                Ok(-1)
+            }
+        };
+
+        // Camera and lights come from the shared buffers
+        program.set_uniform_block_binding("Camera", CAMERA_BINDING)?;
+        if pass == ObjectPass::Lit {
+            LightCulling::bind_program(program)?;
+        }
+
         Ok(Self {
-            model_scale: program.get_uniform_loc("model_scale")?,
-            model_translation: program.get_uniform_loc("model_translation")?,
-            model_rotation: program.get_uniform_loc("model_rotation")?,
-            view_rotation: program.get_uniform_loc("view_rotation")?,
-            view_translation: program.get_uniform_loc("view_translation")?,
-            view_location: program.get_uniform_loc("view_location")?,
-            projection: program.get_uniform_loc("projection")?,
-            lights_count: program.get_uniform_loc("lights_count")?,
-            light_locations: program.get_uniform_loc("light_locations")?,
-            light_colors: program.get_uniform_loc("light_colors")?,
-            light_radiuses: program.get_uniform_loc("light_radiuses")?,
+            model_scale: model_uniform_loc("model_scale")?,
+            model_translation: model_uniform_loc("model_translation")?,
+            model_rotation: model_uniform_loc("model_rotation")?,
+            // Only the passes with a surface use the color
+            model_color: if instanced { -1 } else { material_uniform_loc("model_color")? },
+            shadow_maps: lit_uniform_loc("shadow_maps")?,
+            shadow_bias: lit_uniform_loc("shadow_bias")?,
+            shadow_pcf_radius: lit_uniform_loc("shadow_pcf_radius")?,
+            material_base_color: material_uniform_loc("material_base_color")?,
+            material_ambient: material_uniform_loc("material_ambient")?,
+            material_specular_strength: material_uniform_loc("material_specular_strength")?,
+            material_specular_roughness: material_uniform_loc("material_specular_roughness")?,
+            material_metallic: material_uniform_loc("material_metallic")?,
+            material_roughness: material_uniform_loc("material_roughness")?,
+            material_emissive: material_uniform_loc("material_emissive")?,
+            diffuse_map: material_uniform_loc("diffuse_map")?,
+            shading_model: lit_uniform_loc("shading_model")?,
+            environment_map: lit_uniform_loc("environment_map")?,
+            environment_intensity: lit_uniform_loc("environment_intensity")?,
+            ambient_occlusion: lit_uniform_loc("ambient_occlusion")?,
         })
     }
 }
 
+/// Per-instance data for [`ObjectsDraw::draw_instanced`]. The model matrix is split into its
+/// columns, as a vertex attribute can't be larger than a vec4
+#[derive(VertexAttribPointers, Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct InstanceData {
+    pub model_0: data::f32_f32_f32_f32,
+    pub model_1: data::f32_f32_f32_f32,
+    pub model_2: data::f32_f32_f32_f32,
+    pub model_3: data::f32_f32_f32_f32,
+    pub clr: data::u2_u10_u10_u10_rev_float,
+}
+
+impl InstanceData {
+    pub(crate) fn new(model: &Matrix4<f32>, color: Color) -> InstanceData {
+        InstanceData {
+            model_0: model.column(0).into_owned().into(),
+            model_1: model.column(1).into_owned().into(),
+            model_2: model.column(2).into_owned().into(),
+            model_3: model.column(3).into_owned().into(),
+            clr: color.into(),
+        }
+    }
+}
+
+// The location of the first per-instance attribute, right after those of VertexData
+const INSTANCE_FIRST_LOCATION: usize = 4;
+
+/// The texture unit the diffuse map of the drawn objects is bound to
+pub const DIFFUSE_MAP_UNIT: u32 = 1;
+
+/// How lit objects react to light
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShadingModel {
+    /// Gaussian specular highlights, lights fading out linearly and a constant ambient term
+    Classic,
+    /// Metallic-roughness Cook-Torrance with inverse square falloff and ambient light from the
+    /// [`Environment`]
+    Pbr,
+}
+
+impl ShadingModel {
+    // The SHADING_* defines of triangle.frag and the deferred lighting shaders
+    pub(crate) fn id(self) -> i32 {
+        match self {
+            ShadingModel::Classic => 0,
+            ShadingModel::Pbr => 1,
+        }
+    }
+}
+
 pub struct ObjectsDraw {
     pub program: Program,
-    vbo: ArrayBuffer,
+    instance_vbo: ArrayBuffer<InstanceData>,
     vao: VertexArray,
     uniform_locs: ObjectUniforms,
 }
 
 impl ObjectsDraw {
-    pub fn new(res: &Resources, gl: &gl::Gl, verticies: Vec<VertexData>) -> Result<ObjectsDraw, failure::Error> {
+    /// Objects are drawn separately using [`Self::draw`]
+    pub fn new(res: &Resources, gl: &gl::Gl, meshes: &MeshRegistry) -> Result<ObjectsDraw, failure::Error> {
         let program = Program::from_res(gl, res, "shaders/triangle")?;
 
-        let vbo = ArrayBuffer::new(gl);
+        Self::from_program(gl, program, meshes, false, ObjectPass::Lit)
+    }
+
+    /// Objects sharing a mesh are drawn at once using [`Self::draw_instanced`]
+    pub fn new_instanced(res: &Resources, gl: &gl::Gl, meshes: &MeshRegistry) -> Result<ObjectsDraw, failure::Error> {
+        let program = Program::from_res_shaders(
+            gl,
+            res,
+            "shaders/triangle_instanced",
+            &["shaders/triangle_instanced.vert", "shaders/triangle.frag"],
+        )?;
+
+        Self::from_program(gl, program, meshes, true, ObjectPass::Lit)
+    }
+
+    /// Draws only the distance to the camera's location into the depth buffer, for rendering
+    /// shadow maps. Instanced or not, depending on `instanced`.
+    pub fn new_depth(res: &Resources, gl: &gl::Gl, meshes: &MeshRegistry, instanced: bool) -> Result<ObjectsDraw, failure::Error> {
+        let program = if instanced {
+            Program::from_res_shaders(
+                gl,
+                res,
+                "shaders/shadow_depth_instanced",
+                &["shaders/triangle_instanced.vert", "shaders/shadow_depth.frag"],
+            )?
+        } else {
+            Program::from_res_shaders(
+                gl,
+                res,
+                "shaders/shadow_depth",
+                &["shaders/triangle.vert", "shaders/shadow_depth.frag"],
+            )?
+        };
+
+        Self::from_program(gl, program, meshes, instanced, ObjectPass::Geometry)
+    }
+
+    /// Draws view space normals and depth, for screen-space effects like SSAO. Instanced or not,
+    /// depending on `instanced`.
+    pub fn new_normals(res: &Resources, gl: &gl::Gl, meshes: &MeshRegistry, instanced: bool) -> Result<ObjectsDraw, failure::Error> {
+        let program = if instanced {
+            Program::from_res_shaders(
+                gl,
+                res,
+                "shaders/view_normals_instanced",
+                &["shaders/triangle_instanced.vert", "shaders/view_normals.frag"],
+            )?
+        } else {
+            Program::from_res_shaders(
+                gl,
+                res,
+                "shaders/view_normals",
+                &["shaders/triangle.vert", "shaders/view_normals.frag"],
+            )?
+        };
+
+        Self::from_program(gl, program, meshes, instanced, ObjectPass::Geometry)
+    }
+
+    /// Draws the surfaces of objects into the G-buffer of deferred shading, without lighting them.
+    /// Instanced or not, depending on `instanced`.
+    pub fn new_gbuffer(res: &Resources, gl: &gl::Gl, meshes: &MeshRegistry, instanced: bool) -> Result<ObjectsDraw, failure::Error> {
+        let program = if instanced {
+            Program::from_res_shaders(
+                gl,
+                res,
+                "shaders/gbuffer_instanced",
+                &["shaders/triangle_instanced.vert", "shaders/gbuffer.frag"],
+            )?
+        } else {
+            Program::from_res_shaders(gl, res, "shaders/gbuffer", &["shaders/triangle.vert", "shaders/gbuffer.frag"])?
+        };
+
+        Self::from_program(gl, program, meshes, instanced, ObjectPass::Surface)
+    }
+
+    fn from_program(
+        gl: &gl::Gl,
+        program: Program,
+        meshes: &MeshRegistry,
+        instanced: bool,
+        pass: ObjectPass,
+    ) -> Result<ObjectsDraw, failure::Error> {
+        let instance_vbo = ArrayBuffer::new(gl);
         let vao = VertexArray::new(gl);
 
         vao.bind();
-        vbo.bind();
+        meshes.vbo().bind();
+        meshes.ebo().bind();
         VertexData::vertex_attrib_pointers(gl);
-        vbo.unbind();
+        if instanced {
+            instance_vbo.bind();
+            InstanceData::instance_attrib_pointers(gl, INSTANCE_FIRST_LOCATION);
+            instance_vbo.unbind();
+        }
+        meshes.vbo().unbind();
         vao.unbind();
 
-        let uniform_locs = ObjectUniforms::new(&program)?;
+        let uniform_locs = ObjectUniforms::new(&program, instanced, pass)?;
 
         let objects_draw = ObjectsDraw {
             program,
-            vbo,
+            instance_vbo,
             vao,
 
             uniform_locs,
         };
 
         objects_draw.program.set_used();
-
-        objects_draw.vbo.bind();
-        objects_draw.vbo.static_draw_data(&verticies);
+        objects_draw
+            .program
+            .set_int_uniform(objects_draw.uniform_locs.diffuse_map, DIFFUSE_MAP_UNIT as i32);
+        objects_draw
+            .program
+            .set_int_uniform(objects_draw.uniform_locs.environment_map, ENVIRONMENT_UNIT as i32);
+        objects_draw
+            .program
+            .set_int_uniform(objects_draw.uniform_locs.ambient_occlusion, AMBIENT_OCCLUSION_UNIT as i32);
+        objects_draw.set_color(&WHITE);
 
         Ok(objects_draw)
     }
 
+    pub fn set_shadow_settings(&self, settings: &ShadowSettings) {
+        self.program.set_used();
+        self.program.set_int_uniform(self.uniform_locs.shadow_maps, SHADOW_MAPS_UNIT as i32);
+        self.program.set_float_uniform(self.uniform_locs.shadow_bias, settings.depth_bias);
+        self.program
+            .set_float_uniform(self.uniform_locs.shadow_pcf_radius, settings.pcf_radius);
+    }
+
+    /// Shades with `shading_model`, taking ambient light from `environment` for PBR shading. The
+    /// environment itself has to be bound while drawing.
+    pub fn set_shading(&self, shading_model: ShadingModel, environment: &Environment) {
+        self.program.set_used();
+        self.program.set_int_uniform(self.uniform_locs.shading_model, shading_model.id());
+        self.program
+            .set_float_uniform(self.uniform_locs.environment_intensity, environment.intensity);
+    }
+
+    /// Draws all following objects with `material`, untextured materials sample the white texture.
+    /// Has to be called after [`Self::prepare_for_draws`].
+    pub fn set_material(&self, material: &Material, textures: &TextureRegistry) {
+        let color = |color: &Color| Vector4::new(color.r, color.g, color.b, color.a);
+
+        self.program
+            .set_vec4_uniform(self.uniform_locs.material_base_color, &color(&material.base_color));
+        self.program.set_float_uniform(self.uniform_locs.material_ambient, material.ambient);
+        self.program
+            .set_float_uniform(self.uniform_locs.material_specular_strength, material.specular_strength);
+        self.program
+            .set_float_uniform(self.uniform_locs.material_specular_roughness, material.specular_roughness);
+        self.program
+            .set_float_uniform(self.uniform_locs.material_metallic, material.metallic);
+        self.program
+            .set_float_uniform(self.uniform_locs.material_roughness, material.roughness);
+        self.program
+            .set_vec3_uniform(self.uniform_locs.material_emissive, &color(&material.emissive).xyz());
+
+        textures
+            .get(material.diffuse_map.unwrap_or_else(|| textures.white()))
+            .bind_to_unit(DIFFUSE_MAP_UNIT);
+    }
+
+    /// Tints the vertex colors of all following [`Self::draw`]s with `color`, which starts out
+    /// white. Instanced draws take their color from [`InstanceData`] instead.
+    pub fn set_color(&self, color: &Color) {
+        self.program.set_used();
+        self.program
+            .set_vec4_uniform(self.uniform_locs.model_color, &Vector4::new(color.r, color.g, color.b, color.a));
+    }
+
     pub fn prepare_for_draws(&self) {
         self.program.set_used();
-        self.vbo.bind();
         self.vao.bind();
     }
 
-    pub fn draw(
-        &self,
-        gl: &gl::Gl,
-        model_scale: f32,
-        model_translation: &Matrix4<f32>,
-        model_rotation: &Matrix4<f32>,
-        num_vertices: usize,
-        offset: usize,
-    ) {
+    pub fn draw(&self, gl: &gl::Gl, model_scale: f32, model_translation: &Matrix4<f32>, model_rotation: &Matrix4<f32>, range: &MeshRange) {
         self.program.set_float_uniform(self.uniform_locs.model_scale, model_scale);
         self.program
             .set_mat4_uniform(self.uniform_locs.model_translation, model_translation);
         self.program.set_mat4_uniform(self.uniform_locs.model_rotation, model_rotation);
 
         unsafe {
-            gl.DrawArrays(gl::TRIANGLES, offset as i32, num_vertices as i32);
+            gl.DrawElementsBaseVertex(
+                gl::TRIANGLES,
+                range.num_indices as i32,
+                range.gl_index_type(),
+                range.gl_index_offset(),
+                range.base_vertex as i32,
+            );
         }
     }
 
-    pub(crate) fn set_spotlights<'a>(&self, lights: impl Iterator<Item = (&'a Spotlight, &'a Location)>) {
-        self.program.set_used();
+    /// Draws the mesh in `range` once for every instance in `instances`, in a single draw call
+    pub fn draw_instanced(&self, gl: &gl::Gl, instances: &[InstanceData], range: &MeshRange) {
+        self.instance_vbo.bind();
+        self.instance_vbo.stream_draw_data(instances);
 
-        let mut lights_count = 0;
-        lights.enumerate().for_each(|(i, (light, location))| {
-            self.program.set_vec3_array_uniform(
-                self.uniform_locs.light_locations,
-                i,
-                &Vector3::new(location.x, location.y, location.z),
-            );
-            self.program.set_vec3_array_uniform(
-                self.uniform_locs.light_colors,
-                i,
-                &Vector3::new(light.color.r, light.color.g, light.color.b),
+        unsafe {
+            gl.DrawElementsInstancedBaseVertex(
+                gl::TRIANGLES,
+                range.num_indices as i32,
+                range.gl_index_type(),
+                range.gl_index_offset(),
+                instances.len() as i32,
+                range.base_vertex as i32,
             );
-            self.program
-                .set_float_array_uniform(self.uniform_locs.light_radiuses, i, light.spot_radius);
-            lights_count += 1;
-        });
-
-        self.program.set_uint_uniform(self.uniform_locs.lights_count, lights_count);
-    }
-
-    pub fn set_view(&self, view_rotation: &Matrix4<f32>, view_translation: &Matrix4<f32>, view_location: &Vector3<f32>) {
-        self.program.set_used();
-        self.program.set_mat4_uniform(self.uniform_locs.view_rotation, view_rotation);
-        self.program.set_mat4_uniform(self.uniform_locs.view_translation, view_translation);
-        self.program.set_vec3_uniform(self.uniform_locs.view_location, view_location);
-    }
-
-    pub fn set_projection(&self, projection: &Matrix4<f32>) {
-        self.program.set_used();
-        self.program.set_mat4_uniform(self.uniform_locs.projection, projection);
+        }
     }
 }
@@ -0,0 +1,477 @@
+use std::cell::Cell;
+
+use nalgebra::{Vector2, Vector3, Vector4};
+
+use crate::render_gl;
+use crate::render_gl::buffer::VertexArray;
+use crate::render_gl::framebuffer;
+use crate::render_gl::{ColorSpace, Filter, Program, RenderTarget, Sampling, Texture, TextureFormat, Wrap};
+use crate::resources;
+use crate::resources::Resources;
+
+/// The texture unit the output of the previous pass is bound to, textures declared by a pass
+/// follow it
+const SOURCE_UNIT: u32 = 0;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Failed to load resource {}", name)]
+    ResourceLoad {
+        name: String,
+        #[cause]
+        inner: resources::Error,
+    },
+    #[fail(display = "Resource {} is not valid UTF-8", name)]
+    InvalidUtf8 { name: String },
+    #[fail(display = "Line {}: unknown statement \"{}\"", line, keyword)]
+    UnknownStatement { line: usize, keyword: String },
+    #[fail(display = "Line {}: \"{}\" has to follow a pass", line, keyword)]
+    OutsidePass { line: usize, keyword: String },
+    #[fail(display = "Line {}: expected {} after \"{}\"", line, expected, keyword)]
+    WrongValueCount {
+        line: usize,
+        keyword: String,
+        expected: &'static str,
+    },
+    #[fail(display = "Line {}: failed to parse number \"{}\"", line, value)]
+    InvalidNumber { line: usize, value: String },
+    #[fail(display = "Failed to build post-processing pass {}", name)]
+    Program {
+        name: String,
+        #[cause]
+        inner: render_gl::Error,
+    },
+    #[fail(display = "Failed to decode image {}", name)]
+    Decode {
+        name: String,
+        #[cause]
+        inner: image::ImageError,
+    },
+    #[fail(display = "Failed to create the post-processing targets")]
+    Framebuffer(#[cause] framebuffer::Error),
+}
+
+impl From<framebuffer::Error> for Error {
+    fn from(other: framebuffer::Error) -> Self {
+        Error::Framebuffer(other)
+    }
+}
+
+/// A pass as declared in the configuration
+#[derive(Debug, Clone)]
+pub struct PassConfig {
+    /// Name of the fragment shader in `shaders/post`, which also names the pass
+    pub shader: String,
+    pub enabled: bool,
+    /// Uniforms with 1 to 4 floats each, overriding the defaults in the shader
+    pub params: Vec<(String, Vec<f32>)>,
+    /// Sampler uniforms and the image resources they sample
+    pub textures: Vec<(String, String)>,
+}
+
+/// The passes of a [`PostProcessChain`], in the order they run in. Parsed from a text file of
+/// statements, one per line, with `#` starting comments:
+///
+/// ```text
+/// pass <shader> [off]             starts a pass, which is disabled until toggled with "off"
+/// param <uniform> <1 to 4 floats>
+/// texture <uniform> <image resource>
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PostProcessConfig {
+    pub passes: Vec<PassConfig>,
+}
+
+impl PostProcessConfig {
+    pub fn from_res(res: &Resources, name: &str) -> Result<PostProcessConfig, Error> {
+        let bytes = res.load_bytes(name).map_err(|e| Error::ResourceLoad {
+            name: name.into(),
+            inner: e,
+        })?;
+        let source = String::from_utf8(bytes).map_err(|_| Error::InvalidUtf8 { name: name.into() })?;
+
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<PostProcessConfig, Error> {
+        let mut passes: Vec<PassConfig> = vec![];
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let mut tokens = line.split('#').next().unwrap_or_default().split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let args: Vec<&str> = tokens.collect();
+
+            let wrong_count = |expected| Error::WrongValueCount {
+                line: line_number,
+                keyword: keyword.to_string(),
+                expected,
+            };
+
+            if keyword == "pass" {
+                let enabled = match args[..] {
+                    [_] => true,
+                    [_, "off"] => false,
+                    _ => return Err(wrong_count("a shader name, optionally followed by \"off\"")),
+                };
+                passes.push(PassConfig {
+                    shader: args[0].to_string(),
+                    enabled,
+                    params: vec![],
+                    textures: vec![],
+                });
+                continue;
+            }
+
+            let pass = passes.last_mut().ok_or_else(|| Error::OutsidePass {
+                line: line_number,
+                keyword: keyword.to_string(),
+            })?;
+
+            match keyword {
+                "param" => {
+                    if args.len() < 2 || args.len() > 5 {
+                        return Err(wrong_count("a uniform name and 1 to 4 numbers"));
+                    }
+                    let values = args[1..]
+                        .iter()
+                        .map(|arg| {
+                            arg.parse::<f32>().map_err(|_| Error::InvalidNumber {
+                                line: line_number,
+                                value: arg.to_string(),
+                            })
+                        })
+                        .collect::<Result<Vec<f32>, Error>>()?;
+                    pass.params.push((args[0].to_string(), values));
+                }
+                "texture" => {
+                    if args.len() != 2 {
+                        return Err(wrong_count("a uniform name and an image resource"));
+                    }
+                    pass.textures.push((args[0].to_string(), args[1].to_string()));
+                }
+                _ => {
+                    return Err(Error::UnknownStatement {
+                        line: line_number,
+                        keyword: keyword.to_string(),
+                    })
+                }
+            }
+        }
+
+        Ok(PostProcessConfig { passes })
+    }
+}
+
+/// A fullscreen pass: a fragment shader reading the output of the previous pass from the `source`
+/// sampler. Passes may also use `texel_size`, the size of a source pixel in UV units, and
+/// `frame`, which counts up every frame.
+pub struct PostProcessPass {
+    name: String,
+    pub enabled: bool,
+    program: Program,
+    params: Vec<(i32, Vec<f32>)>,
+    textures: Vec<Texture>,
+    // -1 if the shader doesn't use them
+    texel_size_loc: i32,
+    frame_loc: i32,
+}
+
+impl PostProcessPass {
+    fn new(res: &Resources, gl: &gl::Gl, config: &PassConfig) -> Result<PostProcessPass, Error> {
+        let program_error = |inner| Error::Program {
+            name: config.shader.clone(),
+            inner,
+        };
+
+        let name = format!("shaders/post/{}", config.shader);
+        let program = Program::from_res_shaders(gl, res, &name, &["shaders/fullscreen.vert".to_string(), format!("{}.frag", name)])
+            .map_err(program_error)?;
+
+        let params = config
+            .params
+            .iter()
+            .map(|(uniform, values)| Ok((program.get_uniform_loc(uniform)?, values.clone())))
+            .collect::<Result<Vec<_>, render_gl::Error>>()
+            .map_err(program_error)?;
+
+        // Lookup tables and the like are data rather than colors, interpolated but never blurred
+        let sampling = Sampling {
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mipmaps: false,
+            wrap_u: Wrap::ClampToEdge,
+            wrap_v: Wrap::ClampToEdge,
+            anisotropy: 1.0,
+        };
+
+        program.set_used();
+        program.set_int_uniform(program.get_uniform_loc("source").map_err(program_error)?, SOURCE_UNIT as i32);
+
+        let mut textures = vec![];
+        for (i, (uniform, resource)) in config.textures.iter().enumerate() {
+            let bytes = res.load_bytes(resource).map_err(|e| Error::ResourceLoad {
+                name: resource.clone(),
+                inner: e,
+            })?;
+            let image = image::load_from_memory(&bytes).map_err(|e| Error::Decode {
+                name: resource.clone(),
+                inner: e,
+            })?;
+
+            let loc = program.get_uniform_loc(uniform).map_err(program_error)?;
+            program.set_int_uniform(loc, (SOURCE_UNIT + 1 + i as u32) as i32);
+            textures.push(Texture::from_image(gl, &image, &sampling, ColorSpace::Linear));
+        }
+
+        Ok(PostProcessPass {
+            name: config.shader.clone(),
+            enabled: config.enabled,
+            texel_size_loc: program.get_uniform_loc("texel_size").unwrap_or(-1),
+            frame_loc: program.get_uniform_loc("frame").unwrap_or(-1),
+            program,
+            params,
+            textures,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prepare(&self, size: (u32, u32), frame: u32) {
+        self.program.set_used();
+
+        for (loc, values) in &self.params {
+            match values[..] {
+                [x] => self.program.set_float_uniform(*loc, x),
+                [x, y] => self.program.set_vec2_uniform(*loc, &Vector2::new(x, y)),
+                [x, y, z] => self.program.set_vec3_uniform(*loc, &Vector3::new(x, y, z)),
+                [x, y, z, w] => self.program.set_vec4_uniform(*loc, &Vector4::new(x, y, z, w)),
+                _ => unreachable!("the configuration has 1 to 4 values per param"),
+            }
+        }
+        self.program
+            .set_vec2_uniform(self.texel_size_loc, &Vector2::new(1.0 / size.0 as f32, 1.0 / size.1 as f32));
+        self.program.set_float_uniform(self.frame_loc, frame as f32);
+
+        for (i, texture) in self.textures.iter().enumerate() {
+            texture.bind_to_unit(SOURCE_UNIT + 1 + i as u32);
+        }
+    }
+}
+
+/// Runs an ordered list of fullscreen passes over the finished image. The image is drawn into
+/// [`Self::input`], each enabled pass then reads the output of the previous one, and the last
+/// pass draws into the final output.
+pub struct PostProcessChain {
+    gl: gl::Gl,
+    size: (u32, u32),
+    passes: Vec<PostProcessPass>,
+    // Passes alternate between reading one and drawing into the other
+    targets: [RenderTarget; 2],
+    // The fullscreen triangle is generated from gl_VertexID, but drawing needs some vertex array
+    vao: VertexArray,
+    frame: Cell<u32>,
+}
+
+impl PostProcessChain {
+    pub fn new(res: &Resources, gl: &gl::Gl, config: &PostProcessConfig, size: (u32, u32)) -> Result<PostProcessChain, Error> {
+        let passes = config
+            .passes
+            .iter()
+            .map(|pass| PostProcessPass::new(res, gl, pass))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(PostProcessChain {
+            gl: gl.clone(),
+            size,
+            passes,
+            targets: Self::targets(gl, size)?,
+            vao: VertexArray::new(gl),
+            frame: Cell::new(0),
+        })
+    }
+
+    fn targets(gl: &gl::Gl, size: (u32, u32)) -> Result<[RenderTarget; 2], framebuffer::Error> {
+        // Minimized windows have no size, but targets can't be empty
+        let (width, height) = (size.0.max(1), size.1.max(1));
+
+        Ok([
+            RenderTarget::new(gl, width, height, TextureFormat::Rgba8, 0)?,
+            RenderTarget::new(gl, width, height, TextureFormat::Rgba8, 0)?,
+        ])
+    }
+
+    /// Recreates the targets to match a new window size
+    pub fn resize(&mut self, size: (u32, u32)) -> Result<(), framebuffer::Error> {
+        self.targets = Self::targets(&self.gl, size)?;
+        self.size = size;
+
+        Ok(())
+    }
+
+    pub fn passes(&self) -> &[PostProcessPass] {
+        &self.passes
+    }
+
+    /// Flips whether the pass at `index` runs, returns whether it does now, or `None` if there's
+    /// no such pass
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        self.passes.get_mut(index).map(|pass| {
+            pass.enabled = !pass.enabled;
+            pass.enabled
+        })
+    }
+
+    /// The target to draw the image into, or `None` if no pass is enabled and the image should
+    /// go straight into the window
+    pub fn input(&self) -> Option<&RenderTarget> {
+        if self.passes.iter().any(|pass| pass.enabled) {
+            Some(&self.targets[0])
+        } else {
+            None
+        }
+    }
+
+    /// Runs the enabled passes over [`Self::input`], the last into `output`, or into the window if
+    /// there's none. The output is bound afterwards.
+    pub fn run(&self, output: Option<&RenderTarget>) {
+        let frame = self.frame.get().wrapping_add(1);
+        self.frame.set(frame);
+
+        let mut enabled = self.passes.iter().filter(|pass| pass.enabled).peekable();
+        if enabled.peek().is_none() {
+            return;
+        }
+
+        self.vao.bind();
+        unsafe {
+            // Passes replace what's in their target, even where they output translucent colors
+            self.gl.Disable(gl::DEPTH_TEST);
+            self.gl.Disable(gl::BLEND);
+        }
+
+        let mut current = 0;
+        while let Some(pass) = enabled.next() {
+            pass.prepare(self.size, frame);
+            self.targets[current].color().bind_to_unit(SOURCE_UNIT);
+
+            if enabled.peek().is_some() {
+                self.targets[1 - current].bind();
+                current = 1 - current;
+            } else if let Some(output) = output {
+                output.bind();
+            } else {
+                self.targets[current].unbind();
+            }
+
+            unsafe {
+                self.gl.DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+        }
+
+        unsafe {
+            self.gl.Enable(gl::BLEND);
+            self.gl.Enable(gl::DEPTH_TEST);
+        }
+        self.vao.unbind();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, PostProcessConfig};
+
+    #[test]
+    fn passes_keep_their_order_and_statements() {
+        let config = PostProcessConfig::parse(
+            "# color grading first\n\
+             pass grade\n\
+             param exposure 1.5\n\
+             param tint 1 0.5 0.25 1   # rgba\n\
+             texture lut luts/warm.png\n\
+             \n\
+             pass vignette off\n\
+             param strength 0.3\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.passes.len(), 2);
+
+        let grade = &config.passes[0];
+        assert_eq!(grade.shader, "grade");
+        assert!(grade.enabled);
+        assert_eq!(
+            grade.params,
+            vec![("exposure".to_string(), vec![1.5]), ("tint".to_string(), vec![1.0, 0.5, 0.25, 1.0])]
+        );
+        assert_eq!(grade.textures, vec![("lut".to_string(), "luts/warm.png".to_string())]);
+
+        let vignette = &config.passes[1];
+        assert_eq!(vignette.shader, "vignette");
+        assert!(!vignette.enabled);
+        assert_eq!(vignette.params, vec![("strength".to_string(), vec![0.3])]);
+        assert!(vignette.textures.is_empty());
+    }
+
+    #[test]
+    fn empty_configs_have_no_passes() {
+        assert!(PostProcessConfig::parse("").unwrap().passes.is_empty());
+        assert!(PostProcessConfig::parse("# nothing yet\n\n").unwrap().passes.is_empty());
+    }
+
+    #[test]
+    fn passes_need_a_shader_and_at_most_off() {
+        for source in ["pass", "pass grade on", "pass grade off again"] {
+            match PostProcessConfig::parse(source) {
+                Err(Error::WrongValueCount { line: 1, keyword, .. }) => assert_eq!(keyword, "pass"),
+                other => panic!("{:?} parsed as {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn params_need_a_name_and_1_to_4_numbers() {
+        for source in ["pass grade\nparam exposure", "pass grade\nparam tint 1 1 1 1 1"] {
+            match PostProcessConfig::parse(source) {
+                Err(Error::WrongValueCount { line: 2, keyword, .. }) => assert_eq!(keyword, "param"),
+                other => panic!("{:?} parsed as {:?}", source, other),
+            }
+        }
+
+        match PostProcessConfig::parse("pass grade\nparam tint 1 half 1") {
+            Err(Error::InvalidNumber { line: 2, value }) => assert_eq!(value, "half"),
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn textures_need_a_name_and_an_image() {
+        for source in ["pass grade\ntexture lut", "pass grade\ntexture lut a.png b.png"] {
+            match PostProcessConfig::parse(source) {
+                Err(Error::WrongValueCount { line: 2, keyword, .. }) => assert_eq!(keyword, "texture"),
+                other => panic!("{:?} parsed as {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn statements_have_to_follow_a_pass() {
+        match PostProcessConfig::parse("# no pass yet\nparam exposure 1") {
+            Err(Error::OutsidePass { line: 2, keyword }) => assert_eq!(keyword, "param"),
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_statements_are_errors() {
+        match PostProcessConfig::parse("pass grade\n\nuniform exposure 1") {
+            Err(Error::UnknownStatement { line: 3, keyword }) => assert_eq!(keyword, "uniform"),
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::f32::consts::TAU;
+
+use nalgebra::{Matrix4, Perspective3, Point3, Translation3, Vector3};
+
+use crate::models::mesh_registry::MeshRegistry;
+use crate::primitives::object_draw::ObjectsDraw;
+use crate::primitives::spatial::Location;
+use crate::primitives::spotlight::Spotlight;
+use crate::primitives::uniform_blocks::UniformBlocks;
+use crate::render_gl::{Framebuffer, Texture};
+use crate::resources::Resources;
+
+/// The texture unit the shadow maps are bound to while drawing lit objects
+pub const SHADOW_MAPS_UNIT: u32 = 0;
+
+// Near plane of the shadow map projections, anything closer to a light doesn't cast shadows
+const NEAR: f32 = 0.05;
+
+// Direction and up vector of every cube map face, in the order of the layer-faces
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+#[derive(Debug, Copy, Clone)]
+pub struct ShadowSettings {
+    /// At most this many lights get a shadow map, the first ones which have `casts_shadows` set
+    pub max_casters: usize,
+    /// Width and height of every cube map face in texels
+    pub resolution: u32,
+    /// Subtracted from the distance between a fragment and the light before comparing it with the
+    /// shadow map, in world units. Too little causes shadow acne, too much detaches shadows.
+    pub depth_bias: f32,
+    /// Spread of the PCF samples, relative to the distance between a fragment and the light.
+    /// Zero gives hard shadows.
+    pub pcf_radius: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            max_casters: 4,
+            resolution: 1024,
+            depth_bias: 0.05,
+            pcf_radius: 0.01,
+        }
+    }
+}
+
+/// Omnidirectional shadows for spotlights. Every shadow casting light gets a layer of a cube map
+/// array, with the distance to the light in each direction rendered into the six faces.
+pub struct ShadowMaps {
+    pub settings: ShadowSettings,
+    texture: Texture,
+    framebuffer: Framebuffer,
+    depth_draw: ObjectsDraw,
+}
+
+impl ShadowMaps {
+    pub fn new(
+        res: &Resources,
+        gl: &gl::Gl,
+        meshes: &MeshRegistry,
+        settings: ShadowSettings,
+        instanced: bool,
+    ) -> Result<ShadowMaps, failure::Error> {
+        let texture = Texture::new_depth_cube_array(gl, settings.resolution, settings.max_casters as u32);
+
+        let framebuffer = Framebuffer::new(gl);
+        framebuffer.bind();
+        framebuffer.attach_depth_layer(&texture, 0);
+        framebuffer.disable_color();
+        let complete = framebuffer.check_complete();
+        framebuffer.unbind();
+        complete?;
+
+        Ok(ShadowMaps {
+            settings,
+            texture,
+            framebuffer,
+            depth_draw: ObjectsDraw::new_depth(res, gl, meshes, instanced)?,
+        })
+    }
+
+    /// The shadow map layer of every light, `None` for lights without shadows
+    pub(crate) fn assign_layers<'a>(&self, lights: impl Iterator<Item = &'a Spotlight>) -> Vec<Option<usize>> {
+        let mut casters = 0;
+
+        lights
+            .map(|light| {
+                if light.casts_shadows && casters < self.settings.max_casters {
+                    casters += 1;
+                    Some(casters - 1)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Renders the shadow maps of `casters`, given as (location, radius, layer). `draw` has to draw
+    /// all shadow casting objects using the [`ObjectsDraw`] it's passed.
+    ///
+    /// Overwrites the camera uniform block and the viewport, the viewport is restored to
+    /// `viewport_size` afterwards.
+    pub(crate) fn render(
+        &self,
+        gl: &gl::Gl,
+        uniform_blocks: &UniformBlocks,
+        casters: impl Iterator<Item = (Location, f32, usize)>,
+        viewport_size: (u32, u32),
+        draw: impl Fn(&ObjectsDraw),
+    ) {
+        self.framebuffer.bind();
+        unsafe {
+            gl.Viewport(0, 0, self.settings.resolution as i32, self.settings.resolution as i32);
+        }
+
+        for (location, radius, layer) in casters {
+            let location: Vector3<f32> = location.into();
+            // The far plane is where the light's attenuation reaches zero
+            let projection = Perspective3::new(1.0, TAU / 4.0, NEAR, radius.max(NEAR * 2.0)).to_homogeneous();
+            let translation = Translation3::from(-location).to_homogeneous();
+
+            for (face, (direction, up)) in CUBE_FACES.iter().enumerate() {
+                let rotation = Matrix4::look_at_rh(&Point3::origin(), &Point3::from(Vector3::from(*direction)), &Vector3::from(*up));
+
+                uniform_blocks.set_camera(&rotation, &translation, &location, &projection);
+                self.framebuffer.attach_depth_layer(&self.texture, (layer * 6 + face) as u32);
+                unsafe {
+                    gl.Clear(gl::DEPTH_BUFFER_BIT);
+                }
+
+                draw(&self.depth_draw);
+            }
+        }
+
+        self.framebuffer.unbind();
+        unsafe {
+            gl.Viewport(0, 0, viewport_size.0 as i32, viewport_size.1 as i32);
+        }
+    }
+
+    /// Binds the shadow maps for lit objects to sample
+    pub fn bind(&self) {
+        self.texture.bind_to_unit(SHADOW_MAPS_UNIT);
+    }
+}
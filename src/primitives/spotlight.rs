@@ -1,10 +1,11 @@
-use crate::models::cube::Cube;
 use crate::primitives::light::Color;
 
+#[derive(Debug, Copy, Clone)]
 pub struct Spotlight {
-    pub(crate) cube: Cube,
     pub(crate) color: Color,
     pub(crate) spot_radius: f32,
+    /// Whether the light gets a shadow map, as long as the shadow budget allows
+    pub(crate) casts_shadows: bool,
 }
 
 pub fn spot_radius_to_cube_scale(spot_radius: f32) -> f32 {
@@ -14,12 +15,17 @@ pub fn spot_radius_to_cube_scale(spot_radius: f32) -> f32 {
 impl Spotlight {
     pub(crate) fn new(color: Color, spot_radius: f32) -> Self {
         Self {
-            cube: Cube::new(color),
             spot_radius,
             color,
+            casts_shadows: false,
         }
     }
 
+    pub(crate) fn with_shadows(mut self) -> Self {
+        self.casts_shadows = true;
+        self
+    }
+
     #[allow(dead_code)]
     fn set_color(&mut self, color: Color) {
         self.color = color;
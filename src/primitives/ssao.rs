@@ -0,0 +1,276 @@
+use failure::Error;
+use image::{DynamicImage, ImageBuffer, Rgba};
+use nalgebra::{Matrix4, Vector2, Vector3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::models::mesh_registry::MeshRegistry;
+use crate::primitives::object_draw::ObjectsDraw;
+use crate::render_gl::buffer::VertexArray;
+use crate::render_gl::framebuffer;
+use crate::render_gl::framebuffer::Attachment;
+use crate::render_gl::{ColorSpace, Filter, Framebuffer, Program, RenderTarget, Sampling, Texture, Texture2D, TextureFormat, Wrap};
+use crate::resources::Resources;
+
+/// The texture unit the ambient occlusion is bound to while drawing lit objects
+pub const AMBIENT_OCCLUSION_UNIT: u32 = 3;
+
+// Texture units of the SSAO pass, and of the blur pass' source
+const DEPTH_UNIT: u32 = 0;
+const NORMALS_UNIT: u32 = 1;
+const NOISE_UNIT: u32 = 2;
+
+/// The most kernel samples ssao.frag has room for
+pub const MAX_SAMPLES: usize = 64;
+
+// Width and height of the tiled noise texture, which the blur has to cover to hide the tiling
+const NOISE_SIZE: u32 = 4;
+
+#[derive(Debug, Copy, Clone)]
+pub struct SsaoSettings {
+    /// Without occlusion, ambient light reaches everywhere equally
+    pub enabled: bool,
+    /// Samples per pixel, up to [`MAX_SAMPLES`]
+    pub samples: usize,
+    /// How far around a fragment to look for occluders, in world units
+    pub radius: f32,
+    /// Keeps flat surfaces from occluding themselves, in world units
+    pub bias: f32,
+    /// The occlusion factor is raised to this power, higher darkens creases more
+    pub power: f32,
+    /// Computes occlusion at half the resolution, the blur upsamples it back
+    pub half_resolution: bool,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        SsaoSettings {
+            enabled: true,
+            samples: 16,
+            radius: 1.0,
+            bias: 0.025,
+            power: 1.5,
+            half_resolution: true,
+        }
+    }
+}
+
+// Targets which depend on the viewport size
+struct SsaoTargets {
+    // View space normals and depth of the prepass
+    prepass: Framebuffer,
+    normals: Texture2D,
+    depth: Texture2D,
+    occlusion: RenderTarget,
+    blurred: RenderTarget,
+}
+
+impl SsaoTargets {
+    fn new(gl: &gl::Gl, settings: &SsaoSettings, size: (u32, u32)) -> Result<SsaoTargets, framebuffer::Error> {
+        // Minimized windows have no size, but targets can't be empty
+        let (width, height) = (size.0.max(1), size.1.max(1));
+        let divisor = if settings.half_resolution { 2 } else { 1 };
+
+        let normals = Texture2D::new(gl, width, height, TextureFormat::Rgba16F);
+        let depth = Texture2D::new(gl, width, height, TextureFormat::Depth32F);
+
+        let prepass = Framebuffer::new(gl);
+        prepass.bind();
+        prepass.attach_texture(Attachment::Color(0), &normals);
+        prepass.attach_texture(Attachment::Depth, &depth);
+        let complete = prepass.check_complete();
+        prepass.unbind();
+        complete?;
+
+        Ok(SsaoTargets {
+            prepass,
+            normals,
+            depth,
+            occlusion: RenderTarget::new(gl, (width / divisor).max(1), (height / divisor).max(1), TextureFormat::Rgba8, 0)?,
+            blurred: RenderTarget::new(gl, width, height, TextureFormat::Rgba8, 0)?,
+        })
+    }
+}
+
+/// Screen-space ambient occlusion. A prepass draws view space normals and depth, from which a
+/// hemisphere of samples around every pixel, rotated by a tiled noise texture, estimates how much
+/// of the surroundings is blocked. A blur then smooths out the noise. The result scales the
+/// ambient light of lit objects.
+pub struct Ssao {
+    pub settings: SsaoSettings,
+    gl: gl::Gl,
+    targets: SsaoTargets,
+    normals_draw: ObjectsDraw,
+    noise: Texture,
+    program: Program,
+    blur: Program,
+    // The fullscreen triangle is generated from gl_VertexID, but drawing needs some vertex array
+    vao: VertexArray,
+    projection_loc: i32,
+    inverse_projection_loc: i32,
+    noise_scale_loc: i32,
+    samples_loc: i32,
+    radius_loc: i32,
+    bias_loc: i32,
+    power_loc: i32,
+    blur_texel_size_loc: i32,
+}
+
+impl Ssao {
+    pub fn new(
+        res: &Resources,
+        gl: &gl::Gl,
+        meshes: &MeshRegistry,
+        settings: SsaoSettings,
+        instanced: bool,
+        size: (u32, u32),
+    ) -> Result<Ssao, Error> {
+        let program = Program::from_res_shaders(gl, res, "shaders/ssao", &["shaders/fullscreen.vert", "shaders/ssao.frag"])?;
+        let blur = Program::from_res_shaders(gl, res, "shaders/ssao_blur", &["shaders/fullscreen.vert", "shaders/ssao_blur.frag"])?;
+
+        // Fixed, so that every run samples the same way
+        let mut rng = StdRng::seed_from_u64(0);
+
+        program.set_used();
+        program.set_int_uniform(program.get_uniform_loc("depth")?, DEPTH_UNIT as i32);
+        program.set_int_uniform(program.get_uniform_loc("normals")?, NORMALS_UNIT as i32);
+        program.set_int_uniform(program.get_uniform_loc("noise")?, NOISE_UNIT as i32);
+        let kernel_loc = program.get_uniform_loc("kernel")?;
+        for (i, sample) in Self::kernel(&mut rng).iter().enumerate() {
+            program.set_vec3_array_uniform(kernel_loc, i, sample);
+        }
+
+        blur.set_used();
+        blur.set_int_uniform(blur.get_uniform_loc("source")?, DEPTH_UNIT as i32);
+
+        Ok(Ssao {
+            settings,
+            gl: gl.clone(),
+            targets: SsaoTargets::new(gl, &settings, size)?,
+            normals_draw: ObjectsDraw::new_normals(res, gl, meshes, instanced)?,
+            noise: Self::noise(gl, &mut rng),
+            projection_loc: program.get_uniform_loc("projection")?,
+            inverse_projection_loc: program.get_uniform_loc("inverse_projection")?,
+            noise_scale_loc: program.get_uniform_loc("noise_scale")?,
+            samples_loc: program.get_uniform_loc("samples")?,
+            radius_loc: program.get_uniform_loc("radius")?,
+            bias_loc: program.get_uniform_loc("bias")?,
+            power_loc: program.get_uniform_loc("power")?,
+            blur_texel_size_loc: blur.get_uniform_loc("texel_size")?,
+            program,
+            blur,
+            vao: VertexArray::new(gl),
+        })
+    }
+
+    // Points in the hemisphere around +Z, denser towards the center so that close occluders count
+    // the most. Lower sample counts use a prefix, which is spread out just as well.
+    fn kernel(rng: &mut impl Rng) -> Vec<Vector3<f32>> {
+        (0..MAX_SAMPLES)
+            .map(|i| {
+                let direction = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(0.0..1.0))
+                    .try_normalize(1e-6)
+                    .unwrap_or_else(Vector3::z);
+                let t = (i % 16) as f32 / 16.0;
+                direction * rng.gen_range(0.0..1.0) * (0.1 + 0.9 * t * t)
+            })
+            .collect()
+    }
+
+    // Random rotations around the normal, as directions in the XY plane encoded into [0, 1]
+    fn noise(gl: &gl::Gl, rng: &mut impl Rng) -> Texture {
+        let image = ImageBuffer::from_fn(NOISE_SIZE, NOISE_SIZE, |_, _| {
+            let angle: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+            let encode = |value: f32| ((value * 0.5 + 0.5) * 255.0) as u8;
+            Rgba([encode(angle.cos()), encode(angle.sin()), 128, 255])
+        });
+        let sampling = Sampling {
+            min_filter: Filter::Nearest,
+            mag_filter: Filter::Nearest,
+            mipmaps: false,
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::Repeat,
+            anisotropy: 1.0,
+        };
+
+        Texture::from_image(gl, &DynamicImage::ImageRgba8(image), &sampling, ColorSpace::Linear)
+    }
+
+    /// Recreates the targets to match a new window size, or changed settings
+    pub fn resize(&mut self, size: (u32, u32)) -> Result<(), framebuffer::Error> {
+        self.targets = SsaoTargets::new(&self.gl, &self.settings, size)?;
+
+        Ok(())
+    }
+
+    /// Computes the occlusion of what `draw` draws using the [`ObjectsDraw`] it's passed, seen
+    /// through the camera in the uniform block, which has to be projected with `projection`.
+    /// Without SSAO this just clears the occlusion, leaving ambient light as it is.
+    ///
+    /// Changes the viewport and binds the window, the viewport is restored to `viewport_size`
+    /// afterwards.
+    pub(crate) fn render(&self, projection: &Matrix4<f32>, viewport_size: (u32, u32), draw: impl Fn(&ObjectsDraw)) {
+        let targets = &self.targets;
+
+        if !self.settings.enabled {
+            targets.blurred.framebuffer().clear_color(0, [1.0; 4]);
+            return;
+        }
+
+        targets.prepass.bind();
+        unsafe {
+            self.gl.Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+        draw(&self.normals_draw);
+
+        self.vao.bind();
+        unsafe {
+            self.gl.Disable(gl::DEPTH_TEST);
+        }
+
+        let (width, height) = targets.occlusion.size();
+        self.program.set_used();
+        self.program.set_mat4_uniform(self.projection_loc, projection);
+        self.program.set_mat4_uniform(
+            self.inverse_projection_loc,
+            &projection.try_inverse().unwrap_or_else(Matrix4::identity),
+        );
+        self.program.set_vec2_uniform(
+            self.noise_scale_loc,
+            &Vector2::new(width as f32 / NOISE_SIZE as f32, height as f32 / NOISE_SIZE as f32),
+        );
+        self.program
+            .set_int_uniform(self.samples_loc, self.settings.samples.clamp(1, MAX_SAMPLES) as i32);
+        self.program.set_float_uniform(self.radius_loc, self.settings.radius);
+        self.program.set_float_uniform(self.bias_loc, self.settings.bias);
+        self.program.set_float_uniform(self.power_loc, self.settings.power);
+        targets.depth.bind_to_unit(DEPTH_UNIT);
+        targets.normals.bind_to_unit(NORMALS_UNIT);
+        self.noise.bind_to_unit(NOISE_UNIT);
+
+        targets.occlusion.bind();
+        unsafe {
+            self.gl.Viewport(0, 0, width as i32, height as i32);
+            self.gl.DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+
+        self.blur.set_used();
+        self.blur
+            .set_vec2_uniform(self.blur_texel_size_loc, &Vector2::new(1.0 / width as f32, 1.0 / height as f32));
+        targets.occlusion.color().bind_to_unit(DEPTH_UNIT);
+
+        targets.blurred.bind();
+        unsafe {
+            self.gl.Viewport(0, 0, viewport_size.0 as i32, viewport_size.1 as i32);
+            self.gl.DrawArrays(gl::TRIANGLES, 0, 3);
+            self.gl.Enable(gl::DEPTH_TEST);
+        }
+        targets.blurred.unbind();
+        self.vao.unbind();
+    }
+
+    /// Binds the occlusion for lit objects to sample
+    pub fn bind(&self) {
+        self.targets.blurred.color().bind_to_unit(AMBIENT_OCCLUSION_UNIT);
+    }
+}
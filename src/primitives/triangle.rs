@@ -1,10 +1,10 @@
 use gl;
-use nalgebra::{Vector3, Vector4};
+use nalgebra::{Vector2, Vector3, Vector4};
 
 use crate::primitives::light::Color;
 use crate::primitives::spatial::Location;
 use crate::render_gl::data;
-use crate::render_gl::data::{f32_f32_f32, f32_f32_f32_f32};
+use crate::render_gl::data::{f32_f32, f32_f32_f32, f32_f32_f32_f32};
 
 #[derive(VertexAttribPointers, Copy, Clone, Debug)]
 #[repr(C, packed)]
@@ -12,6 +12,7 @@ pub struct VertexData {
     pub pos: data::f32_f32_f32,
     pub clr: data::u2_u10_u10_u10_rev_float,
     pub norm: data::f32_f32_f32,
+    pub uv: data::f32_f32,
 }
 
 impl VertexData {
@@ -20,6 +21,7 @@ impl VertexData {
             pos: vertex.pos.into(),
             clr: vertex.clr.into(),
             norm: normal.into(),
+            uv: vertex.uv.into(),
         }
     }
 }
@@ -28,6 +30,8 @@ impl VertexData {
 pub(crate) struct Vertex {
     pub pos: Location,
     pub clr: Color,
+    /// Texture coordinates, with (0, 0) at the bottom left of the texture
+    pub uv: Vector2<f32>,
 }
 
 #[derive(Debug, Clone)]
@@ -72,6 +76,12 @@ impl From<Vector4<f32>> for f32_f32_f32_f32 {
     }
 }
 
+impl From<Vector2<f32>> for f32_f32 {
+    fn from(vector: Vector2<f32>) -> Self {
+        (vector.x, vector.y).into()
+    }
+}
+
 impl From<Vector3<f32>> for f32_f32_f32 {
     fn from(vector: Vector3<f32>) -> Self {
         (vector.x, vector.y, vector.z).into()
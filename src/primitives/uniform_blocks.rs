@@ -0,0 +1,63 @@
+use nalgebra::{Matrix4, Vector3};
+
+use crate::render_gl::buffer::{BufferUsage, UniformBuffer};
+
+// Fixed binding points of the blocks, shared by every program
+pub const CAMERA_BINDING: u32 = 0;
+
+/// The `Camera` uniform block, laid out according to std140
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct CameraBlock {
+    view_rotation: [f32; 16],
+    view_translation: [f32; 16],
+    projection: [f32; 16],
+    // std140 pads vec3 to the size of a vec4
+    view_location: [f32; 4],
+}
+
+/// The uniform buffers backing the uniform blocks every program shares. They're updated and bound
+/// once per frame, rather than setting the same uniforms on every program.
+pub struct UniformBlocks {
+    camera: UniformBuffer<CameraBlock>,
+}
+
+impl UniformBlocks {
+    pub fn new(gl: &gl::Gl) -> UniformBlocks {
+        let camera = UniformBuffer::new(gl);
+        camera.bind();
+        camera.allocate(1, BufferUsage::Dynamic);
+        camera.unbind();
+
+        UniformBlocks { camera }
+    }
+
+    pub fn set_camera(
+        &self,
+        view_rotation: &Matrix4<f32>,
+        view_translation: &Matrix4<f32>,
+        view_location: &Vector3<f32>,
+        projection: &Matrix4<f32>,
+    ) {
+        let mut block = CameraBlock {
+            view_rotation: [0.0; 16],
+            view_translation: [0.0; 16],
+            projection: [0.0; 16],
+            view_location: [view_location.x, view_location.y, view_location.z, 1.0],
+        };
+        block.view_rotation.copy_from_slice(view_rotation.as_slice());
+        block.view_translation.copy_from_slice(view_translation.as_slice());
+        block.projection.copy_from_slice(projection.as_slice());
+
+        self.camera.bind();
+        self.camera
+            .sub_data(0, &[block])
+            .expect("The camera buffer is allocated with room for a block");
+        self.camera.unbind();
+    }
+
+    /// Binds the buffers to their binding points, for all programs drawn afterwards
+    pub fn bind(&self) {
+        self.camera.bind_base(CAMERA_BINDING);
+    }
+}
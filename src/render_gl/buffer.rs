@@ -1,12 +1,55 @@
+use std::cell::Cell;
+
 use gl;
 
-pub struct Buffer<B>
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Writing {} elements at {} overflows a buffer of {}", len, offset, capacity)]
+    Overflow { offset: usize, len: usize, capacity: usize },
+    #[fail(display = "Mapped buffers can't be empty")]
+    ZeroCapacity,
+    #[fail(display = "Failed to map a buffer of {} bytes", size)]
+    MapFailed { size: usize },
+}
+
+/// How often the contents of a buffer are expected to change, a hint for the driver
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BufferUsage {
+    /// Uploaded once, drawn many times
+    Static,
+    /// Updated now and then, drawn many times
+    Dynamic,
+    /// Updated (about) every time it's drawn
+    Stream,
+    /// Written by the GPU once, and read back once
+    Readback,
+}
+
+impl BufferUsage {
+    fn gl_usage(self) -> gl::types::GLenum {
+        match self {
+            BufferUsage::Static => gl::STATIC_DRAW,
+            BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+            BufferUsage::Stream => gl::STREAM_DRAW,
+            BufferUsage::Readback => gl::STREAM_READ,
+        }
+    }
+}
+
+/// A GL buffer holding elements of type `T`. Lengths and offsets are all counted in elements.
+///
+/// Like the other GL wrappers, all data transfers go to the buffer's binding point, so the buffer
+/// has to be bound first.
+pub struct Buffer<B, T>
 where
     B: BufferType,
 {
     gl: gl::Gl,
     vbo: gl::types::GLuint,
-    _marker: ::std::marker::PhantomData<B>,
+    len: Cell<usize>,
+    capacity: Cell<usize>,
+    usage: Cell<BufferUsage>,
+    _marker: ::std::marker::PhantomData<(B, T)>,
 }
 
 pub trait BufferType {
@@ -19,7 +62,7 @@ impl BufferType for BufferTypeArray {
     const BUFFER_TYPE: gl::types::GLuint = gl::ARRAY_BUFFER;
 }
 
-pub type ArrayBuffer = Buffer<BufferTypeArray>;
+pub type ArrayBuffer<T> = Buffer<BufferTypeArray, T>;
 
 pub struct BufferTypeElementArray;
 
@@ -27,13 +70,45 @@ impl BufferType for BufferTypeElementArray {
     const BUFFER_TYPE: gl::types::GLuint = gl::ELEMENT_ARRAY_BUFFER;
 }
 
-pub type ElementArrayBuffer = Buffer<BufferTypeElementArray>;
+pub type ElementArrayBuffer<T> = Buffer<BufferTypeElementArray, T>;
+
+/// Buffer types with indexed binding points, which shaders refer to by number
+pub trait IndexedBufferType: BufferType {}
+
+pub struct BufferTypeUniform;
+
+impl BufferType for BufferTypeUniform {
+    const BUFFER_TYPE: gl::types::GLuint = gl::UNIFORM_BUFFER;
+}
+
+impl IndexedBufferType for BufferTypeUniform {}
+
+pub type UniformBuffer<T> = Buffer<BufferTypeUniform, T>;
+
+pub struct BufferTypeShaderStorage;
+
+impl BufferType for BufferTypeShaderStorage {
+    const BUFFER_TYPE: gl::types::GLuint = gl::SHADER_STORAGE_BUFFER;
+}
+
+impl IndexedBufferType for BufferTypeShaderStorage {}
+
+pub type ShaderStorageBuffer<T> = Buffer<BufferTypeShaderStorage, T>;
+
+pub struct BufferTypePixelPack;
+
+impl BufferType for BufferTypePixelPack {
+    const BUFFER_TYPE: gl::types::GLuint = gl::PIXEL_PACK_BUFFER;
+}
 
-impl<B> Buffer<B>
+/// Pixels read from framebuffers and textures go into the bound pixel pack buffer, if there is one
+pub type PixelPackBuffer<T> = Buffer<BufferTypePixelPack, T>;
+
+impl<B, T> Buffer<B, T>
 where
     B: BufferType,
 {
-    pub fn new(gl: &gl::Gl) -> Buffer<B> {
+    pub fn new(gl: &gl::Gl) -> Buffer<B, T> {
         let mut vbo: gl::types::GLuint = 0;
 
         unsafe {
@@ -43,6 +118,9 @@ where
         Buffer {
             gl: gl.clone(),
             vbo,
+            len: Cell::new(0),
+            capacity: Cell::new(0),
+            usage: Cell::new(BufferUsage::Static),
             _marker: ::std::marker::PhantomData,
         }
     }
@@ -59,19 +137,116 @@ where
         }
     }
 
-    pub fn static_draw_data<T>(&self, data: &[T]) {
+    /// Number of elements written to the buffer since it was last (re)allocated
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of elements the buffer has room for without reallocating
+    pub fn capacity(&self) -> usize {
+        self.capacity.get()
+    }
+
+    /// Reallocates the buffer to fit exactly `data`
+    pub fn upload(&self, data: &[T], usage: BufferUsage) {
+        self.buffer_data(data.len(), data.as_ptr() as *const gl::types::GLvoid, usage);
+        self.len.set(data.len());
+    }
+
+    /// Reallocates the buffer with room for `capacity` elements of undefined contents
+    pub fn allocate(&self, capacity: usize, usage: BufferUsage) {
+        self.buffer_data(capacity, std::ptr::null(), usage);
+        self.len.set(0);
+    }
+
+    /// Overwrites part of the buffer, starting at element `offset`, without reallocating it. The
+    /// data has to fit into the buffer's capacity.
+    pub fn sub_data(&self, offset: usize, data: &[T]) -> Result<(), Error> {
+        if offset + data.len() > self.capacity() {
+            return Err(Error::Overflow {
+                offset,
+                len: data.len(),
+                capacity: self.capacity(),
+            });
+        }
+
+        self.buffer_sub_data(offset, data);
+        Ok(())
+    }
+
+    /// Gives the buffer fresh storage of the same size and usage. Draws still reading the old
+    /// storage don't have to finish before the buffer is written to again.
+    pub fn orphan(&self) {
+        self.buffer_data(self.capacity(), std::ptr::null(), self.usage.get());
+        self.len.set(0);
+    }
+
+    pub fn static_draw_data(&self, data: &[T]) {
+        self.upload(data, BufferUsage::Static);
+    }
+
+    pub fn dynamic_draw_data(&self, data: &[T]) {
+        self.upload(data, BufferUsage::Dynamic);
+    }
+
+    /// Replaces the contents with `data`, for data that changes every frame. The storage is
+    /// orphaned and reused when `data` fits, and only grows when it doesn't.
+    pub fn stream_draw_data(&self, data: &[T]) {
+        if data.len() <= self.capacity() && self.usage.get() == BufferUsage::Stream {
+            self.orphan();
+            self.buffer_sub_data(0, data);
+        } else {
+            self.upload(data, BufferUsage::Stream);
+        }
+    }
+
+    fn buffer_sub_data(&self, offset: usize, data: &[T]) {
         unsafe {
-            self.gl.BufferData(
-                gl::ARRAY_BUFFER,
+            self.gl.BufferSubData(
+                B::BUFFER_TYPE,
+                (offset * std::mem::size_of::<T>()) as gl::types::GLintptr,
                 std::mem::size_of_val(data) as gl::types::GLsizeiptr,
                 data.as_ptr() as *const gl::types::GLvoid,
-                gl::STATIC_DRAW,
             );
         }
+
+        self.len.set(self.len().max(offset + data.len()));
+    }
+
+    fn buffer_data(&self, capacity: usize, data: *const gl::types::GLvoid, usage: BufferUsage) {
+        unsafe {
+            self.gl.BufferData(
+                B::BUFFER_TYPE,
+                (capacity * std::mem::size_of::<T>()) as gl::types::GLsizeiptr,
+                data,
+                usage.gl_usage(),
+            );
+        }
+
+        self.capacity.set(capacity);
+        self.usage.set(usage);
+    }
+}
+
+impl<B, T> Buffer<B, T>
+where
+    B: IndexedBufferType,
+{
+    /// Binds the whole buffer to the indexed binding point `binding`, e.g. the one a block was
+    /// assigned with [`crate::render_gl::Program::set_uniform_block_binding`] or
+    /// [`crate::render_gl::Program::set_storage_block_binding`]
+    pub fn bind_base(&self, binding: u32) {
+        unsafe {
+            self.gl.BindBufferBase(B::BUFFER_TYPE, binding, self.vbo);
+        }
     }
 }
 
-impl<B> Drop for Buffer<B>
+impl<B, T> Drop for Buffer<B, T>
 where
     B: BufferType,
 {
@@ -82,6 +257,118 @@ where
     }
 }
 
+/// A buffer with immutable storage which stays mapped for as long as it lives, so it can be
+/// written to directly without any copies or GL calls. The mapping is coherent, writes become
+/// visible to the GPU without flushing.
+///
+/// The GPU may still be reading the previous contents when they're written to again. Call
+/// [`Self::fence`] after the draws using the buffer, the next [`Self::write`] then waits for them.
+pub struct MappedBuffer<B, T>
+where
+    B: BufferType,
+{
+    buffer: Buffer<B, T>,
+    ptr: *mut T,
+    fence: Cell<Option<gl::types::GLsync>>,
+}
+
+impl<B, T> MappedBuffer<B, T>
+where
+    B: BufferType,
+    T: Copy + Default,
+{
+    /// Allocates and maps room for `capacity` elements, which start out as their default
+    pub fn new(gl: &gl::Gl, capacity: usize) -> Result<MappedBuffer<B, T>, Error> {
+        const FLAGS: gl::types::GLbitfield = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+
+        // Zero sized storage can't be mapped
+        if capacity == 0 || std::mem::size_of::<T>() == 0 {
+            return Err(Error::ZeroCapacity);
+        }
+
+        let buffer = Buffer::new(gl);
+        let initial = vec![T::default(); capacity];
+        let size = std::mem::size_of_val(initial.as_slice());
+
+        buffer.bind();
+        let ptr = unsafe {
+            gl.BufferStorage(
+                B::BUFFER_TYPE,
+                size as gl::types::GLsizeiptr,
+                initial.as_ptr() as *const gl::types::GLvoid,
+                FLAGS,
+            );
+            gl.MapBufferRange(B::BUFFER_TYPE, 0, size as gl::types::GLsizeiptr, FLAGS) as *mut T
+        };
+        buffer.unbind();
+
+        // The buffer is deleted on the way out, there's nothing to unmap
+        if ptr.is_null() {
+            return Err(Error::MapFailed { size });
+        }
+
+        buffer.capacity.set(capacity);
+        buffer.len.set(capacity);
+
+        Ok(MappedBuffer {
+            buffer,
+            ptr,
+            fence: Cell::new(None),
+        })
+    }
+
+    pub fn bind(&self) {
+        self.buffer.bind();
+    }
+
+    pub fn unbind(&self) {
+        self.buffer.unbind();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// The mapped contents, once the GPU is done with the draws issued before the last fence
+    pub fn write(&mut self) -> &mut [T] {
+        if let Some(fence) = self.fence.take() {
+            unsafe {
+                // Wait a second at a time, only giving up if the wait itself fails
+                while let gl::TIMEOUT_EXPIRED = self.buffer.gl.ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000_000) {}
+                self.buffer.gl.DeleteSync(fence);
+            }
+        }
+
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.capacity()) }
+    }
+
+    /// Marks the point after which the contents may be written again
+    pub fn fence(&self) {
+        unsafe {
+            if let Some(fence) = self.fence.take() {
+                self.buffer.gl.DeleteSync(fence);
+            }
+            self.fence.set(Some(self.buffer.gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0)));
+        }
+    }
+}
+
+impl<B, T> Drop for MappedBuffer<B, T>
+where
+    B: BufferType,
+{
+    fn drop(&mut self) {
+        self.buffer.bind();
+        unsafe {
+            if let Some(fence) = self.fence.take() {
+                self.buffer.gl.DeleteSync(fence);
+            }
+            self.buffer.gl.UnmapBuffer(B::BUFFER_TYPE);
+        }
+        self.buffer.unbind();
+    }
+}
+
 pub struct VertexArray {
     gl: gl::Gl,
     vao: gl::types::GLuint,
@@ -118,3 +405,107 @@ impl Drop for VertexArray {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::os::raw::c_void;
+
+    use gl::types::{GLbitfield, GLboolean, GLenum, GLintptr, GLsizei, GLsizeiptr, GLuint};
+
+    use super::{ArrayBuffer, BufferTypeArray, Error, MappedBuffer};
+
+    // Just enough of GL for mapped buffers, which records what it was asked to do
+    thread_local! {
+        static MAPPING: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+        static MAP_FAILS: Cell<bool> = const { Cell::new(false) };
+        static GENERATED: Cell<usize> = const { Cell::new(0) };
+        static DELETED: Cell<usize> = const { Cell::new(0) };
+        static UNMAPPED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    extern "system" fn gen_buffers(n: GLsizei, buffers: *mut GLuint) {
+        GENERATED.with(|generated| generated.set(generated.get() + n as usize));
+        unsafe { *buffers = 1 };
+    }
+
+    extern "system" fn delete_buffers(n: GLsizei, _: *const GLuint) {
+        DELETED.with(|deleted| deleted.set(deleted.get() + n as usize));
+    }
+
+    extern "system" fn bind_buffer(_: GLenum, _: GLuint) {}
+
+    extern "system" fn buffer_storage(_: GLenum, size: GLsizeiptr, _: *const c_void, _: GLbitfield) {
+        MAPPING.with(|mapping| *mapping.borrow_mut() = vec![0; size as usize / 4]);
+    }
+
+    extern "system" fn map_buffer_range(_: GLenum, _: GLintptr, _: GLsizeiptr, _: GLbitfield) -> *mut c_void {
+        if MAP_FAILS.with(Cell::get) {
+            std::ptr::null_mut()
+        } else {
+            MAPPING.with(|mapping| mapping.borrow_mut().as_mut_ptr() as *mut c_void)
+        }
+    }
+
+    extern "system" fn unmap_buffer(_: GLenum) -> GLboolean {
+        UNMAPPED.with(|unmapped| unmapped.set(unmapped.get() + 1));
+        gl::TRUE
+    }
+
+    fn fake_gl(map_fails: bool) -> gl::Gl {
+        MAP_FAILS.with(|fails| fails.set(map_fails));
+
+        gl::Gl::load_with(|name| match name {
+            "glGenBuffers" => gen_buffers as *const c_void,
+            "glDeleteBuffers" => delete_buffers as *const c_void,
+            "glBindBuffer" => bind_buffer as *const c_void,
+            "glBufferStorage" => buffer_storage as *const c_void,
+            "glMapBufferRange" => map_buffer_range as *const c_void,
+            "glUnmapBuffer" => unmap_buffer as *const c_void,
+            _ => std::ptr::null(),
+        })
+    }
+
+    fn counts() -> (usize, usize, usize) {
+        (GENERATED.with(Cell::get), DELETED.with(Cell::get), UNMAPPED.with(Cell::get))
+    }
+
+    #[test]
+    fn writes_go_straight_into_the_mapping() {
+        let gl = fake_gl(false);
+
+        let mut buffer = MappedBuffer::<BufferTypeArray, u32>::new(&gl, 4).unwrap();
+        assert_eq!(buffer.capacity(), 4);
+        buffer.write().copy_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(MAPPING.with(|mapping| mapping.borrow().clone()), vec![1, 2, 3, 4]);
+
+        drop(buffer);
+        assert_eq!(counts(), (1, 1, 1));
+    }
+
+    #[test]
+    fn failed_mappings_are_errors() {
+        let gl = fake_gl(true);
+
+        let buffer = MappedBuffer::<BufferTypeArray, u32>::new(&gl, 4);
+        assert!(matches!(buffer, Err(Error::MapFailed { size: 16 })));
+
+        // The buffer is deleted, without unmapping what was never mapped
+        assert_eq!(counts(), (1, 1, 0));
+    }
+
+    #[test]
+    fn empty_buffers_are_not_mapped() {
+        let gl = fake_gl(false);
+
+        assert!(matches!(
+            MappedBuffer::<BufferTypeArray, u32>::new(&gl, 0),
+            Err(Error::ZeroCapacity)
+        ));
+        assert_eq!(counts(), (0, 0, 0));
+
+        // Plain buffers may be empty
+        drop(ArrayBuffer::<u32>::new(&gl));
+        assert_eq!(counts(), (1, 1, 0));
+    }
+}
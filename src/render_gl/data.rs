@@ -5,6 +5,40 @@ use nalgebra::Vector4;
 use crate::primitives::light::Color;
 use crate::primitives::spatial::Location;
 
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct f32_f32 {
+    pub d0: f32,
+    pub d1: f32,
+}
+
+impl f32_f32 {
+    pub fn new(d0: f32, d1: f32) -> f32_f32 {
+        f32_f32 { d0, d1 }
+    }
+
+    pub fn vertex_attrib_pointer(gl: &gl::Gl, stride: usize, location: usize, offset: usize) {
+        unsafe {
+            gl.EnableVertexAttribArray(location as gl::types::GLuint);
+            gl.VertexAttribPointer(
+                location as gl::types::GLuint,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                stride as gl::types::GLint,
+                offset as *const gl::types::GLvoid,
+            );
+        }
+    }
+}
+
+impl From<(f32, f32)> for f32_f32 {
+    fn from(other: (f32, f32)) -> Self {
+        f32_f32::new(other.0, other.1)
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, Debug)]
 #[repr(C, packed)]
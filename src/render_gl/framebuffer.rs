@@ -0,0 +1,329 @@
+use std::cell::Cell;
+
+use gl;
+
+use crate::render_gl::{Renderbuffer, Texture, Texture2D, TextureFormat};
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Framebuffer is incomplete: the default framebuffer doesn't exist")]
+    Undefined,
+    #[fail(display = "Framebuffer is incomplete: an attachment is incomplete")]
+    IncompleteAttachment,
+    #[fail(display = "Framebuffer is incomplete: it has no attachments")]
+    MissingAttachment,
+    #[fail(display = "Framebuffer is incomplete: a draw buffer has no attachment")]
+    IncompleteDrawBuffer,
+    #[fail(display = "Framebuffer is incomplete: the read buffer has no attachment")]
+    IncompleteReadBuffer,
+    #[fail(display = "Framebuffer is incomplete: the combination of attachment formats is unsupported")]
+    Unsupported,
+    #[fail(display = "Framebuffer is incomplete: the attachments have different numbers of samples")]
+    IncompleteMultisample,
+    #[fail(display = "Framebuffer is incomplete: layered and non-layered attachments are mixed")]
+    IncompleteLayerTargets,
+    #[fail(display = "Framebuffer is incomplete, status {:#x}", status)]
+    Incomplete { status: u32 },
+}
+
+impl Error {
+    fn from_status(status: gl::types::GLenum) -> Error {
+        match status {
+            gl::FRAMEBUFFER_UNDEFINED => Error::Undefined,
+            gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => Error::IncompleteAttachment,
+            gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => Error::MissingAttachment,
+            gl::FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER => Error::IncompleteDrawBuffer,
+            gl::FRAMEBUFFER_INCOMPLETE_READ_BUFFER => Error::IncompleteReadBuffer,
+            gl::FRAMEBUFFER_UNSUPPORTED => Error::Unsupported,
+            gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => Error::IncompleteMultisample,
+            gl::FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS => Error::IncompleteLayerTargets,
+            status => Error::Incomplete { status },
+        }
+    }
+}
+
+/// Where an image is attached to a framebuffer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Attachment {
+    /// The color attachment with the given index, written by fragment shader output `index`
+    Color(u32),
+    Depth,
+    DepthStencil,
+}
+
+impl Attachment {
+    /// The attachment a depth or depth-stencil format belongs to, `None` for color formats
+    pub fn for_depth_format(format: TextureFormat) -> Option<Attachment> {
+        match format {
+            _ if format.has_stencil() => Some(Attachment::DepthStencil),
+            _ if format.is_depth() => Some(Attachment::Depth),
+            _ => None,
+        }
+    }
+
+    fn gl_attachment(self) -> gl::types::GLenum {
+        match self {
+            Attachment::Color(index) => gl::COLOR_ATTACHMENT0 + index,
+            Attachment::Depth => gl::DEPTH_ATTACHMENT,
+            Attachment::DepthStencil => gl::DEPTH_STENCIL_ATTACHMENT,
+        }
+    }
+}
+
+/// A framebuffer object. It borrows its attachments only while attaching them, so it's up to the
+/// owner to keep them alive for as long as they're attached, like [`RenderTarget`] does.
+pub struct Framebuffer {
+    gl: gl::Gl,
+    fbo: gl::types::GLuint,
+    // Bit i is set while color attachment i is attached, those are the draw buffers
+    color_attachments: Cell<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(gl: &gl::Gl) -> Framebuffer {
+        let mut fbo: gl::types::GLuint = 0;
+
+        unsafe {
+            gl.GenFramebuffers(1, &mut fbo);
+        }
+
+        Framebuffer {
+            gl: gl.clone(),
+            fbo,
+            color_attachments: Cell::new(0),
+        }
+    }
+
+    pub fn id(&self) -> gl::types::GLuint {
+        self.fbo
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        }
+    }
+
+    /// Binds the default framebuffer, i.e. the window
+    pub fn unbind(&self) {
+        unsafe {
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// Attaches a 2D texture, multisampled or not. Color attachments become draw buffers. The
+    /// framebuffer has to be bound.
+    pub fn attach_texture(&self, attachment: Attachment, texture: &Texture2D) {
+        unsafe {
+            self.gl.FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                attachment.gl_attachment(),
+                texture.texture().target(),
+                texture.texture().id(),
+                0,
+            );
+        }
+        self.attached(attachment, true);
+    }
+
+    /// Color attachments become draw buffers. The framebuffer has to be bound.
+    pub fn attach_renderbuffer(&self, attachment: Attachment, renderbuffer: &Renderbuffer) {
+        unsafe {
+            self.gl
+                .FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment.gl_attachment(), gl::RENDERBUFFER, renderbuffer.id());
+        }
+        self.attached(attachment, true);
+    }
+
+    /// Renders depth into a single layer of a layered texture, like one face of a cube map array.
+    /// The framebuffer has to be bound.
+    pub fn attach_depth_layer(&self, texture: &Texture, layer: u32) {
+        unsafe {
+            self.gl
+                .FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, texture.id(), 0, layer as i32);
+        }
+    }
+
+    /// Removes whatever is attached to `attachment`. The framebuffer has to be bound.
+    pub fn detach(&self, attachment: Attachment) {
+        unsafe {
+            self.gl
+                .FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment.gl_attachment(), gl::RENDERBUFFER, 0);
+        }
+        self.attached(attachment, false);
+    }
+
+    /// Makes the framebuffer depth-only, without any color attachments. The framebuffer has to be
+    /// bound.
+    pub fn disable_color(&self) {
+        self.color_attachments.set(0);
+        unsafe {
+            self.gl.DrawBuffer(gl::NONE);
+            self.gl.ReadBuffer(gl::NONE);
+        }
+    }
+
+    // Keeps the draw buffers in sync with the color attachments
+    fn attached(&self, attachment: Attachment, attached: bool) {
+        let index = match attachment {
+            Attachment::Color(index) => index,
+            _ => return,
+        };
+
+        let mask = if attached {
+            self.color_attachments.get() | 1 << index
+        } else {
+            self.color_attachments.get() & !(1 << index)
+        };
+        self.color_attachments.set(mask);
+
+        // Draw buffer i writes fragment shader output i, gaps are left unwritten
+        let highest = 32 - mask.leading_zeros();
+        let draw_buffers: Vec<gl::types::GLenum> = (0..highest)
+            .map(|i| if mask & 1 << i != 0 { gl::COLOR_ATTACHMENT0 + i } else { gl::NONE })
+            .collect();
+
+        unsafe {
+            if draw_buffers.is_empty() {
+                self.gl.DrawBuffer(gl::NONE);
+            } else {
+                self.gl.DrawBuffers(draw_buffers.len() as i32, draw_buffers.as_ptr());
+            }
+        }
+    }
+
+    /// Fills color attachment `index` with `color`, whether the framebuffer is bound or not
+    pub fn clear_color(&self, index: u32, color: [f32; 4]) {
+        unsafe {
+            self.gl.ClearNamedFramebufferfv(self.fbo, gl::COLOR, index as i32, color.as_ptr());
+        }
+    }
+
+    /// The framebuffer has to be bound
+    pub fn check_complete(&self) -> Result<(), Error> {
+        let status = unsafe { self.gl.CheckFramebufferStatus(gl::FRAMEBUFFER) };
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(Error::from_status(status));
+        }
+
+        Ok(())
+    }
+
+    /// Copies the `src_size` area of this framebuffer into the `dst_size` area of `dst`, or of the
+    /// window if `dst` is `None`. `mask` selects the buffers, e.g. `gl::COLOR_BUFFER_BIT`, and
+    /// `filter` is used for scaling, it has to be `gl::NEAREST` when copying depth or stencil.
+    ///
+    /// Color is read from color attachment 0 and written to all draw buffers of `dst`.
+    pub fn blit(
+        &self,
+        dst: Option<&Framebuffer>,
+        src_size: (u32, u32),
+        dst_size: (u32, u32),
+        mask: gl::types::GLbitfield,
+        filter: gl::types::GLenum,
+    ) {
+        unsafe {
+            self.gl.NamedFramebufferReadBuffer(self.fbo, gl::COLOR_ATTACHMENT0);
+            self.gl.BlitNamedFramebuffer(
+                self.fbo,
+                dst.map_or(0, |dst| dst.fbo),
+                0,
+                0,
+                src_size.0 as i32,
+                src_size.1 as i32,
+                0,
+                0,
+                dst_size.0 as i32,
+                dst_size.1 as i32,
+                mask,
+                filter,
+            );
+        }
+    }
+
+    /// Resolves a multisampled framebuffer into `dst`, or the window if `dst` is `None`, both of
+    /// which are `size` large
+    pub fn resolve(&self, dst: Option<&Framebuffer>, size: (u32, u32)) {
+        self.blit(dst, size, size, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteFramebuffers(1, &self.fbo);
+        }
+    }
+}
+
+/// A framebuffer together with the color texture and depth renderbuffer it renders into. With
+/// `samples` above zero everything is multisampled, and has to be resolved into a target without
+/// samples before the color can be sampled.
+pub struct RenderTarget {
+    framebuffer: Framebuffer,
+    color: Texture2D,
+    depth: Renderbuffer,
+}
+
+impl RenderTarget {
+    pub fn new(gl: &gl::Gl, width: u32, height: u32, color_format: TextureFormat, samples: u32) -> Result<RenderTarget, Error> {
+        let color = if samples > 0 {
+            Texture2D::new_multisampled(gl, width, height, color_format, samples)
+        } else {
+            Texture2D::new(gl, width, height, color_format)
+        };
+        let depth = Renderbuffer::new(gl, width, height, TextureFormat::Depth24Stencil8, samples);
+
+        let framebuffer = Framebuffer::new(gl);
+        framebuffer.bind();
+        framebuffer.attach_texture(Attachment::Color(0), &color);
+        framebuffer.attach_renderbuffer(Attachment::DepthStencil, &depth);
+        let complete = framebuffer.check_complete();
+        framebuffer.unbind();
+        complete?;
+
+        Ok(RenderTarget { framebuffer, color, depth })
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    pub fn color(&self) -> &Texture2D {
+        &self.color
+    }
+
+    pub fn depth(&self) -> &Renderbuffer {
+        &self.depth
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.color.width(), self.color.height())
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.color.samples()
+    }
+
+    pub fn bind(&self) {
+        self.framebuffer.bind();
+    }
+
+    pub fn unbind(&self) {
+        self.framebuffer.unbind();
+    }
+
+    /// Resolves the color into `dst`, which has to be the same size. Without samples this is a
+    /// plain copy.
+    pub fn resolve_into(&self, dst: &RenderTarget) {
+        self.framebuffer.resolve(Some(&dst.framebuffer), self.size());
+    }
+
+    /// Copies the color into the window, scaled to `window_size`. A multisampled target has to be
+    /// resolved into one without samples first, as the window's samples won't match.
+    pub fn blit_to_window(&self, window_size: (u32, u32)) {
+        self.framebuffer
+            .blit(None, self.size(), window_size, gl::COLOR_BUFFER_BIT, gl::LINEAR);
+    }
+}
@@ -1,9 +1,17 @@
 pub mod buffer;
 mod color_buffer;
 pub mod data;
+pub mod framebuffer;
+pub mod readback;
+mod renderbuffer;
 mod shader;
+mod texture;
 mod viewport;
 
 pub use self::color_buffer::ColorBuffer;
+pub use self::framebuffer::{Framebuffer, RenderTarget};
+pub use self::readback::FrameReadback;
+pub use self::renderbuffer::Renderbuffer;
 pub use self::shader::{Error, Program, Shader};
+pub use self::texture::{ColorSpace, Filter, Sampling, Texture, Texture2D, TextureFormat, Wrap};
 pub use self::viewport::Viewport;
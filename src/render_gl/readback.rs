@@ -0,0 +1,141 @@
+use image::RgbaImage;
+
+use crate::render_gl::buffer::{BufferUsage, PixelPackBuffer};
+use crate::render_gl::Framebuffer;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Failed to map the {} bytes of a read back frame", len)]
+    MapFailed { len: usize },
+}
+
+/// Reads a frame back from the GPU without stalling the frame that follows it. The pixels are
+/// copied into a pixel pack buffer, which is only mapped once a fence says the copy is done.
+pub struct FrameReadback {
+    gl: gl::Gl,
+    buffer: PixelPackBuffer<u8>,
+    fence: gl::types::GLsync,
+    size: (u32, u32),
+}
+
+impl FrameReadback {
+    /// Starts copying the color of `source`, or of the window's back buffer without one, which
+    /// have to be `size`. For the window that's before swapping, as the back buffer is undefined
+    /// afterwards.
+    pub fn start(gl: &gl::Gl, source: Option<&Framebuffer>, size: (u32, u32)) -> FrameReadback {
+        let buffer = PixelPackBuffer::new(gl);
+        buffer.bind();
+        buffer.allocate((size.0 * size.1 * 4) as usize, BufferUsage::Readback);
+
+        let fence = unsafe {
+            match source {
+                Some(framebuffer) => {
+                    gl.BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer.id());
+                    gl.ReadBuffer(gl::COLOR_ATTACHMENT0);
+                }
+                None => {
+                    gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+                    gl.ReadBuffer(gl::BACK);
+                }
+            }
+
+            // Into the bound pixel pack buffer, at offset 0
+            gl.ReadPixels(
+                0,
+                0,
+                size.0 as i32,
+                size.1 as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                std::ptr::null_mut(),
+            );
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+
+            gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0)
+        };
+        buffer.unbind();
+
+        FrameReadback {
+            gl: gl.clone(),
+            buffer,
+            fence,
+            size,
+        }
+    }
+
+    /// Whether the copy is done, so that [`Self::finish`] won't wait
+    pub fn is_ready(&self) -> bool {
+        let status = unsafe { self.gl.ClientWaitSync(self.fence, gl::SYNC_FLUSH_COMMANDS_BIT, 0) };
+        status == gl::ALREADY_SIGNALED || status == gl::CONDITION_SATISFIED
+    }
+
+    /// The frame, top row first, waiting for the copy if it isn't done yet. The window has no use
+    /// for alpha, so it's always opaque.
+    pub fn finish(self) -> Result<RgbaImage, Error> {
+        let (width, height) = self.size;
+        let len = (width * height * 4) as usize;
+
+        self.buffer.bind();
+        let pixels = unsafe {
+            // Wait a second at a time, only giving up if the wait itself fails
+            while let gl::TIMEOUT_EXPIRED = self.gl.ClientWaitSync(self.fence, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000_000) {}
+
+            let ptr =
+                self.gl
+                    .MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, len as gl::types::GLsizeiptr, gl::MAP_READ_BIT) as *const u8;
+            if ptr.is_null() {
+                None
+            } else {
+                let pixels = std::slice::from_raw_parts(ptr, len).to_vec();
+                self.gl.UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+                Some(pixels)
+            }
+        };
+        self.buffer.unbind();
+
+        pixels
+            .map(|pixels| opaque_top_down(width, height, pixels))
+            .ok_or(Error::MapFailed { len })
+    }
+}
+
+// Turns `width` x `height` RGBA pixels as GL reads them, rows starting at the bottom, into an
+// opaque image with its top row first
+fn opaque_top_down(width: u32, height: u32, pixels: Vec<u8>) -> RgbaImage {
+    let mut image = RgbaImage::from_raw(width, height, pixels).expect("the buffer fits the frame");
+    image.pixels_mut().for_each(|pixel| pixel[3] = 255);
+
+    image::imageops::flip_vertical(&image)
+}
+
+impl Drop for FrameReadback {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteSync(self.fence);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::opaque_top_down;
+
+    #[test]
+    fn frames_are_flipped_and_opaque() {
+        // Two rows of two pixels, bottom row first
+        let pixels = vec![
+            1, 2, 3, 0, 4, 5, 6, 128, //
+            7, 8, 9, 255, 10, 11, 12, 1,
+        ];
+
+        let image = opaque_top_down(2, 2, pixels);
+
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(*image.get_pixel(0, 0), Rgba([7, 8, 9, 255]));
+        assert_eq!(*image.get_pixel(1, 0), Rgba([10, 11, 12, 255]));
+        assert_eq!(*image.get_pixel(0, 1), Rgba([1, 2, 3, 255]));
+        assert_eq!(*image.get_pixel(1, 1), Rgba([4, 5, 6, 255]));
+    }
+}
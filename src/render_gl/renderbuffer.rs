@@ -0,0 +1,71 @@
+use gl;
+
+use crate::render_gl::TextureFormat;
+
+/// Storage for a framebuffer attachment which is never sampled, like the depth buffer of a scene
+/// that's only read back by blitting
+pub struct Renderbuffer {
+    gl: gl::Gl,
+    rbo: gl::types::GLuint,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    samples: u32,
+}
+
+impl Renderbuffer {
+    /// `samples` of zero gives a renderbuffer which isn't multisampled
+    pub fn new(gl: &gl::Gl, width: u32, height: u32, format: TextureFormat, samples: u32) -> Renderbuffer {
+        let mut rbo: gl::types::GLuint = 0;
+
+        unsafe {
+            gl.GenRenderbuffers(1, &mut rbo);
+            gl.BindRenderbuffer(gl::RENDERBUFFER, rbo);
+            gl.RenderbufferStorageMultisample(
+                gl::RENDERBUFFER,
+                samples as i32,
+                format.gl_internal_format(),
+                width as i32,
+                height as i32,
+            );
+            gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
+        }
+
+        Renderbuffer {
+            gl: gl.clone(),
+            rbo,
+            width,
+            height,
+            format,
+            samples,
+        }
+    }
+
+    pub fn id(&self) -> gl::types::GLuint {
+        self.rbo
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+impl Drop for Renderbuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteRenderbuffers(1, &self.rbo);
+        }
+    }
+}
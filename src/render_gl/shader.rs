@@ -2,7 +2,7 @@ use std;
 use std::ffi::{CStr, CString};
 
 use gl;
-use nalgebra::{Matrix4, Vector3, Vector4};
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
 
 use crate::resources;
 use crate::resources::Resources;
@@ -23,6 +23,10 @@ pub enum Error {
     LinkError { name: String, message: String },
     #[fail(display = "Uniform with name \"{}\" not found", name)]
     UniformNameError { name: String },
+    #[fail(display = "Uniform block with name \"{}\" not found", name)]
+    UniformBlockNameError { name: String },
+    #[fail(display = "Shader storage block with name \"{}\" not found", name)]
+    StorageBlockNameError { name: String },
 }
 
 pub struct Shader {
@@ -84,9 +88,20 @@ impl Program {
     pub fn from_res(gl: &gl::Gl, res: &Resources, name: &str) -> Result<Program, Error> {
         const POSSIBLE_EXT: [&str; 2] = [".vert", ".frag"];
 
-        let shaders = POSSIBLE_EXT
+        let shader_names = POSSIBLE_EXT
             .iter()
-            .map(|file_extension| Shader::from_res(gl, res, &format!("{}{}", name, file_extension)))
+            .map(|file_extension| format!("{}{}", name, file_extension))
+            .collect::<Vec<String>>();
+
+        Program::from_res_shaders(gl, res, name, &shader_names)
+    }
+
+    /// Like [`Self::from_res`], but with explicitly named shader resources, so that programs can
+    /// share shader stages (e.g. two vertex shaders feeding the same fragment shader)
+    pub fn from_res_shaders<S: AsRef<str>>(gl: &gl::Gl, res: &Resources, name: &str, shader_names: &[S]) -> Result<Program, Error> {
+        let shaders = shader_names
+            .iter()
+            .map(|shader_name| Shader::from_res(gl, res, shader_name.as_ref()))
             .collect::<Result<Vec<Shader>, Error>>()?;
 
         Program::from_shaders(gl, &shaders[..]).map_err(|message| Error::LinkError {
@@ -161,6 +176,42 @@ impl Program {
         Ok(loc)
     }
 
+    /// Makes the uniform block `block_name` read from the uniform buffer bound to `binding`
+    pub fn set_uniform_block_binding(&self, block_name: &str, binding: u32) -> Result<(), Error> {
+        let cname = std::ffi::CString::new(block_name).expect("CString::new failed");
+        let index = unsafe { self.gl.GetUniformBlockIndex(self.id(), cname.as_ptr()) };
+
+        if index == gl::INVALID_INDEX {
+            return Err(Error::UniformBlockNameError {
+                name: block_name.to_string(),
+            });
+        }
+
+        unsafe {
+            self.gl.UniformBlockBinding(self.id(), index, binding);
+        }
+
+        Ok(())
+    }
+
+    /// Makes the shader storage block `block_name` use the storage buffer bound to `binding`
+    pub fn set_storage_block_binding(&self, block_name: &str, binding: u32) -> Result<(), Error> {
+        let cname = std::ffi::CString::new(block_name).expect("CString::new failed");
+        let index = unsafe { self.gl.GetProgramResourceIndex(self.id(), gl::SHADER_STORAGE_BLOCK, cname.as_ptr()) };
+
+        if index == gl::INVALID_INDEX {
+            return Err(Error::StorageBlockNameError {
+                name: block_name.to_string(),
+            });
+        }
+
+        unsafe {
+            self.gl.ShaderStorageBlockBinding(self.id(), index, binding);
+        }
+
+        Ok(())
+    }
+
     pub fn set_vec4_uniform(&self, loc: i32, vec: &Vector4<f32>) {
         unsafe {
             self.gl.Uniform4f(loc, vec[0], vec[1], vec[2], vec[3]);
@@ -173,6 +224,12 @@ impl Program {
         }
     }
 
+    pub fn set_vec2_uniform(&self, loc: i32, vec: &Vector2<f32>) {
+        unsafe {
+            self.gl.Uniform2f(loc, vec[0], vec[1]);
+        }
+    }
+
     pub fn set_mat4_uniform(&self, loc: i32, mat: &Matrix4<f32>) {
         unsafe {
             self.gl.UniformMatrix4fv(loc, 1, gl::FALSE, mat.as_slice().as_ptr());
@@ -203,6 +260,13 @@ impl Program {
         }
     }
 
+    /// Also used for samplers, whose value is the texture unit to sample
+    pub fn set_int_uniform(&self, loc: i32, int: i32) {
+        unsafe {
+            self.gl.Uniform1i(loc, int);
+        }
+    }
+
     pub fn set_uint_uniform(&self, loc: i32, uint: usize) {
         unsafe {
             self.gl.Uniform1ui(loc, uint as u32);
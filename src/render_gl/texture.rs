@@ -0,0 +1,374 @@
+use gl;
+use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+/// How the color values of an image are encoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Values are proportional to light intensity, like data textures
+    Linear,
+    /// Gamma encoded, like practically every color image. Sampling decodes it into linear colors.
+    Srgb,
+}
+
+/// How an image texture is sampled
+#[derive(Debug, Copy, Clone)]
+pub struct Sampling {
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    /// Generate a full mipmap chain and filter between its levels with `min_filter`
+    pub mipmaps: bool,
+    /// Horizontally
+    pub wrap_u: Wrap,
+    /// Vertically
+    pub wrap_v: Wrap,
+    /// Samples taken along oblique viewing angles, clamped to what the driver supports. 1 turns
+    /// anisotropic filtering off.
+    pub anisotropy: f32,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling {
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mipmaps: true,
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::Repeat,
+            anisotropy: 8.0,
+        }
+    }
+}
+
+pub struct Texture {
+    gl: gl::Gl,
+    id: gl::types::GLuint,
+    target: gl::types::GLenum,
+}
+
+impl Texture {
+    /// An array of `layers` cube maps of `size` x `size` 32-bit float depth texels, set up for
+    /// depth comparisons (`samplerCubeArrayShadow`) with linear filtering
+    pub fn new_depth_cube_array(gl: &gl::Gl, size: u32, layers: u32) -> Texture {
+        let texture = Texture::new(gl, gl::TEXTURE_CUBE_MAP_ARRAY);
+
+        texture.bind();
+        unsafe {
+            // Every cube map takes up 6 layer-faces
+            gl.TexStorage3D(
+                gl::TEXTURE_CUBE_MAP_ARRAY,
+                1,
+                gl::DEPTH_COMPONENT32F,
+                size as i32,
+                size as i32,
+                (layers.max(1) * 6) as i32,
+            );
+            gl.TexParameteri(gl::TEXTURE_CUBE_MAP_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl.TexParameteri(gl::TEXTURE_CUBE_MAP_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl.TexParameteri(gl::TEXTURE_CUBE_MAP_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl.TexParameteri(gl::TEXTURE_CUBE_MAP_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl.TexParameteri(gl::TEXTURE_CUBE_MAP_ARRAY, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+            gl.TexParameteri(
+                gl::TEXTURE_CUBE_MAP_ARRAY,
+                gl::TEXTURE_COMPARE_MODE,
+                gl::COMPARE_REF_TO_TEXTURE as i32,
+            );
+            gl.TexParameteri(gl::TEXTURE_CUBE_MAP_ARRAY, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+        }
+        texture.unbind();
+
+        texture
+    }
+
+    /// Uploads `image` as an 8-bit RGBA 2D texture. Images are stored top row first, so they're
+    /// flipped to put UV (0, 0) at the bottom left, as OBJ and most modelling tools expect.
+    pub fn from_image(gl: &gl::Gl, image: &DynamicImage, sampling: &Sampling, color_space: ColorSpace) -> Texture {
+        let rgba = image.flipv().to_rgba8();
+        let (width, height) = rgba.dimensions();
+        let levels = if sampling.mipmaps {
+            32 - width.max(height).max(1).leading_zeros()
+        } else {
+            1
+        };
+
+        let texture = Texture::new(gl, gl::TEXTURE_2D);
+
+        texture.bind();
+        unsafe {
+            let internal_format = match color_space {
+                ColorSpace::Linear => gl::RGBA8,
+                ColorSpace::Srgb => gl::SRGB8_ALPHA8,
+            };
+            gl.TexStorage2D(gl::TEXTURE_2D, levels as i32, internal_format, width as i32, height as i32);
+            gl.TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                rgba.as_ptr() as *const gl::types::GLvoid,
+            );
+            if sampling.mipmaps {
+                gl.GenerateMipmap(gl::TEXTURE_2D);
+            }
+        }
+        texture.set_sampling(sampling);
+        texture.unbind();
+
+        texture
+    }
+
+    /// A single opaque white texel, for drawing untextured meshes with a textured program
+    pub fn white(gl: &gl::Gl) -> Texture {
+        let white = ImageBuffer::from_pixel(1, 1, Rgba([255u8, 255, 255, 255]));
+
+        Texture::from_image(
+            gl,
+            &DynamicImage::ImageRgba8(white),
+            &Sampling {
+                mipmaps: false,
+                ..Sampling::default()
+            },
+            ColorSpace::Linear,
+        )
+    }
+
+    // The texture has to be bound
+    fn set_sampling(&self, sampling: &Sampling) {
+        let filter = |filter: Filter| match filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear => gl::LINEAR,
+        };
+        let min_filter = match (sampling.mipmaps, sampling.min_filter) {
+            (false, min_filter) => filter(min_filter),
+            (true, Filter::Nearest) => gl::NEAREST_MIPMAP_NEAREST,
+            (true, Filter::Linear) => gl::LINEAR_MIPMAP_LINEAR,
+        };
+        let wrap = |wrap: Wrap| match wrap {
+            Wrap::Repeat => gl::REPEAT,
+            Wrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+            Wrap::ClampToEdge => gl::CLAMP_TO_EDGE,
+        };
+
+        unsafe {
+            self.gl.TexParameteri(self.target, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+            self.gl
+                .TexParameteri(self.target, gl::TEXTURE_MAG_FILTER, filter(sampling.mag_filter) as i32);
+            self.gl.TexParameteri(self.target, gl::TEXTURE_WRAP_S, wrap(sampling.wrap_u) as i32);
+            self.gl.TexParameteri(self.target, gl::TEXTURE_WRAP_T, wrap(sampling.wrap_v) as i32);
+
+            if sampling.anisotropy > 1.0 {
+                let mut max_anisotropy = 1.0;
+                self.gl.GetFloatv(gl::MAX_TEXTURE_MAX_ANISOTROPY, &mut max_anisotropy);
+                self.gl
+                    .TexParameterf(self.target, gl::TEXTURE_MAX_ANISOTROPY, sampling.anisotropy.min(max_anisotropy));
+            }
+        }
+    }
+
+    fn new(gl: &gl::Gl, target: gl::types::GLenum) -> Texture {
+        let mut id: gl::types::GLuint = 0;
+
+        unsafe {
+            gl.GenTextures(1, &mut id);
+        }
+
+        Texture {
+            gl: gl.clone(),
+            id,
+            target,
+        }
+    }
+
+    pub fn id(&self) -> gl::types::GLuint {
+        self.id
+    }
+
+    pub fn target(&self) -> gl::types::GLenum {
+        self.target
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            self.gl.BindTexture(self.target, self.id);
+        }
+    }
+
+    pub fn unbind(&self) {
+        unsafe {
+            self.gl.BindTexture(self.target, 0);
+        }
+    }
+
+    /// Binds the texture to texture unit `unit`, the value of the sampler uniforms reading it
+    pub fn bind_to_unit(&self, unit: u32) {
+        unsafe {
+            self.gl.ActiveTexture(gl::TEXTURE0 + unit);
+            self.gl.BindTexture(self.target, self.id);
+            self.gl.ActiveTexture(gl::TEXTURE0);
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+/// Internal formats of 2D textures and renderbuffers used as render targets
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureFormat {
+    Rgba8,
+    Srgb8Alpha8,
+    Rgba16F,
+    Rgba32F,
+    Depth32F,
+    Depth24Stencil8,
+}
+
+impl TextureFormat {
+    pub fn gl_internal_format(self) -> gl::types::GLenum {
+        match self {
+            TextureFormat::Rgba8 => gl::RGBA8,
+            TextureFormat::Srgb8Alpha8 => gl::SRGB8_ALPHA8,
+            TextureFormat::Rgba16F => gl::RGBA16F,
+            TextureFormat::Rgba32F => gl::RGBA32F,
+            TextureFormat::Depth32F => gl::DEPTH_COMPONENT32F,
+            TextureFormat::Depth24Stencil8 => gl::DEPTH24_STENCIL8,
+        }
+    }
+
+    pub fn is_depth(self) -> bool {
+        matches!(self, TextureFormat::Depth32F | TextureFormat::Depth24Stencil8)
+    }
+
+    pub fn has_stencil(self) -> bool {
+        self == TextureFormat::Depth24Stencil8
+    }
+}
+
+/// A single level 2D texture with immutable storage, optionally multisampled
+pub struct Texture2D {
+    texture: Texture,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    samples: u32,
+}
+
+impl Texture2D {
+    /// Linearly filtered and clamped to the edge, ready to be sampled by post-processing passes
+    pub fn new(gl: &gl::Gl, width: u32, height: u32, format: TextureFormat) -> Texture2D {
+        let texture = Texture::new(gl, gl::TEXTURE_2D);
+
+        texture.bind();
+        unsafe {
+            gl.TexStorage2D(gl::TEXTURE_2D, 1, format.gl_internal_format(), width as i32, height as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        }
+        texture.unbind();
+
+        Texture2D {
+            texture,
+            width,
+            height,
+            format,
+            samples: 0,
+        }
+    }
+
+    /// A texture with `samples` samples per texel, which can only be rendered into and resolved
+    /// by blitting, or read with `texelFetch` from a `sampler2DMS`
+    pub fn new_multisampled(gl: &gl::Gl, width: u32, height: u32, format: TextureFormat, samples: u32) -> Texture2D {
+        let texture = Texture::new(gl, gl::TEXTURE_2D_MULTISAMPLE);
+
+        texture.bind();
+        unsafe {
+            gl.TexStorage2DMultisample(
+                gl::TEXTURE_2D_MULTISAMPLE,
+                samples as i32,
+                format.gl_internal_format(),
+                width as i32,
+                height as i32,
+                gl::TRUE,
+            );
+        }
+        texture.unbind();
+
+        Texture2D {
+            texture,
+            width,
+            height,
+            format,
+            samples,
+        }
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    /// Zero for textures which aren't multisampled
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn bind_to_unit(&self, unit: u32) {
+        self.texture.bind_to_unit(unit);
+    }
+
+    /// Downloads the texture as 8-bit RGBA, top row first. Only for color textures without
+    /// samples, floating point colors are clamped to [0, 1].
+    pub fn read_rgba8(&self) -> RgbaImage {
+        let mut pixels = vec![0u8; (self.width * self.height * 4) as usize];
+
+        unsafe {
+            self.texture.gl.GetTextureImage(
+                self.texture.id,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.len() as i32,
+                pixels.as_mut_ptr() as *mut gl::types::GLvoid,
+            );
+        }
+
+        let image = RgbaImage::from_raw(self.width, self.height, pixels).expect("the buffer fits the texture");
+
+        // GL's rows start at the bottom
+        image::imageops::flip_vertical(&image)
+    }
+}
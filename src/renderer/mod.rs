@@ -0,0 +1,112 @@
+use nalgebra::Matrix4;
+
+use crate::models::material::{MaterialHandle, MaterialRegistry};
+use crate::models::mesh_registry::{MeshHandle, MeshRegistry};
+use crate::models::world_model::Model;
+use crate::primitives::camera::Camera;
+use crate::primitives::deferred::RenderPath;
+use crate::primitives::environment::Environment;
+use crate::primitives::hdr::HdrSettings;
+use crate::primitives::light::Color;
+use crate::primitives::object_draw::ShadingModel;
+use crate::primitives::post_process::PostProcessConfig;
+use crate::primitives::shadows::ShadowSettings;
+use crate::primitives::spatial::Location;
+use crate::primitives::spotlight::Spotlight;
+use crate::primitives::ssao::SsaoSettings;
+
+pub mod opengl;
+pub mod path_tracer;
+pub mod software;
+
+/// The parts of a scene which don't change from frame to frame, handed over to the renderer
+pub(crate) struct SceneAssets {
+    pub meshes: MeshRegistry,
+    pub materials: MaterialRegistry,
+    pub environment: Environment,
+}
+
+/// How frames are rendered. The CPU renderers only know about the tone mapping and exposure of
+/// `hdr` and the light emissive strength, and the software renderer about the shading too.
+pub(crate) struct RenderSettings {
+    // Draw all objects with a single instanced draw call, rather than one draw call per object
+    pub instanced_objects: bool,
+    pub render_path: RenderPath,
+    pub shadows: ShadowSettings,
+    pub ssao: SsaoSettings,
+    pub shading: ShadingModel,
+    pub hdr: HdrSettings,
+    // Light gizmos are drawn this many times brighter than their light, so that they glow
+    pub light_emissive_strength: f32,
+    pub post_process: PostProcessConfig,
+}
+
+/// Where a mesh is drawn, split up like [`Model::model`]
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Transform {
+    pub scale: f32,
+    pub translation: Matrix4<f32>,
+    pub rotation: Matrix4<f32>,
+}
+
+impl Transform {
+    pub fn of(model: &impl Model) -> Transform {
+        let (scale, translation, rotation) = model.model();
+
+        Transform {
+            scale,
+            translation,
+            rotation,
+        }
+    }
+}
+
+impl Model for Transform {
+    fn model(&self) -> (f32, Matrix4<f32>, Matrix4<f32>) {
+        (self.scale, self.translation, self.rotation)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct SceneLight {
+    pub spotlight: Spotlight,
+    pub location: Location,
+}
+
+/// Draws frames of a scene. A frame starts with [`Self::clear`], then gets its camera, lights and
+/// meshes, and ends with [`Self::present`]. Meshes may be drawn as they come in, or only once the
+/// frame is presented, so the camera and lights have to be set before presenting.
+pub(crate) trait Renderer {
+    /// Starts a new frame, with nothing drawn yet
+    fn clear(&mut self);
+
+    /// Looks through `camera`, with a perspective projection fitting the renderer's size
+    fn set_camera(&mut self, camera: &Camera);
+
+    fn set_lights(&mut self, lights: &[SceneLight]);
+
+    /// Draws `mesh` lit by the lights, with the surface of `material` tinted by `color`
+    fn draw_mesh(&mut self, mesh: MeshHandle, material: MaterialHandle, transform: &Transform, color: Color);
+
+    /// Draws `mesh` in a solid glowing `color` which light doesn't affect, to show where the lights
+    /// are
+    fn draw_light_mesh(&mut self, mesh: MeshHandle, color: Color, transform: &Transform);
+
+    /// Finishes the frame
+    fn present(&mut self);
+}
+
+/// Settings for testing the CPU renderers, with nothing they don't support turned on
+#[cfg(test)]
+pub(crate) fn test_settings(shading: ShadingModel) -> RenderSettings {
+    RenderSettings {
+        instanced_objects: false,
+        render_path: RenderPath::Forward,
+        shadows: ShadowSettings::default(),
+        ssao: SsaoSettings::default(),
+        shading,
+        hdr: HdrSettings::default(),
+        light_emissive_strength: 1.0,
+        post_process: PostProcessConfig::default(),
+    }
+}
@@ -0,0 +1,361 @@
+use image::RgbaImage;
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::models::cube::Cube;
+use crate::models::material::{MaterialHandle, MaterialRegistry};
+use crate::models::mesh_registry::{MeshHandle, MeshRegistry};
+use crate::models::texture_registry::TextureRegistry;
+use crate::models::world_model::Model;
+use crate::primitives::camera::Camera;
+use crate::primitives::deferred::{Deferred, RenderPath};
+use crate::primitives::draw_list::DrawList;
+use crate::primitives::environment::{Environment, EnvironmentMap};
+use crate::primitives::hdr::{Hdr, HdrSettings};
+use crate::primitives::light::consts::WHITE;
+use crate::primitives::light::Color;
+use crate::primitives::light_culling::LightCulling;
+use crate::primitives::object_draw::{InstanceData, ObjectsDraw, ShadingModel};
+use crate::primitives::post_process::{PostProcessChain, PostProcessPass};
+use crate::primitives::projection::perspective;
+use crate::primitives::shadows::ShadowMaps;
+use crate::primitives::spotlight_draw::SpotlightDraw;
+use crate::primitives::ssao::Ssao;
+use crate::primitives::uniform_blocks::UniformBlocks;
+use crate::render_gl::{FrameReadback, RenderTarget, Sampling, TextureFormat, Viewport};
+use crate::renderer::{RenderSettings, Renderer, SceneAssets, SceneLight, Transform};
+use crate::resources::Resources;
+
+/// Renders through OpenGL, with everything the GPU can do: tiled forward or deferred shading,
+/// shadow maps, SSAO, HDR with bloom, and the post-processing chain. Draws are collected and only
+/// rendered once the frame is presented, as shadows need all objects before anything is lit.
+pub(crate) struct GlRenderer {
+    gl: gl::Gl,
+    settings: RenderSettings,
+
+    meshes: MeshRegistry,
+    textures: TextureRegistry,
+    materials: MaterialRegistry,
+    environment: Environment,
+    environment_map: EnvironmentMap,
+    uniform_blocks: UniformBlocks,
+    light_culling: LightCulling,
+    shadow_maps: ShadowMaps,
+    ssao: Ssao,
+    deferred: Deferred,
+    hdr: Hdr,
+    post_process: PostProcessChain,
+    objects_draw: ObjectsDraw,
+    spotslights_draw: SpotlightDraw,
+
+    projection: Matrix4<f32>,
+    viewport_size: (u32, u32),
+    // Rendered into instead of the window
+    output: Option<RenderTarget>,
+
+    // The frame being drawn
+    view: (Matrix4<f32>, Matrix4<f32>, Vector3<f32>),
+    lights: Vec<SceneLight>,
+    objects: DrawList<(Transform, Color)>,
+    light_meshes: Vec<(MeshHandle, Color, Transform)>,
+}
+
+impl GlRenderer {
+    pub fn new(
+        res: &Resources,
+        gl: &gl::Gl,
+        assets: SceneAssets,
+        settings: RenderSettings,
+        viewport_size: (u32, u32),
+    ) -> Result<GlRenderer, failure::Error> {
+        let SceneAssets {
+            mut meshes,
+            materials,
+            environment,
+        } = assets;
+
+        // Deferred light volumes
+        let cube = meshes.load("cube", || Cube::new(WHITE).verticies);
+        meshes.upload(gl);
+
+        let textures = TextureRegistry::new(gl, Sampling::default());
+
+        let objects_draw = if settings.instanced_objects {
+            ObjectsDraw::new_instanced(res, gl, &meshes)?
+        } else {
+            ObjectsDraw::new(res, gl, &meshes)?
+        };
+
+        let hdr = Hdr::new(res, gl, Self::hdr_settings(&settings), viewport_size)?;
+        let post_process = PostProcessChain::new(res, gl, &settings.post_process, viewport_size)?;
+
+        objects_draw.set_shadow_settings(&settings.shadows);
+        objects_draw.set_shading(settings.shading, &environment);
+
+        let shadow_maps = ShadowMaps::new(res, gl, &meshes, settings.shadows, settings.instanced_objects)?;
+        let ssao = Ssao::new(res, gl, &meshes, settings.ssao, settings.instanced_objects, viewport_size)?;
+        let deferred = Deferred::new(res, gl, &meshes, cube, settings.instanced_objects, viewport_size)?;
+        deferred.set_shadow_settings(&settings.shadows);
+        deferred.set_shading(settings.shading, &environment);
+
+        let spotlight_draw = SpotlightDraw::new(res, gl, &meshes)?;
+        spotlight_draw.set_emissive_strength(settings.light_emissive_strength);
+
+        Ok(GlRenderer {
+            gl: gl.clone(),
+            settings,
+
+            textures,
+            materials,
+            environment_map: EnvironmentMap::new(gl, &environment),
+            environment,
+            uniform_blocks: UniformBlocks::new(gl),
+            light_culling: LightCulling::new(gl),
+            shadow_maps,
+            ssao,
+            deferred,
+            hdr,
+            post_process,
+            objects_draw,
+            spotslights_draw: spotlight_draw,
+            meshes,
+
+            projection: perspective(viewport_size.0 as f32 / viewport_size.1.max(1) as f32),
+            viewport_size,
+            output: None,
+
+            view: (Matrix4::identity(), Matrix4::identity(), Vector3::zeros()),
+            lights: vec![],
+            objects: DrawList::new(),
+            light_meshes: vec![],
+        })
+    }
+
+    pub fn resize(&mut self, size: (u32, u32)) -> Result<(), failure::Error> {
+        // Minimized windows have no height
+        self.projection = perspective(size.0 as f32 / size.1.max(1) as f32);
+        self.viewport_size = size;
+        self.hdr.resize(self.viewport_size)?;
+        self.ssao.resize(self.viewport_size)?;
+        self.deferred.resize(self.viewport_size)?;
+        self.post_process.resize(self.viewport_size)?;
+
+        Ok(())
+    }
+
+    pub fn output(&self) -> Option<&RenderTarget> {
+        self.output.as_ref()
+    }
+
+    pub fn viewport_size(&self) -> (u32, u32) {
+        self.viewport_size
+    }
+
+    /// Starts reading back the last presented frame, from the output or from the window
+    pub fn read_frame(&self) -> FrameReadback {
+        FrameReadback::start(&self.gl, self.output.as_ref().map(RenderTarget::framebuffer), self.viewport_size)
+    }
+
+    /// Renders a frame with `draw` into an offscreen target of `size`, and reads it back. Afterwards
+    /// the renderer is back at its own size and output.
+    pub fn render_offscreen(&mut self, size: (u32, u32), draw: impl FnOnce(&mut GlRenderer)) -> Result<RgbaImage, failure::Error> {
+        let previous_size = self.viewport_size;
+        let target = RenderTarget::new(&self.gl, size.0, size.1, TextureFormat::Rgba8, 0)?;
+        let previous_output = self.output.replace(target);
+
+        let frame = self.resize_if_needed(size).map(|()| {
+            Viewport::for_window(size.0 as i32, size.1 as i32).set_used(&self.gl);
+            draw(self);
+            self.output().expect("The output was just set").color().read_rgba8()
+        });
+
+        self.output = previous_output;
+        Viewport::for_window(previous_size.0 as i32, previous_size.1 as i32).set_used(&self.gl);
+        self.resize_if_needed(previous_size)?;
+
+        frame
+    }
+
+    // Resizing reallocates all intermediate targets, even to the same size
+    fn resize_if_needed(&mut self, size: (u32, u32)) -> Result<(), failure::Error> {
+        if size == self.viewport_size {
+            Ok(())
+        } else {
+            self.resize(size)
+        }
+    }
+
+    pub fn toggle_shading(&mut self) {
+        self.settings.shading = match self.settings.shading {
+            ShadingModel::Classic => ShadingModel::Pbr,
+            ShadingModel::Pbr => ShadingModel::Classic,
+        };
+        self.objects_draw.set_shading(self.settings.shading, &self.environment);
+        self.deferred.set_shading(self.settings.shading, &self.environment);
+    }
+
+    // The deferred path lights a G-buffer without samples, so the scene can't have any either
+    fn hdr_settings(settings: &RenderSettings) -> HdrSettings {
+        match settings.render_path {
+            RenderPath::Forward => settings.hdr,
+            RenderPath::Deferred => HdrSettings {
+                samples: 0,
+                ..settings.hdr
+            },
+        }
+    }
+
+    pub fn toggle_render_path(&mut self) -> Result<(), failure::Error> {
+        self.settings.render_path = match self.settings.render_path {
+            RenderPath::Forward => RenderPath::Deferred,
+            RenderPath::Deferred => RenderPath::Forward,
+        };
+        self.hdr.settings.samples = Self::hdr_settings(&self.settings).samples;
+        self.hdr.resize(self.viewport_size)?;
+
+        Ok(())
+    }
+
+    pub fn toggle_ambient_occlusion(&mut self) {
+        self.ssao.settings.enabled = !self.ssao.settings.enabled;
+    }
+
+    pub fn post_process_passes(&self) -> &[PostProcessPass] {
+        self.post_process.passes()
+    }
+
+    /// Whether the pass is enabled now, or `None` if there's no pass at `index`
+    pub fn toggle_post_process(&mut self, index: usize) -> Option<bool> {
+        self.post_process.toggle(index)
+    }
+
+    fn render(&self) {
+        let gl = &self.gl;
+        let output = self.output.as_ref();
+        let objects = &self.objects;
+
+        self.uniform_blocks.bind();
+
+        let shadow_layers = self.shadow_maps.assign_layers(self.lights.iter().map(|light| &light.spotlight));
+        let casters = self
+            .lights
+            .iter()
+            .zip(shadow_layers.iter())
+            .filter_map(|(light, layer)| layer.map(|layer| (light.location, light.spotlight.spot_radius, layer)));
+        self.shadow_maps
+            .render(gl, &self.uniform_blocks, casters, self.viewport_size, |depth_draw| {
+                self.draw_objects(depth_draw, objects)
+            });
+
+        let (view_rotation, view_translation, view_location) = self.view;
+
+        self.uniform_blocks
+            .set_camera(&view_rotation, &view_translation, &view_location, &self.projection);
+
+        self.ssao.render(&self.projection, self.viewport_size, |normals_draw| {
+            self.draw_objects(normals_draw, objects)
+        });
+
+        let (width, height) = self.viewport_size;
+        self.light_culling.set_spotlights(
+            self.lights
+                .iter()
+                .zip(shadow_layers)
+                .map(|(light, layer)| (&light.spotlight, &light.location, layer)),
+            &(self.projection * view_rotation * view_translation),
+            width,
+            height,
+        );
+        self.light_culling.bind();
+        self.shadow_maps.bind();
+        self.ssao.bind();
+        self.environment_map.bind();
+
+        match self.settings.render_path {
+            RenderPath::Forward => {
+                self.hdr.begin();
+                self.draw_objects(&self.objects_draw, objects);
+            }
+            RenderPath::Deferred => {
+                self.deferred
+                    .render_gbuffer(|gbuffer_draw| self.draw_objects(gbuffer_draw, objects));
+                self.hdr.begin();
+                self.deferred.light(self.hdr.scene(), self.lights.len());
+            }
+        }
+
+        if !self.light_meshes.is_empty() {
+            self.spotslights_draw.prepare_for_draws();
+        }
+
+        self.light_meshes.iter().for_each(|(mesh, color, transform)| {
+            self.spotslights_draw
+                .set_solid_color(&Vector4::<f32>::new(color.r, color.g, color.b, color.a));
+
+            self.spotslights_draw.draw(
+                gl,
+                transform.scale,
+                &transform.translation,
+                &transform.rotation,
+                &self.meshes.range(*mesh),
+            );
+        });
+
+        self.hdr.finish(self.post_process.input().or(output));
+        self.post_process.run(output);
+    }
+
+    fn draw_objects(&self, objects_draw: &ObjectsDraw, objects: &DrawList<(Transform, Color)>) {
+        if objects.is_empty() {
+            return;
+        }
+
+        objects_draw.prepare_for_draws();
+
+        objects.batches().for_each(|batch| {
+            objects_draw.set_material(self.materials.get(batch.material), &self.textures);
+            let range = self.meshes.range(batch.mesh);
+
+            if self.settings.instanced_objects {
+                let instances: Vec<InstanceData> = batch
+                    .objects
+                    .iter()
+                    .map(|(transform, color)| InstanceData::new(&transform.model_matrix(), *color))
+                    .collect();
+
+                objects_draw.draw_instanced(&self.gl, &instances, &range);
+            } else {
+                batch.objects.iter().for_each(|(transform, color)| {
+                    objects_draw.set_color(color);
+                    objects_draw.draw(&self.gl, transform.scale, &transform.translation, &transform.rotation, &range);
+                });
+            }
+        });
+    }
+}
+
+impl Renderer for GlRenderer {
+    fn clear(&mut self) {
+        self.objects.clear();
+        self.light_meshes.clear();
+    }
+
+    fn set_camera(&mut self, camera: &Camera) {
+        self.view = camera.view();
+    }
+
+    fn set_lights(&mut self, lights: &[SceneLight]) {
+        self.lights = lights.to_vec();
+    }
+
+    fn draw_mesh(&mut self, mesh: MeshHandle, material: MaterialHandle, transform: &Transform, color: Color) {
+        self.objects.push(mesh, material, (*transform, color));
+    }
+
+    fn draw_light_mesh(&mut self, mesh: MeshHandle, color: Color, transform: &Transform) {
+        self.light_meshes.push((mesh, color, *transform));
+    }
+
+    fn present(&mut self) {
+        self.render();
+    }
+}
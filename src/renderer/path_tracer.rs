@@ -0,0 +1,890 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use image::{Rgb, Rgb32FImage, RgbaImage};
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::models::material::{Material, MaterialHandle, MaterialRegistry};
+use crate::models::mesh_registry::{MeshHandle, MeshRegistry};
+use crate::models::world_model::Model;
+use crate::primitives::camera::Camera;
+use crate::primitives::light::Color;
+use crate::primitives::projection::perspective;
+use crate::renderer::software::{distribution_ggx, fresnel_schlick, geometry_smith, tone_map, EnvironmentLevels};
+use crate::renderer::{RenderSettings, Renderer, SceneAssets, SceneLight, Transform};
+
+#[derive(Debug, Clone, Fail)]
+pub enum Error {
+    #[fail(display = "The camera's view projection can't be inverted, so there are no rays to trace")]
+    SingularCamera,
+}
+
+// Pixels are traced in square tiles, which threads take turns picking up
+const TILE_SIZE: u32 = 16;
+
+// Rays leave surfaces this far off them, so that they don't hit the surface they left
+const RAY_OFFSET: f32 = 1e-3;
+
+// Paths which made it this many bounces may be cut short by russian roulette
+const ROULETTE_BOUNCES: u32 = 3;
+
+/// How much work goes into every pixel
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct TraceSettings {
+    pub samples: u32,
+    /// Bounces after the first hit, 0 only lights surfaces directly
+    pub bounces: u32,
+    pub threads: usize,
+    /// Every seed traces the same image, no matter the number of threads
+    pub seed: u64,
+}
+
+/// Renders with unidirectional path tracing on the CPU, which gets the reflections, shadows and
+/// indirect light the real-time renderers can only approximate. Every surface uses the PBR
+/// parameters of its material, the spotlights are point lights falling off like in PBR shading,
+/// and light meshes glow in their color without reflecting anything. Draws are collected until
+/// the frame is presented, which builds a BVH over them and traces the whole frame.
+pub(crate) struct PathTracer {
+    settings: RenderSettings,
+    trace: TraceSettings,
+    meshes: MeshRegistry,
+    materials: MaterialRegistry,
+    environment: EnvironmentLevels,
+    size: (u32, u32),
+    projection: Matrix4<f32>,
+
+    // The frame being drawn, without rays to trace if the camera can't be inverted
+    inverse_view_projection: Option<Matrix4<f32>>,
+    lights: Vec<SceneLight>,
+    triangles: Vec<Triangle>,
+    surfaces: Vec<Surface>,
+
+    // The last presented frame, as linear radiance and tone mapped, unless it couldn't be traced
+    radiance: Rgb32FImage,
+    image: RgbaImage,
+    traced: Result<(), Error>,
+}
+
+// A triangle in world space
+#[derive(Debug, Copy, Clone)]
+struct Triangle {
+    positions: [Vector3<f32>; 3],
+    normals: [Vector3<f32>; 3],
+    colors: [Vector3<f32>; 3],
+    // Index into the frame's surfaces
+    surface: u32,
+}
+
+impl Triangle {
+    fn centroid(&self) -> Vector3<f32> {
+        (self.positions[0] + self.positions[1] + self.positions[2]) / 3.0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Surface {
+    Lit(Material),
+    // Light meshes, which only give off light
+    Emissive(Vector3<f32>),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Ray {
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+}
+
+impl Ray {
+    fn at(&self, t: f32) -> Vector3<f32> {
+        self.origin + self.direction * t
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Hit {
+    t: f32,
+    triangle: usize,
+    // Barycentric weights of the second and third corners
+    u: f32,
+    v: f32,
+}
+
+impl PathTracer {
+    pub fn new(assets: SceneAssets, settings: RenderSettings, trace: TraceSettings, size: (u32, u32)) -> PathTracer {
+        let size = (size.0.max(1), size.1.max(1));
+
+        PathTracer {
+            settings,
+            trace,
+            meshes: assets.meshes,
+            materials: assets.materials,
+            environment: EnvironmentLevels::new(&assets.environment),
+            size,
+            projection: perspective(size.0 as f32 / size.1 as f32),
+
+            inverse_view_projection: Some(Matrix4::identity()),
+            lights: vec![],
+            triangles: vec![],
+            surfaces: vec![],
+
+            radiance: Rgb32FImage::new(size.0, size.1),
+            image: RgbaImage::new(size.0, size.1),
+            traced: Ok(()),
+        }
+    }
+
+    /// The last presented frame, in sRGB
+    pub fn image(&self) -> Result<&RgbaImage, Error> {
+        self.traced.clone().map(|_| &self.image)
+    }
+
+    /// The last presented frame as linear radiance, before exposure and tone mapping
+    pub fn radiance(&self) -> Result<&Rgb32FImage, Error> {
+        self.traced.clone().map(|_| &self.radiance)
+    }
+
+    fn add(&mut self, mesh: MeshHandle, surface: Surface, transform: &Transform) {
+        let mesh = self.meshes.get(mesh);
+        let model = transform.model_matrix();
+        let normal_matrix = transform.normal_matrix();
+        let index = self.surfaces.len() as u32;
+
+        // Like triangle.vert
+        let verticies: Vec<(Vector3<f32>, Vector3<f32>, Vector3<f32>)> = mesh
+            .verticies
+            .iter()
+            .map(|vertex| {
+                let (pos, norm) = (vertex.pos, vertex.norm);
+                let color = Color::from(vertex.clr);
+                (
+                    model.transform_point(&Point3::new(pos.d0, pos.d1, pos.d2)).coords,
+                    normal_matrix * Vector3::new(norm.d0, norm.d1, norm.d2),
+                    Vector3::new(color.r, color.g, color.b),
+                )
+            })
+            .collect();
+
+        self.triangles.extend(mesh.indices.chunks_exact(3).map(|corners| {
+            let corner = |i: usize| verticies[corners[i] as usize];
+            let (a, b, c) = (corner(0), corner(1), corner(2));
+
+            Triangle {
+                positions: [a.0, b.0, c.0],
+                normals: [a.1, b.1, c.1],
+                colors: [a.2, b.2, c.2],
+                surface: index,
+            }
+        }));
+        self.surfaces.push(surface);
+    }
+
+    // Traces all tiles on the configured number of threads, and gathers their pixels
+    fn render(&mut self, inverse_view_projection: Matrix4<f32>) {
+        let (width, height) = self.size;
+        let (columns, rows) = (width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE));
+        let tile_count = (columns * rows) as usize;
+
+        let scene = TraceScene {
+            bvh: Bvh::new(&self.triangles),
+            triangles: &self.triangles,
+            surfaces: &self.surfaces,
+            lights: &self.lights,
+            environment: &self.environment,
+        };
+        let camera = CameraRays {
+            inverse_view_projection,
+            size: self.size,
+        };
+        let trace = self.trace;
+        let next_tile = AtomicUsize::new(0);
+
+        let tiles: Vec<(usize, Vec<Vector3<f32>>)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..trace.threads.max(1))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut traced = vec![];
+                        loop {
+                            let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+                            if tile >= tile_count {
+                                return traced;
+                            }
+
+                            let x = (tile as u32 % columns) * TILE_SIZE;
+                            let y = (tile as u32 / columns) * TILE_SIZE;
+                            traced.push((tile, scene.trace_tile(&camera, &trace, tile as u64, (x, y))));
+                        }
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("A path tracing thread panicked"))
+                .collect()
+        });
+
+        for (tile, pixels) in tiles {
+            let (x, y) = ((tile as u32 % columns) * TILE_SIZE, (tile as u32 / columns) * TILE_SIZE);
+            let tile_width = TILE_SIZE.min(width - x);
+
+            for (index, color) in pixels.iter().enumerate() {
+                let (px, py) = (x + index as u32 % tile_width, y + index as u32 / tile_width);
+                self.radiance.put_pixel(px, py, Rgb([color.x, color.y, color.z]));
+                self.image.put_pixel(px, py, tone_map(&self.settings.hdr, color));
+            }
+        }
+    }
+}
+
+impl Renderer for PathTracer {
+    fn clear(&mut self) {
+        self.triangles.clear();
+        self.surfaces.clear();
+    }
+
+    fn set_camera(&mut self, camera: &Camera) {
+        let (view_rotation, view_translation, _) = camera.view();
+        // Cameras which are somewhere out of range invert to infinities, which are no better
+        self.inverse_view_projection = (self.projection * view_rotation * view_translation)
+            .try_inverse()
+            .filter(|inverse| inverse.iter().all(|element| element.is_finite()));
+    }
+
+    fn set_lights(&mut self, lights: &[SceneLight]) {
+        self.lights = lights.to_vec();
+    }
+
+    fn draw_mesh(&mut self, mesh: MeshHandle, material: MaterialHandle, transform: &Transform, color: Color) {
+        let material = self.materials.get(material).tinted(color);
+        self.add(mesh, Surface::Lit(material), transform);
+    }
+
+    fn draw_light_mesh(&mut self, mesh: MeshHandle, color: Color, transform: &Transform) {
+        let emission = Vector3::new(color.r, color.g, color.b) * self.settings.light_emissive_strength;
+        self.add(mesh, Surface::Emissive(emission), transform);
+    }
+
+    fn present(&mut self) {
+        self.traced = match self.inverse_view_projection {
+            Some(inverse_view_projection) => {
+                self.render(inverse_view_projection);
+                Ok(())
+            }
+            None => Err(Error::SingularCamera),
+        };
+    }
+}
+
+// Turns pixels into rays through them
+struct CameraRays {
+    inverse_view_projection: Matrix4<f32>,
+    size: (u32, u32),
+}
+
+impl CameraRays {
+    // `x` and `y` are in pixels, with Y pointing down
+    fn ray(&self, x: f32, y: f32) -> Ray {
+        let ndc = (2.0 * x / self.size.0 as f32 - 1.0, 1.0 - 2.0 * y / self.size.1 as f32);
+        let unproject = |z: f32| {
+            let point = self.inverse_view_projection * Vector4::new(ndc.0, ndc.1, z, 1.0);
+            point.xyz() / point.w
+        };
+
+        // The far plane is so far away that it's imprecise, so aim at a point in between instead
+        let near = unproject(-1.0);
+        Ray {
+            origin: near,
+            direction: (unproject(0.0) - near).normalize(),
+        }
+    }
+}
+
+// Everything threads share while tracing
+struct TraceScene<'a> {
+    bvh: Bvh,
+    triangles: &'a [Triangle],
+    surfaces: &'a [Surface],
+    lights: &'a [SceneLight],
+    environment: &'a EnvironmentLevels,
+}
+
+impl<'a> TraceScene<'a> {
+    // The average radiance of every pixel in the tile at `origin`, row by row
+    fn trace_tile(&self, camera: &CameraRays, trace: &TraceSettings, tile: u64, origin: (u32, u32)) -> Vec<Vector3<f32>> {
+        // Seeded by tile rather than by thread, so the threads don't change the noise
+        let mut rng = StdRng::seed_from_u64(trace.seed ^ tile.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let (width, height) = camera.size;
+        let (x_end, y_end) = ((origin.0 + TILE_SIZE).min(width), (origin.1 + TILE_SIZE).min(height));
+        let samples = trace.samples.max(1);
+
+        (origin.1..y_end)
+            .flat_map(|y| (origin.0..x_end).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let total: Vector3<f32> = (0..samples)
+                    .map(|_| {
+                        let ray = camera.ray(x as f32 + rng.gen::<f32>(), y as f32 + rng.gen::<f32>());
+                        self.radiance(ray, trace.bounces, &mut rng)
+                    })
+                    .sum();
+                total / samples as f32
+            })
+            .collect()
+    }
+
+    fn radiance(&self, mut ray: Ray, bounces: u32, rng: &mut StdRng) -> Vector3<f32> {
+        let mut radiance = Vector3::zeros();
+        let mut throughput = Vector3::repeat(1.0);
+
+        for bounce in 0..=bounces {
+            let hit = match self.bvh.intersect(self.triangles, &ray, f32::INFINITY, |_| true) {
+                Some(hit) => hit,
+                // Like in the game, the environment lights the scene but isn't drawn behind it
+                None if bounce == 0 => break,
+                None => {
+                    radiance += throughput.component_mul(&self.environment.sample(&ray.direction, 0.0));
+                    break;
+                }
+            };
+
+            let triangle = &self.triangles[hit.triangle];
+            let material = match &self.surfaces[triangle.surface as usize] {
+                Surface::Lit(material) => material,
+                Surface::Emissive(emission) => {
+                    radiance += throughput.component_mul(emission);
+                    break;
+                }
+            };
+
+            let weights = [1.0 - hit.u - hit.v, hit.u, hit.v];
+            let interpolate = |values: &[Vector3<f32>; 3]| values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2];
+
+            // Both normals face the ray, whichever way the triangle winds
+            let position = ray.at(hit.t);
+            let edges = (
+                triangle.positions[1] - triangle.positions[0],
+                triangle.positions[2] - triangle.positions[0],
+            );
+            let mut geometric_normal = edges.0.cross(&edges.1).normalize();
+            if geometric_normal.dot(&ray.direction) > 0.0 {
+                geometric_normal = -geometric_normal;
+            }
+            let mut normal = interpolate(&triangle.normals).try_normalize(1e-6).unwrap_or(geometric_normal);
+            if normal.dot(&geometric_normal) < 0.0 {
+                normal = -normal;
+            }
+
+            let base_color = &material.base_color;
+            let bsdf = Bsdf {
+                albedo: Vector3::new(base_color.r, base_color.g, base_color.b).component_mul(&interpolate(&triangle.colors)),
+                metallic: material.metallic.clamp(0.0, 1.0),
+                roughness: material.roughness.clamp(0.04, 1.0),
+                normal,
+                view: -ray.direction,
+            };
+
+            let emissive = &material.emissive;
+            radiance += throughput.component_mul(&Vector3::new(emissive.r, emissive.g, emissive.b));
+
+            let origin = position + geometric_normal * RAY_OFFSET;
+            radiance += throughput.component_mul(&self.direct_light(&bsdf, &origin));
+
+            if bounce == bounces {
+                break;
+            }
+
+            let (direction, weight) = match bsdf.sample(rng) {
+                Some(sample) => sample,
+                None => break,
+            };
+            throughput = throughput.component_mul(&weight);
+
+            if bounce >= ROULETTE_BOUNCES {
+                let survival = throughput.max().clamp(0.05, 0.95);
+                if rng.gen::<f32>() > survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+
+            ray = Ray { origin, direction };
+        }
+
+        radiance
+    }
+
+    // Light reaching `origin` straight from the lights, which are points so bounces never hit them
+    fn direct_light(&self, bsdf: &Bsdf, origin: &Vector3<f32>) -> Vector3<f32> {
+        self.lights
+            .iter()
+            .filter_map(|light| {
+                let to_light = Vector3::from(light.location) - origin;
+                let distance = to_light.norm();
+                let radius = light.spotlight.spot_radius;
+                if distance >= radius || distance == 0.0 {
+                    return None;
+                }
+
+                let direction = to_light / distance;
+                if bsdf.normal.dot(&direction) <= 0.0 {
+                    return None;
+                }
+
+                // Light meshes surround their lights, so they can't cast shadows
+                let ray = Ray {
+                    origin: *origin,
+                    direction,
+                };
+                let casts_shadows = |triangle: &Triangle| matches!(self.surfaces[triangle.surface as usize], Surface::Lit(_));
+                if self.bvh.intersect(self.triangles, &ray, distance, casts_shadows).is_some() {
+                    return None;
+                }
+
+                // The same falloff as PBR shading
+                let window = (1.0 - (distance / radius).powi(4)).clamp(0.0, 1.0);
+                let attenuation = window * window / (distance * distance).max(0.01);
+                let color = &light.spotlight.color;
+
+                Some(bsdf.evaluate(&direction).component_mul(&Vector3::new(color.r, color.g, color.b)) * attenuation)
+            })
+            .sum()
+    }
+}
+
+// The PBR shading model at a point, a Lambertian diffuse lobe and a GGX specular lobe
+struct Bsdf {
+    albedo: Vector3<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal: Vector3<f32>,
+    // Towards where the light leaves
+    view: Vector3<f32>,
+}
+
+impl Bsdf {
+    fn f0(&self) -> Vector3<f32> {
+        Vector3::repeat(0.04).lerp(&self.albedo, self.metallic)
+    }
+
+    // Reflected radiance towards the view per radiance arriving from `light`, times the cosine
+    fn evaluate(&self, light: &Vector3<f32>) -> Vector3<f32> {
+        let n_dot_l = self.normal.dot(light);
+        let n_dot_v = self.normal.dot(&self.view).max(1e-4);
+        if n_dot_l <= 0.0 {
+            return Vector3::zeros();
+        }
+
+        let halfway = (self.view + light).normalize();
+        let fresnel = fresnel_schlick(halfway.dot(&self.view).max(0.0), &self.f0());
+        let distribution = distribution_ggx(self.normal.dot(&halfway).max(0.0), self.roughness);
+        let geometry = geometry_smith(n_dot_v, n_dot_l, self.roughness);
+        let specular = fresnel * (distribution * geometry / (4.0 * n_dot_v * n_dot_l.max(1e-4)));
+        let diffuse = (Vector3::repeat(1.0) - fresnel).component_mul(&self.albedo) * ((1.0 - self.metallic) / PI);
+
+        (diffuse + specular) * n_dot_l
+    }
+
+    // Picks the specular lobe about as often as it reflects compared to the diffuse lobe
+    fn specular_probability(&self) -> f32 {
+        let luminance = |color: Vector3<f32>| color.dot(&Vector3::new(0.2126, 0.7152, 0.0722));
+        let n_dot_v = self.normal.dot(&self.view).max(1e-4);
+        let specular = luminance(fresnel_schlick(n_dot_v, &self.f0()));
+        let diffuse = luminance(self.albedo) * (1.0 - self.metallic) * (1.0 - specular);
+
+        (specular / (specular + diffuse).max(1e-6)).clamp(0.1, 0.9)
+    }
+
+    // Probability density of sampling `light`, over both lobes
+    fn pdf(&self, light: &Vector3<f32>, specular_probability: f32) -> f32 {
+        let n_dot_l = self.normal.dot(light).max(0.0);
+        let halfway = (self.view + light).normalize();
+        let n_dot_h = self.normal.dot(&halfway).max(0.0);
+        let v_dot_h = self.view.dot(&halfway).max(1e-4);
+
+        let diffuse = n_dot_l / PI;
+        let specular = distribution_ggx(n_dot_h, self.roughness) * n_dot_h / (4.0 * v_dot_h);
+        specular_probability * specular + (1.0 - specular_probability) * diffuse
+    }
+
+    // A direction for the path to continue in, and what its radiance gets multiplied with
+    fn sample(&self, rng: &mut StdRng) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let (tangent, bitangent) = orthonormal_basis(&self.normal);
+        let to_world = |local: Vector3<f32>| tangent * local.x + bitangent * local.y + self.normal * local.z;
+        let (u1, u2) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let phi = 2.0 * PI * u1;
+        let specular_probability = self.specular_probability();
+
+        let direction = if rng.gen::<f32>() < specular_probability {
+            // A GGX distributed microfacet normal, which the view reflects off
+            let alpha = self.roughness * self.roughness;
+            let cos_theta = ((1.0 - u2) / (1.0 + (alpha * alpha - 1.0) * u2)).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let halfway = to_world(Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+            halfway * (2.0 * self.view.dot(&halfway)) - self.view
+        } else {
+            // Cosine weighted
+            let radius = u2.sqrt();
+            to_world(Vector3::new(radius * phi.cos(), radius * phi.sin(), (1.0 - u2).max(0.0).sqrt()))
+        };
+
+        let pdf = self.pdf(&direction, specular_probability);
+        if self.normal.dot(&direction) <= 0.0 || pdf <= 0.0 {
+            return None;
+        }
+
+        Some((direction, self.evaluate(&direction) / pdf))
+    }
+}
+
+fn orthonormal_basis(normal: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let helper = if normal.x.abs() > 0.9 { Vector3::y() } else { Vector3::x() };
+    let tangent = helper.cross(normal).normalize();
+    (tangent, normal.cross(&tangent))
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Aabb {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+impl Aabb {
+    fn empty() -> Aabb {
+        Aabb {
+            min: Vector3::repeat(f32::INFINITY),
+            max: Vector3::repeat(f32::NEG_INFINITY),
+        }
+    }
+
+    fn grow(&mut self, point: &Vector3<f32>) {
+        self.min = self.min.inf(point);
+        self.max = self.max.sup(point);
+    }
+
+    fn largest_axis(&self) -> usize {
+        (self.max - self.min).imax()
+    }
+
+    // Where the ray enters the box, if it does before `max_t`
+    fn entry(&self, ray: &Ray, inverse_direction: &Vector3<f32>, max_t: f32) -> Option<f32> {
+        let near = (self.min - ray.origin).component_mul(inverse_direction);
+        let far = (self.max - ray.origin).component_mul(inverse_direction);
+        let entry = near.inf(&far).max().max(0.0);
+        let exit = near.sup(&far).min().min(max_t);
+
+        if entry <= exit {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+}
+
+// Leaves hold at most this many triangles
+const BVH_LEAF_SIZE: usize = 4;
+
+// Inner nodes have their first child right after them, and the second at `offset`. Leaves have
+// `count` triangles from `offset` on in the BVH's triangle order.
+#[derive(Debug, Copy, Clone)]
+struct BvhNode {
+    bounds: Aabb,
+    offset: u32,
+    count: u32,
+    axis: u8,
+}
+
+/// A bounding volume hierarchy, split at the median of the triangles' centroids along the widest
+/// axis
+struct Bvh {
+    nodes: Vec<BvhNode>,
+    // Triangle indices, ordered so that every leaf's triangles are next to each other
+    order: Vec<u32>,
+}
+
+impl Bvh {
+    fn new(triangles: &[Triangle]) -> Bvh {
+        let centroids: Vec<Vector3<f32>> = triangles.iter().map(Triangle::centroid).collect();
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * triangles.len() / BVH_LEAF_SIZE + 1),
+            order: (0..triangles.len() as u32).collect(),
+        };
+        if !triangles.is_empty() {
+            bvh.build(triangles, &centroids, 0, triangles.len());
+        }
+
+        bvh
+    }
+
+    // Adds the node for the triangles from `start` to `end` in the order, and its children
+    fn build(&mut self, triangles: &[Triangle], centroids: &[Vector3<f32>], start: usize, end: usize) {
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &triangle in &self.order[start..end] {
+            triangles[triangle as usize]
+                .positions
+                .iter()
+                .for_each(|position| bounds.grow(position));
+            centroid_bounds.grow(&centroids[triangle as usize]);
+        }
+
+        let index = self.nodes.len();
+        let axis = centroid_bounds.largest_axis();
+        self.nodes.push(BvhNode {
+            bounds,
+            offset: start as u32,
+            count: (end - start) as u32,
+            axis: axis as u8,
+        });
+
+        // Triangles on top of each other can't be split up
+        if end - start <= BVH_LEAF_SIZE || centroid_bounds.max[axis] <= centroid_bounds.min[axis] {
+            return;
+        }
+
+        let middle = (start + end) / 2;
+        self.order[start..end].select_nth_unstable_by(middle - start, |a, b| {
+            centroids[*a as usize][axis].total_cmp(&centroids[*b as usize][axis])
+        });
+
+        self.build(triangles, centroids, start, middle);
+        let second = self.nodes.len();
+        self.build(triangles, centroids, middle, end);
+
+        self.nodes[index].offset = second as u32;
+        self.nodes[index].count = 0;
+    }
+
+    // The closest hit before `max_t` on a triangle which `accept`s it
+    fn intersect(&self, triangles: &[Triangle], ray: &Ray, max_t: f32, accept: impl Fn(&Triangle) -> bool) -> Option<Hit> {
+        let inverse_direction = ray.direction.map(|component| 1.0 / component);
+        let mut closest: Option<Hit> = None;
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let limit = closest.map_or(max_t, |hit| hit.t);
+            if node.bounds.entry(ray, &inverse_direction, limit).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                let (start, end) = (node.offset as usize, (node.offset + node.count) as usize);
+                for &triangle in &self.order[start..end] {
+                    let candidate = &triangles[triangle as usize];
+                    if let Some((t, u, v)) = intersect_triangle(candidate, ray) {
+                        if t < closest.map_or(max_t, |hit| hit.t) && accept(candidate) {
+                            closest = Some(Hit {
+                                t,
+                                triangle: triangle as usize,
+                                u,
+                                v,
+                            });
+                        }
+                    }
+                }
+            } else {
+                // Visit the nearer child first, so that the farther one is more likely to be culled
+                let (first, second) = (index + 1, node.offset as usize);
+                if ray.direction[node.axis as usize] < 0.0 {
+                    stack.push(first);
+                    stack.push(second);
+                } else {
+                    stack.push(second);
+                    stack.push(first);
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+// Möller-Trumbore, from both sides
+fn intersect_triangle(triangle: &Triangle, ray: &Ray) -> Option<(f32, f32, f32)> {
+    let [a, b, c] = &triangle.positions;
+    let (edge1, edge2) = (b - a, c - a);
+
+    let p = ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+
+    let s = ray.origin - a;
+    let u = s.dot(&p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(&q) * inverse_determinant;
+    if t > 0.0 {
+        Some((t, u, v))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{intersect_triangle, Bvh, Error, PathTracer, Ray, TraceSettings, Triangle};
+    use crate::models::cube::Cube;
+    use crate::models::material::{Material, MaterialRegistry};
+    use crate::models::mesh_registry::MeshRegistry;
+    use crate::models::world_model::Spatial;
+    use crate::primitives::camera::Camera;
+    use crate::primitives::environment::Environment;
+    use crate::primitives::light::consts;
+    use crate::primitives::object_draw::ShadingModel;
+    use crate::primitives::spatial::{Location, Orientation};
+    use crate::primitives::spotlight::Spotlight;
+    use crate::renderer::{test_settings, Renderer, SceneAssets, SceneLight, Transform};
+
+    fn triangle(positions: [Vector3<f32>; 3]) -> Triangle {
+        Triangle {
+            positions,
+            normals: [Vector3::z(); 3],
+            colors: [Vector3::repeat(1.0); 3],
+            surface: 0,
+        }
+    }
+
+    fn ray(origin: Vector3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    #[test]
+    fn rays_hit_triangles_in_front_of_them() {
+        let triangle = triangle([Vector3::zeros(), Vector3::x(), Vector3::y()]);
+
+        let (t, u, v) = intersect_triangle(&triangle, &ray(Vector3::new(0.25, 0.5, 2.0), -Vector3::z())).unwrap();
+        assert!((t - 2.0).abs() < 1e-6);
+        assert!((u - 0.25).abs() < 1e-6 && (v - 0.5).abs() < 1e-6);
+
+        // From behind, as both sides are hit
+        let (t, _, _) = intersect_triangle(&triangle, &ray(Vector3::new(0.25, 0.25, -1.0), Vector3::z())).unwrap();
+        assert!((t - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rays_miss_triangles_beside_behind_or_parallel_to_them() {
+        let triangle = triangle([Vector3::zeros(), Vector3::x(), Vector3::y()]);
+
+        assert!(intersect_triangle(&triangle, &ray(Vector3::new(0.75, 0.75, 2.0), -Vector3::z())).is_none());
+        assert!(intersect_triangle(&triangle, &ray(Vector3::new(0.25, 0.25, 2.0), Vector3::z())).is_none());
+        assert!(intersect_triangle(&triangle, &ray(Vector3::new(-1.0, 0.25, 0.0), Vector3::x())).is_none());
+    }
+
+    #[test]
+    fn bvh_finds_the_same_hits_as_testing_every_triangle() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut point = |scale: f32| Vector3::new(rng.gen(), rng.gen(), rng.gen()) * scale;
+        let triangles: Vec<Triangle> = (0..500)
+            .map(|_| {
+                let corner = point(20.0);
+                triangle([corner, corner + point(2.0), corner + point(2.0)])
+            })
+            .collect();
+        let bvh = Bvh::new(&triangles);
+
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let ray = ray(point(20.0), point(2.0) - Vector3::repeat(1.0));
+            let closest = triangles
+                .iter()
+                .enumerate()
+                .filter_map(|(index, triangle)| intersect_triangle(triangle, &ray).map(|(t, _, _)| (index, t)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            let hit = bvh.intersect(&triangles, &ray, f32::INFINITY, |_| true);
+            assert_eq!(hit.map(|hit| (hit.triangle, hit.t)), closest);
+            hits += closest.is_some() as usize;
+        }
+
+        // Otherwise the rays would hardly test anything
+        assert!(hits > 100, "Only {} rays hit", hits);
+    }
+
+    // A wall of cubes with a light in front of it, through a camera looking down -Z
+    fn trace(threads: usize) -> PathTracer {
+        let mut meshes = MeshRegistry::new();
+        let cube = meshes.load("cube", || Cube::new(consts::WHITE).verticies);
+        let mut materials = MaterialRegistry::new();
+        let plastic = materials.add("plastic", Material::default());
+        let assets = SceneAssets {
+            meshes,
+            materials,
+            environment: Environment::sky(1.0),
+        };
+        let trace = TraceSettings {
+            samples: 2,
+            bounces: 2,
+            threads,
+            seed: 1,
+        };
+
+        let mut tracer = PathTracer::new(assets, test_settings(ShadingModel::Pbr), trace, (40, 24));
+        tracer.set_camera(&Camera {
+            location: Location::new(5.0, 5.0, 12.0),
+            orientation: Orientation::default(),
+        });
+        tracer.set_lights(&[SceneLight {
+            spotlight: Spotlight {
+                color: consts::WHITE,
+                spot_radius: 20.0,
+                casts_shadows: false,
+            },
+            location: Location::new(5.0, 5.0, 4.0),
+        }]);
+        for x in 0..5 {
+            for y in 0..5 {
+                let spatial = Spatial::new(Location::new(x as f32 * 2.0, y as f32 * 2.0, 0.0), Orientation::default(), 1.5);
+                tracer.draw_mesh(cube, plastic, &Transform::of(&spatial), consts::WHITE);
+            }
+        }
+        tracer.present();
+
+        tracer
+    }
+
+    #[test]
+    fn threads_trace_identical_images() {
+        let single = trace(1);
+        let single = single.radiance().unwrap();
+        assert!(single.pixels().any(|pixel| pixel.0.iter().any(|channel| *channel > 0.0)));
+
+        for threads in [2, 5] {
+            assert_eq!(trace(threads).radiance().unwrap(), single, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn cameras_which_cant_be_inverted_are_errors() {
+        let mut tracer = trace(1);
+        tracer.set_camera(&Camera {
+            location: Location::new(f32::NAN, 0.0, 0.0),
+            orientation: Orientation::default(),
+        });
+        tracer.present();
+
+        assert!(matches!(tracer.image(), Err(Error::SingularCamera)));
+        assert!(matches!(tracer.radiance(), Err(Error::SingularCamera)));
+    }
+}
@@ -0,0 +1,679 @@
+use std::f32::consts::PI;
+
+use image::{Rgba, RgbaImage};
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::models::material::{Material, MaterialHandle, MaterialRegistry};
+use crate::models::mesh_registry::{MeshHandle, MeshRegistry};
+use crate::models::world_model::Model;
+use crate::primitives::camera::Camera;
+use crate::primitives::environment::Environment;
+use crate::primitives::hdr::{HdrSettings, ToneMapping};
+use crate::primitives::light::Color;
+use crate::primitives::object_draw::ShadingModel;
+use crate::primitives::projection::perspective;
+use crate::renderer::{RenderSettings, Renderer, SceneAssets, SceneLight, Transform};
+
+/// Renders on the CPU, drawing every mesh as it comes in. Lights the way triangle.frag does, but
+/// has no shadows, ambient occlusion, diffuse maps, MSAA, bloom or post-processing. The same frame
+/// always comes out exactly the same, no matter the machine.
+pub(crate) struct SoftwareRenderer {
+    settings: RenderSettings,
+    meshes: MeshRegistry,
+    materials: MaterialRegistry,
+    environment: EnvironmentLevels,
+    size: (u32, u32),
+    projection: Matrix4<f32>,
+
+    view_projection: Matrix4<f32>,
+    view_location: Vector3<f32>,
+    lights: Vec<SceneLight>,
+    // The frame being drawn in linear colors, and its depth from 0 (near) to 1 (far), top row first
+    color: Vec<Vector4<f32>>,
+    depth: Vec<f32>,
+    // The last presented frame
+    image: RgbaImage,
+}
+
+// A vertex as the vertex shader outputs it, everything but the position is interpolated across
+// triangles
+#[derive(Debug, Copy, Clone)]
+struct ShadedVertex {
+    position: Vector4<f32>,
+    world: Vector3<f32>,
+    normal: Vector3<f32>,
+    color: Vector4<f32>,
+}
+
+impl ShadedVertex {
+    fn lerp(&self, other: &ShadedVertex, t: f32) -> ShadedVertex {
+        ShadedVertex {
+            position: self.position.lerp(&other.position, t),
+            world: self.world.lerp(&other.world, t),
+            normal: self.normal.lerp(&other.normal, t),
+            color: self.color.lerp(&other.color, t),
+        }
+    }
+}
+
+// The surface of a mesh
+#[derive(Copy, Clone)]
+enum Surface<'a> {
+    Lit(&'a Material),
+    // Light gizmos, in a color which is already as bright as it should be
+    Solid(Vector4<f32>),
+}
+
+impl SoftwareRenderer {
+    pub fn new(assets: SceneAssets, settings: RenderSettings, size: (u32, u32)) -> SoftwareRenderer {
+        let mut renderer = SoftwareRenderer {
+            settings,
+            meshes: assets.meshes,
+            materials: assets.materials,
+            environment: EnvironmentLevels::new(&assets.environment),
+            size: (0, 0),
+            projection: Matrix4::identity(),
+
+            view_projection: Matrix4::identity(),
+            view_location: Vector3::zeros(),
+            lights: vec![],
+            color: vec![],
+            depth: vec![],
+            image: RgbaImage::new(1, 1),
+        };
+        renderer.resize(size);
+
+        renderer
+    }
+
+    pub fn resize(&mut self, size: (u32, u32)) {
+        // Images can't be empty
+        self.size = (size.0.max(1), size.1.max(1));
+        self.projection = perspective(self.size.0 as f32 / self.size.1 as f32);
+        self.image = RgbaImage::new(self.size.0, self.size.1);
+        self.clear();
+    }
+
+    /// The last presented frame, in sRGB
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    fn draw(&mut self, mesh: MeshHandle, surface: Surface, transform: &Transform) {
+        let mesh = self.meshes.get(mesh);
+        let model = transform.model_matrix();
+        let normal_matrix = transform.normal_matrix();
+
+        // Like triangle.vert
+        let verticies: Vec<ShadedVertex> = mesh
+            .verticies
+            .iter()
+            .map(|vertex| {
+                let (pos, norm, clr) = (vertex.pos, vertex.norm, vertex.clr);
+                let world = model.transform_point(&Vector3::new(pos.d0, pos.d1, pos.d2).into()).coords;
+                let color = Color::from(clr);
+
+                ShadedVertex {
+                    position: self.view_projection * world.push(1.0),
+                    world,
+                    normal: normal_matrix * Vector3::new(norm.d0, norm.d1, norm.d2),
+                    color: Vector4::new(color.r, color.g, color.b, color.a),
+                }
+            })
+            .collect();
+
+        // Whatever is behind the near plane would divide by a negative w
+        let triangles: Vec<[ShadedVertex; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                let polygon = clip_near(&[
+                    verticies[triangle[0] as usize],
+                    verticies[triangle[1] as usize],
+                    verticies[triangle[2] as usize],
+                ]);
+                (1..polygon.len().saturating_sub(1)).map(move |i| [polygon[0], polygon[i], polygon[i + 1]])
+            })
+            .collect();
+
+        for triangle in &triangles {
+            self.rasterize(triangle, surface);
+        }
+    }
+
+    // Fills the pixels whose centers lie within the triangle, interpolating its verticies
+    // perspective-correctly, and blends them in like the SRC_ALPHA, ONE_MINUS_SRC_ALPHA blending of
+    // the GPU does
+    fn rasterize(&mut self, triangle: &[ShadedVertex; 3], surface: Surface) {
+        let (width, height) = self.size;
+
+        // Window coordinates with Y pointing down, depth and 1 / w
+        let window: Vec<(f32, f32, f32, f32)> = triangle
+            .iter()
+            .map(|vertex| {
+                let inverse_w = 1.0 / vertex.position.w;
+                let ndc = vertex.position.xyz() * inverse_w;
+                (
+                    (ndc.x + 1.0) * 0.5 * width as f32,
+                    (1.0 - ndc.y) * 0.5 * height as f32,
+                    ndc.z * 0.5 + 0.5,
+                    inverse_w,
+                )
+            })
+            .collect();
+        let (a, b, c) = (window[0], window[1], window[2]);
+
+        let edge = |from: (f32, f32, f32, f32), to: (f32, f32, f32, f32), x: f32, y: f32| {
+            (to.0 - from.0) * (y - from.1) - (to.1 - from.1) * (x - from.0)
+        };
+        let area = edge(a, b, c.0, c.1);
+        if area == 0.0 || !area.is_finite() {
+            return;
+        }
+
+        // Pixel centers on an edge belong to the triangle it's a top or left edge of, so triangles
+        // sharing the edge don't both draw them. Those are the edges whose inward facing normal
+        // points right, or down if they're horizontal.
+        let top_left = |from: (f32, f32, f32, f32), to: (f32, f32, f32, f32)| {
+            let inward = (area.signum() * (from.1 - to.1), area.signum() * (to.0 - from.0));
+            inward.0 > 0.0 || (inward.0 == 0.0 && inward.1 > 0.0)
+        };
+        let owned = [top_left(b, c), top_left(c, a), top_left(a, b)];
+
+        let min_x = a.0.min(b.0).min(c.0).floor().max(0.0) as u32;
+        let min_y = a.1.min(b.1).min(c.1).floor().max(0.0) as u32;
+        let max_x = (a.0.max(b.0).max(c.0).ceil().min(width as f32) as u32).min(width);
+        let max_y = (a.1.max(b.1).max(c.1).ceil().min(height as f32) as u32).min(height);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+                // Barycentric coordinates, positive inside the triangle whichever way it winds
+                let weights = [edge(b, c, px, py) / area, edge(c, a, px, py) / area, edge(a, b, px, py) / area];
+                if weights
+                    .iter()
+                    .zip(&owned)
+                    .any(|(weight, owned)| *weight < 0.0 || (*weight == 0.0 && !owned))
+                {
+                    continue;
+                }
+
+                let index = (y * width + x) as usize;
+                let depth = weights[0] * a.2 + weights[1] * b.2 + weights[2] * c.2;
+                if !(0.0..=1.0).contains(&depth) || depth >= self.depth[index] {
+                    continue;
+                }
+
+                // Depth is linear in window space, everything else is linear in world space
+                let perspective = [weights[0] * a.3, weights[1] * b.3, weights[2] * c.3];
+                let total = perspective[0] + perspective[1] + perspective[2];
+                let interpolate = |attribute: fn(&ShadedVertex) -> Vector4<f32>| {
+                    (attribute(&triangle[0]) * perspective[0]
+                        + attribute(&triangle[1]) * perspective[1]
+                        + attribute(&triangle[2]) * perspective[2])
+                        / total
+                };
+
+                let color = match surface {
+                    Surface::Lit(material) => self.shade(
+                        material,
+                        &interpolate(|vertex| vertex.world.push(0.0)).xyz(),
+                        &interpolate(|vertex| vertex.normal.push(0.0)).xyz(),
+                        &interpolate(|vertex| vertex.color),
+                    ),
+                    Surface::Solid(color) => color,
+                };
+
+                let destination = self.color[index];
+                self.color[index] = color * color.w + destination * (1.0 - color.w);
+                self.depth[index] = depth;
+            }
+        }
+    }
+
+    // The main of triangle.frag, without shadows or ambient occlusion
+    fn shade(&self, material: &Material, world: &Vector3<f32>, normal: &Vector3<f32>, vertex_color: &Vector4<f32>) -> Vector4<f32> {
+        let base_color = &material.base_color;
+        let surface_color = Vector4::new(base_color.r, base_color.g, base_color.b, base_color.a).component_mul(vertex_color);
+
+        // Lights only ever reach as far as their radius
+        let lights = self
+            .lights
+            .iter()
+            .map(|light| (Vector3::from(light.location), &light.spotlight))
+            .filter(|(location, spotlight)| (location - world).norm() < spotlight.spot_radius);
+
+        let color = match self.settings.shading {
+            ShadingModel::Classic => self.shade_classic(material, world, normal, &surface_color, lights),
+            ShadingModel::Pbr => self.shade_pbr(material, world, normal, &surface_color, lights),
+        };
+
+        let emissive = &material.emissive;
+        color + Vector4::new(emissive.r, emissive.g, emissive.b, 0.0)
+    }
+
+    fn shade_classic<'a>(
+        &self,
+        material: &Material,
+        world: &Vector3<f32>,
+        normal: &Vector3<f32>,
+        surface_color: &Vector4<f32>,
+        lights: impl Iterator<Item = (Vector3<f32>, &'a crate::primitives::spotlight::Spotlight)>,
+    ) -> Vector4<f32> {
+        let mut final_color = Vector3::repeat(material.ambient);
+
+        for (location, light) in lights {
+            let light_color = Vector3::new(light.color.r, light.color.g, light.color.b);
+            let light_distance = (location - world).norm();
+            let light_attenuation = 1.0 - light_distance.min(light.spot_radius) / light.spot_radius;
+
+            let light_direction = location - world;
+            let diffuse = normal_dot_sat(normal, &light_direction);
+            final_color += light_color * light_attenuation * diffuse;
+
+            let view_direction = self.view_location - world;
+            let halfway = light_direction + view_direction;
+            let angle = normalize(normal).dot(&normalize(&halfway)).clamp(-1.0, 1.0).acos();
+            let exponent = angle / material.specular_roughness;
+            let term = (-(exponent * exponent)).exp();
+            final_color += light_color * material.specular_strength * term.powi(64) * light_attenuation;
+        }
+
+        Vector4::new(
+            surface_color.x * final_color.x,
+            surface_color.y * final_color.y,
+            surface_color.z * final_color.z,
+            surface_color.w,
+        )
+    }
+
+    fn shade_pbr<'a>(
+        &self,
+        material: &Material,
+        world: &Vector3<f32>,
+        normal: &Vector3<f32>,
+        surface_color: &Vector4<f32>,
+        lights: impl Iterator<Item = (Vector3<f32>, &'a crate::primitives::spotlight::Spotlight)>,
+    ) -> Vector4<f32> {
+        let albedo = surface_color.xyz();
+        let roughness = material.roughness.clamp(0.04, 1.0);
+        let metallic = material.metallic.clamp(0.0, 1.0);
+
+        let n = normalize(normal);
+        let v = normalize(&(self.view_location - world));
+        let n_dot_v = n.dot(&v).max(1e-4);
+
+        // Dielectrics reflect about 4% head-on, metals reflect their albedo
+        let f0 = Vector3::repeat(0.04).lerp(&albedo, metallic);
+
+        let mut radiance_out = Vector3::zeros();
+        for (location, light) in lights {
+            let to_light = location - world;
+            let light_distance = to_light.norm();
+            let l = to_light / light_distance;
+            let h = normalize(&(v + l));
+            let n_dot_l = n.dot(&l).max(0.0);
+
+            let window = (1.0 - (light_distance / light.spot_radius).powi(4)).clamp(0.0, 1.0);
+            let attenuation = window * window / (light_distance * light_distance).max(0.01);
+            let radiance = Vector3::new(light.color.r, light.color.g, light.color.b) * attenuation;
+
+            let fresnel = fresnel_schlick(h.dot(&v).max(0.0), &f0);
+            let distribution = distribution_ggx(n.dot(&h).max(0.0), roughness);
+            let geometry = geometry_smith(n_dot_v, n_dot_l, roughness);
+            let specular = fresnel * (distribution * geometry / (4.0 * n_dot_v * n_dot_l.max(1e-4)));
+
+            // Whatever isn't reflected is refracted and diffused, metals absorb it all
+            let diffuse = (Vector3::repeat(1.0) - fresnel).component_mul(&albedo) * ((1.0 - metallic) / PI);
+
+            radiance_out += (diffuse + specular).component_mul(&radiance) * n_dot_l;
+        }
+
+        // Irradiance from the heavily blurred environment, reflections blurred by roughness
+        let ambient_fresnel = environment_brdf(&f0, roughness, n_dot_v);
+        let ambient_diffuse = (Vector3::repeat(1.0) - ambient_fresnel)
+            .component_mul(&albedo)
+            .component_mul(&self.environment.sample(&n, 0.8))
+            * (1.0 - metallic);
+        let reflected = -v - n * 2.0 * n.dot(&-v);
+        let ambient_specular = ambient_fresnel.component_mul(&self.environment.sample(&reflected, roughness));
+        radiance_out += ambient_diffuse + ambient_specular;
+
+        radiance_out.push(surface_color.w)
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn clear(&mut self) {
+        let pixels = (self.size.0 * self.size.1) as usize;
+        self.color = vec![Vector4::zeros(); pixels];
+        self.depth = vec![1.0; pixels];
+    }
+
+    fn set_camera(&mut self, camera: &Camera) {
+        let (view_rotation, view_translation, view_location) = camera.view();
+        self.view_projection = self.projection * view_rotation * view_translation;
+        self.view_location = view_location;
+    }
+
+    fn set_lights(&mut self, lights: &[SceneLight]) {
+        self.lights = lights.to_vec();
+    }
+
+    fn draw_mesh(&mut self, mesh: MeshHandle, material: MaterialHandle, transform: &Transform, color: Color) {
+        // Materials are cheap to copy, and the registry can't stay borrowed while drawing
+        let material = self.materials.get(material).tinted(color);
+        self.draw(mesh, Surface::Lit(&material), transform);
+    }
+
+    fn draw_light_mesh(&mut self, mesh: MeshHandle, color: Color, transform: &Transform) {
+        let strength = self.settings.light_emissive_strength;
+        let color = Vector4::new(color.r * strength, color.g * strength, color.b * strength, color.a);
+        self.draw(mesh, Surface::Solid(color), transform);
+    }
+
+    fn present(&mut self) {
+        let width = self.size.0;
+
+        for (index, color) in self.color.iter().enumerate() {
+            self.image.put_pixel(
+                index as u32 % width,
+                index as u32 / width,
+                tone_map(&self.settings.hdr, &color.xyz()),
+            );
+        }
+    }
+}
+
+/// Exposes, tone maps and sRGB encodes a linear color like tonemap.frag
+pub(crate) fn tone_map(hdr: &HdrSettings, color: &Vector3<f32>) -> Rgba<u8> {
+    let color = (color * hdr.exposure).map(|channel| channel.max(0.0));
+    let color = match hdr.tone_mapping {
+        ToneMapping::Clamp => color,
+        ToneMapping::Reinhard => color.map(|channel| channel / (1.0 + channel)),
+        ToneMapping::Aces => color.map(aces),
+    };
+    let encode = |channel: f32| (linear_to_srgb(channel.clamp(0.0, 1.0)) * 255.0).round() as u8;
+
+    Rgba([encode(color.x), encode(color.y), encode(color.z), 255])
+}
+
+/// The environment's mip levels in linear colors, bottom row first like GL textures, sampled like
+/// the environment map with trilinear filtering
+pub(crate) struct EnvironmentLevels {
+    levels: Vec<(u32, u32, Vec<Vector3<f32>>)>,
+    intensity: f32,
+}
+
+impl EnvironmentLevels {
+    pub fn new(environment: &Environment) -> EnvironmentLevels {
+        let image = environment.image().flipv().to_rgba8();
+        let (mut width, mut height) = image.dimensions();
+
+        let mut texels: Vec<Vector3<f32>> = image
+            .pixels()
+            .map(|pixel| Vector3::new(pixel[0], pixel[1], pixel[2]).map(|channel| srgb_to_linear(channel as f32 / 255.0)))
+            .collect();
+
+        // Every level averages 2x2 texels of the one before, down to a single texel
+        let mut levels = vec![];
+        loop {
+            levels.push((width, height, texels.clone()));
+            if width == 1 && height == 1 {
+                break;
+            }
+
+            let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+            let texel = |x: u32, y: u32| texels[(y.min(height - 1) * width + x.min(width - 1)) as usize];
+            texels = (0..next_height)
+                .flat_map(|y| (0..next_width).map(move |x| (x, y)))
+                .map(|(x, y)| (texel(2 * x, 2 * y) + texel(2 * x + 1, 2 * y) + texel(2 * x, 2 * y + 1) + texel(2 * x + 1, 2 * y + 1)) / 4.0)
+                .collect();
+            width = next_width;
+            height = next_height;
+        }
+
+        EnvironmentLevels {
+            levels,
+            intensity: environment.intensity,
+        }
+    }
+
+    /// Like environment() in triangle.frag, `blur` goes from the sharpest level at 0 to the
+    /// blurriest at 1
+    pub fn sample(&self, direction: &Vector3<f32>, blur: f32) -> Vector3<f32> {
+        let d = normalize(direction);
+        let u = d.y.atan2(d.x) / (2.0 * PI) + 0.5;
+        let v = (-d.z).clamp(-1.0, 1.0).acos() / PI;
+
+        let lod = (blur * (self.levels.len() - 1) as f32).max(0.0);
+        let lower = lod.floor() as usize;
+        let upper = (lower + 1).min(self.levels.len() - 1);
+        let color = self.bilinear(lower, u, v).lerp(&self.bilinear(upper, u, v), lod.fract());
+
+        color * self.intensity
+    }
+
+    // Wraps around horizontally, and clamps to the poles vertically
+    fn bilinear(&self, level: usize, u: f32, v: f32) -> Vector3<f32> {
+        let (width, height, texels) = &self.levels[level];
+        let (x, y) = (u * *width as f32 - 0.5, v * *height as f32 - 0.5);
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+
+        let texel = |x: i64, y: i64| {
+            let x = x.rem_euclid(*width as i64);
+            let y = y.clamp(0, *height as i64 - 1);
+            texels[(y * *width as i64 + x) as usize]
+        };
+        let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+        let bottom = texel(x0, y0).lerp(&texel(x0 + 1, y0), fx);
+        let top = texel(x0, y0 + 1).lerp(&texel(x0 + 1, y0 + 1), fx);
+
+        bottom.lerp(&top, fy)
+    }
+}
+
+// Sutherland-Hodgman against the near plane, z >= -w in clip space
+fn clip_near(triangle: &[ShadedVertex; 3]) -> Vec<ShadedVertex> {
+    let distance = |vertex: &ShadedVertex| vertex.position.z + vertex.position.w;
+    let mut polygon = Vec::with_capacity(4);
+
+    for i in 0..3 {
+        let (current, next) = (&triangle[i], &triangle[(i + 1) % 3]);
+        let (current_distance, next_distance) = (distance(current), distance(next));
+
+        if current_distance >= 0.0 {
+            polygon.push(*current);
+        }
+        if (current_distance >= 0.0) != (next_distance >= 0.0) {
+            polygon.push(current.lerp(next, current_distance / (current_distance - next_distance)));
+        }
+    }
+
+    polygon
+}
+
+// GLSL's normalize, which leaves zero vectors as NaN rather than panicking
+fn normalize(vector: &Vector3<f32>) -> Vector3<f32> {
+    vector / vector.norm()
+}
+
+fn normal_dot_sat(v1: &Vector3<f32>, v2: &Vector3<f32>) -> f32 {
+    normalize(v1).dot(&normalize(v2)).max(0.0)
+}
+
+pub(crate) fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denominator * denominator)
+}
+
+pub(crate) fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let view = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let light = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    view * light
+}
+
+pub(crate) fn fresnel_schlick(cos_theta: f32, f0: &Vector3<f32>) -> Vector3<f32> {
+    f0 + (Vector3::repeat(1.0) - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+fn environment_brdf(f0: &Vector3<f32>, roughness: f32, n_dot_v: f32) -> Vector3<f32> {
+    let c0 = Vector4::new(-1.0, -0.0275, -0.572, 0.022);
+    let c1 = Vector4::new(1.0, 0.0425, 1.04, -0.04);
+    let r = c0 * roughness + c1;
+    let a004 = (r.x * r.x).min((-9.28 * n_dot_v).exp2()) * r.x + r.y;
+    let (scale, bias) = (-1.04 * a004 + r.z, 1.04 * a004 + r.w);
+    f0 * scale + Vector3::repeat(bias)
+}
+
+fn aces(color: f32) -> f32 {
+    ((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+fn linear_to_srgb(color: f32) -> f32 {
+    if color <= 0.0031308 {
+        color * 12.92
+    } else {
+        1.055 * color.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_to_linear(color: f32) -> f32 {
+    if color <= 0.04045 {
+        color / 12.92
+    } else {
+        ((color + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Vector3, Vector4};
+
+    use super::{ShadedVertex, SoftwareRenderer, Surface};
+    use crate::models::material::MaterialRegistry;
+    use crate::models::mesh_registry::MeshRegistry;
+    use crate::primitives::environment::Environment;
+    use crate::primitives::object_draw::ShadingModel;
+    use crate::renderer::{test_settings, Renderer, SceneAssets};
+
+    const SIZE: u32 = 8;
+
+    fn renderer() -> SoftwareRenderer {
+        let assets = SceneAssets {
+            meshes: MeshRegistry::new(),
+            materials: MaterialRegistry::new(),
+            environment: Environment::sky(1.0),
+        };
+        SoftwareRenderer::new(assets, test_settings(ShadingModel::Classic), (SIZE, SIZE))
+    }
+
+    // A triangle already in clip space, given in window coordinates with Y pointing down, and NDC
+    // depth
+    fn triangle(corners: [(f32, f32); 3], z: f32) -> [ShadedVertex; 3] {
+        let vertex = |(x, y): (f32, f32)| ShadedVertex {
+            position: Vector4::new(x / SIZE as f32 * 2.0 - 1.0, 1.0 - y / SIZE as f32 * 2.0, z, 1.0),
+            world: Vector3::zeros(),
+            normal: Vector3::z(),
+            color: Vector4::repeat(1.0),
+        };
+        [vertex(corners[0]), vertex(corners[1]), vertex(corners[2])]
+    }
+
+    // Its hypotenuse runs between pixel centers, covering the pixels with x + y <= 6 below and
+    // right of the first row and column
+    const CORNERS: [(f32, f32); 3] = [(1.0, 1.0), (6.5, 1.0), (1.0, 6.5)];
+
+    fn covered(x: u32, y: u32) -> bool {
+        x >= 1 && y >= 1 && x + y <= 6
+    }
+
+    #[test]
+    fn triangles_cover_the_pixel_centers_within_them() {
+        let mut renderer = renderer();
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        renderer.rasterize(&triangle(CORNERS, 0.0), Surface::Solid(red));
+
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let index = (y * SIZE + x) as usize;
+                if covered(x, y) {
+                    assert_eq!(renderer.color[index], red, "({}, {})", x, y);
+                    assert_eq!(renderer.depth[index], 0.5, "({}, {})", x, y);
+                } else {
+                    assert_eq!(renderer.color[index], Vector4::zeros(), "({}, {})", x, y);
+                    assert_eq!(renderer.depth[index], 1.0, "({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn winding_does_not_matter() {
+        let mut renderer = renderer();
+        let [a, b, c] = CORNERS;
+        renderer.rasterize(&triangle([a, c, b], 0.0), Surface::Solid(Vector4::repeat(1.0)));
+
+        let drawn = renderer.depth.iter().filter(|depth| **depth < 1.0).count();
+        assert_eq!(drawn, 15);
+    }
+
+    #[test]
+    fn nearer_triangles_hide_farther_ones() {
+        let mut renderer = renderer();
+        let (near, far) = (Vector4::new(0.0, 1.0, 0.0, 1.0), Vector4::new(0.0, 0.0, 1.0, 1.0));
+        let index = (2 * SIZE + 2) as usize;
+
+        renderer.rasterize(&triangle(CORNERS, -0.5), Surface::Solid(near));
+        renderer.rasterize(&triangle(CORNERS, 0.5), Surface::Solid(far));
+        assert_eq!(renderer.color[index], near);
+        assert_eq!(renderer.depth[index], 0.25);
+
+        renderer.clear();
+        renderer.rasterize(&triangle(CORNERS, 0.5), Surface::Solid(far));
+        renderer.rasterize(&triangle(CORNERS, -0.5), Surface::Solid(near));
+        assert_eq!(renderer.color[index], near);
+        assert_eq!(renderer.depth[index], 0.25);
+    }
+
+    #[test]
+    fn translucent_colors_are_blended() {
+        let mut renderer = renderer();
+        let index = (2 * SIZE + 2) as usize;
+
+        renderer.rasterize(&triangle(CORNERS, 0.5), Surface::Solid(Vector4::new(1.0, 0.0, 0.0, 1.0)));
+        renderer.rasterize(&triangle(CORNERS, 0.0), Surface::Solid(Vector4::new(0.0, 0.0, 1.0, 0.5)));
+
+        assert_eq!(renderer.color[index], Vector4::new(0.5, 0.0, 0.5, 0.75));
+    }
+
+    #[test]
+    fn shared_edges_are_drawn_once() {
+        // A square split along its diagonal, which runs through the pixel centers with x + y = 7.
+        // The far half goes first so the near one passes the depth test wherever both cover.
+        let (top_left, top_right, bottom_left, bottom_right) = ((0.0, 0.0), (8.0, 0.0), (0.0, 8.0), (8.0, 8.0));
+        let color = Vector4::new(1.0, 0.0, 0.0, 0.5);
+
+        for reversed in [false, true] {
+            let mut halves = [[top_left, top_right, bottom_left], [top_right, bottom_right, bottom_left]];
+            if reversed {
+                halves.iter_mut().for_each(|half| half.reverse());
+            }
+
+            let mut renderer = renderer();
+            renderer.rasterize(&triangle(halves[0], 0.5), Surface::Solid(color));
+            renderer.rasterize(&triangle(halves[1], 0.0), Surface::Solid(color));
+
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let index = (y * SIZE + x) as usize;
+                    assert_eq!(renderer.color[index], Vector4::new(0.5, 0.0, 0.0, 0.25), "({}, {})", x, y);
+                }
+            }
+        }
+    }
+}
@@ -45,7 +45,13 @@ impl Resources {
         })
     }
 
-    pub fn load_cstring(&self, resource_name: &str) -> Result<ffi::CString, Error> {
+    pub fn from_path(root_path: &Path) -> Resources {
+        Resources {
+            root_path: root_path.into(),
+        }
+    }
+
+    pub fn load_bytes(&self, resource_name: &str) -> Result<Vec<u8>, Error> {
         let full_path = resource_name_to_path(&self.root_path, resource_name);
         println!("Loading {:?} from {:?}", resource_name, full_path);
 
@@ -55,6 +61,12 @@ impl Resources {
 
         file.read_to_end(&mut buffer)?;
 
+        Ok(buffer)
+    }
+
+    pub fn load_cstring(&self, resource_name: &str) -> Result<ffi::CString, Error> {
+        let buffer = self.load_bytes(resource_name)?;
+
         if buffer.iter().any(|i| *i == 0) {
             return Err(Error::FileContainsNil);
         }
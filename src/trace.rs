@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use image::DynamicImage;
+
+use crate::game;
+use crate::game::scene::Scene;
+use crate::game::SceneOptions;
+use crate::renderer::path_tracer::{PathTracer, TraceSettings};
+use crate::resources::Resources;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Unknown option {}", flag)]
+    UnknownFlag { flag: String },
+    #[fail(display = "Option {} needs a value", flag)]
+    MissingValue { flag: String },
+    #[fail(display = "Invalid value \"{}\" for option {}", value, flag)]
+    InvalidValue { flag: String, value: String },
+    #[fail(display = "Can't write {}, only .png and .exr are supported", path)]
+    UnsupportedFormat { path: String },
+}
+
+/// What to path trace
+#[derive(Debug, Clone)]
+pub struct TraceOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Paths traced through every pixel
+    pub samples: u32,
+    /// Times a path bounces off surfaces after the first hit
+    pub bounces: u32,
+    pub threads: usize,
+    /// Where the image goes, a PNG which is tone mapped like the game, or an EXR with the linear
+    /// radiance
+    pub output: PathBuf,
+    /// Seeds both the scene and the tracing, the scene is random without one
+    pub seed: Option<u64>,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        TraceOptions {
+            width: 1280,
+            height: 720,
+            samples: 64,
+            bounces: 4,
+            threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            output: PathBuf::from("trace.png"),
+            seed: None,
+        }
+    }
+}
+
+impl TraceOptions {
+    /// Parses `--size WIDTHxHEIGHT`, `--samples N`, `--bounces N`, `--threads N`, `--output FILE`
+    /// and `--seed N`, anything left out keeps its default
+    pub fn from_args(args: &[String]) -> Result<TraceOptions, Error> {
+        let mut options = TraceOptions::default();
+        let mut args = args.iter();
+
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| Error::MissingValue { flag: flag.clone() })?;
+            let invalid = || Error::InvalidValue {
+                flag: flag.clone(),
+                value: value.clone(),
+            };
+
+            match flag.as_str() {
+                "--size" => {
+                    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+                    options.width = width.parse().map_err(|_| invalid())?;
+                    options.height = height.parse().map_err(|_| invalid())?;
+                }
+                "--samples" => options.samples = value.parse().map_err(|_| invalid())?,
+                "--bounces" => options.bounces = value.parse().map_err(|_| invalid())?,
+                "--threads" => options.threads = value.parse().map_err(|_| invalid())?,
+                "--output" => options.output = PathBuf::from(value),
+                "--seed" => options.seed = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(Error::UnknownFlag { flag: flag.clone() }),
+            }
+
+            if options.width == 0 || options.height == 0 || options.samples == 0 || options.threads == 0 {
+                return Err(invalid());
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Path traces a still of the game's scene as it starts out, entirely on the CPU
+pub fn run(options: &TraceOptions) -> Result<(), failure::Error> {
+    let res = Resources::from_relative_exe_path(Path::new("assets"))?;
+
+    let extension = options.output.extension().and_then(|extension| extension.to_str());
+    let exr = match extension.map(|extension| extension.to_ascii_lowercase()).as_deref() {
+        Some("png") => false,
+        Some("exr") => true,
+        _ => {
+            return Err(Error::UnsupportedFormat {
+                path: options.output.display().to_string(),
+            }
+            .into())
+        }
+    };
+
+    // There's no post-processing to configure
+    let scene_options = SceneOptions {
+        seed: options.seed,
+        post_process: false,
+        ..SceneOptions::default()
+    };
+    let (scene, assets) = Scene::new(&scene_options);
+    let trace = TraceSettings {
+        samples: options.samples,
+        bounces: options.bounces,
+        threads: options.threads,
+        seed: options.seed.unwrap_or(0),
+    };
+    let mut tracer = PathTracer::new(
+        assets,
+        game::render_settings(&res, &scene_options)?,
+        trace,
+        (options.width, options.height),
+    );
+
+    println!(
+        "Tracing {}x{} at {} samples per pixel on {} threads",
+        options.width, options.height, options.samples, options.threads
+    );
+    let start = Instant::now();
+    scene.draw(&mut tracer);
+    println!("Traced in {:.1}s", start.elapsed().as_secs_f32());
+
+    if exr {
+        DynamicImage::ImageRgb32F(tracer.radiance()?.clone()).save(&options.output)?;
+    } else {
+        tracer.image()?.save(&options.output)?;
+    }
+
+    Ok(())
+}
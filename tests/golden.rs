@@ -0,0 +1,103 @@
+//! Renders canned scenes headlessly and compares them to the reference images in tests/golden.
+//!
+//! The software renderer's scenes are always checked. The OpenGL ones need an OpenGL 4.5 context
+//! from SDL's offscreen driver, and are skipped with a note where it can't be created. On machines
+//! without a GPU, Mesa's llvmpipe provides one, which is what their references are rendered with:
+//!
+//! ```sh
+//! apt-get install libegl1 libgl1-mesa-dri libsdl2-2.0-0
+//! LIBGL_ALWAYS_SOFTWARE=1 cargo test --test golden -- --nocapture
+//! ```
+//!
+//! Failing scenes leave what they rendered and a diff image in tests/golden/failures. After an
+//! intended change to the shaders, regenerate the references with `CGI_BLESS=1` and check them in.
+//! The OpenGL references have to be regenerated with llvmpipe too.
+
+use std::path::{Path, PathBuf};
+
+use cgi::golden::{check, Tolerance};
+use cgi::headless::{Backend, Framing, HeadlessOptions, Rendered, SceneOptions};
+use cgi::primitives::deferred::RenderPath;
+use cgi::primitives::object_draw::ShadingModel;
+use cgi::resources::Resources;
+
+const SEED: u64 = 1;
+
+// The software renderer has a single render path, so only the shading changes its output
+const SOFTWARE_SCENES: [(&str, RenderPath, ShadingModel); 2] = [
+    ("software_classic", RenderPath::Forward, ShadingModel::Classic),
+    ("software_pbr", RenderPath::Forward, ShadingModel::Pbr),
+];
+
+const OPENGL_SCENES: [(&str, RenderPath, ShadingModel); 4] = [
+    ("forward_classic", RenderPath::Forward, ShadingModel::Classic),
+    ("forward_pbr", RenderPath::Forward, ShadingModel::Pbr),
+    ("deferred_classic", RenderPath::Deferred, ShadingModel::Classic),
+    ("deferred_pbr", RenderPath::Deferred, ShadingModel::Pbr),
+];
+
+fn manifest_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+// Renders the scenes one after another, as SDL can't be used from several test threads. Returns
+// why OpenGL scenes couldn't be rendered, if they fell back to the software renderer.
+fn check_scenes(backend: Backend, scenes: &[(&str, RenderPath, ShadingModel)], tolerance: Tolerance) -> Option<failure::Error> {
+    let references = manifest_path("tests/golden");
+    let mut failures = vec![];
+
+    for (name, render_path, shading) in scenes.iter() {
+        let options = HeadlessOptions {
+            width: 320,
+            height: 180,
+            frames: 1,
+            backend,
+            // Post-processing is left out, film grain and the like would only add noise
+            scene: SceneOptions {
+                seed: Some(SEED),
+                render_path: *render_path,
+                shading: *shading,
+                post_process: false,
+                framing: Framing::Mosaic,
+            },
+            ..HeadlessOptions::default()
+        };
+
+        let res = Resources::from_path(&manifest_path("assets"));
+        let mut frames = vec![];
+        let rendered = cgi::headless::render(res, &options, |_, image| {
+            frames.push(image);
+            Ok(())
+        })
+        .unwrap_or_else(|e| panic!("Failed to render {}: {}", name, cgi::debug::failure_to_string(e)));
+
+        if let Rendered::SoftwareFallback(e) = rendered {
+            return Some(e);
+        }
+        for image in frames {
+            if let Err(e) = check(name, &image, &references, &tolerance) {
+                failures.push(e.to_string());
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+    None
+}
+
+#[test]
+fn software_scenes_match_references() {
+    // The software renderer comes out the same everywhere, but for float rounding in the shading
+    let tolerance = Tolerance {
+        pixel_threshold: 0.05,
+        max_differing: 0.0001,
+    };
+    check_scenes(Backend::Software, &SOFTWARE_SCENES, tolerance);
+}
+
+#[test]
+fn opengl_scenes_match_references() {
+    if let Some(e) = check_scenes(Backend::Auto, &OPENGL_SCENES, Tolerance::default()) {
+        eprintln!("Skipping the OpenGL scenes: {}", e);
+    }
+}